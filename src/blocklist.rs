@@ -1,9 +1,84 @@
 use crate::Result;
 use radix_trie::Trie;
-use std::collections::HashSet;
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 
+/// Header of the compiled blocklist index (format version in the last byte)
+const INDEX_MAGIC: &[u8; 8] = b"SKBHIDX2";
+
+/// Most labels a DNS name can have (255 bytes on the wire); the default
+/// and upper bound of `max_match_labels`
+pub(crate) const MAX_LABELS: usize = 127;
+
+/// Every parent of a domain, longest first, as slices of it:
+/// `a.b.example.com` yields `b.example.com`, `example.com` and `com`.
+/// Allocation-free and linear in the name's length, however deep it is;
+/// parents over `max_labels` labels are skipped.
+fn parent_domains(domain: &str, max_labels: usize) -> impl Iterator<Item = &str> {
+    let labels = domain.bytes().filter(|&b| b == b'.').count() + 1;
+    domain
+        .match_indices('.')
+        .skip(labels.saturating_sub(max_labels + 1))
+        .map(move |(dot, _)| &domain[dot + 1..])
+}
+
+/// The last `max_labels` labels of a domain: all of it when it isn't deeper
+fn capped_name(domain: &str, max_labels: usize) -> &str {
+    let labels = domain.bytes().filter(|&b| b == b'.').count() + 1;
+    if labels <= max_labels {
+        return domain;
+    }
+    parent_domains(domain, max_labels).next().unwrap_or(domain)
+}
+
+/// Write a length-prefixed string to an index file
+fn write_index_str(out: &mut impl Write, value: &str) -> std::io::Result<()> {
+    out.write_all(&(value.len() as u32).to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+/// Cursor over the bytes of an index file
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl IndexReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.bytes.len() < len {
+            anyhow::bail!("blocklist index is truncated");
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn read_str(&mut self) -> Result<String> {
+        let len = self.read_u32()? as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_string())
+    }
+
+    fn read_set(&mut self) -> Result<HashSet<String>> {
+        let count = self.read_u32()? as usize;
+        let mut set = HashSet::with_capacity(count.min(self.bytes.len()));
+        for _ in 0..count {
+            set.insert(self.read_str()?);
+        }
+        Ok(set)
+    }
+}
+
 pub struct BlocklistManager {
     // Domain blocklist using radix trie for efficient lookups
     domains: Arc<RwLock<Trie<String, ()>>>,
@@ -15,6 +90,189 @@ pub struct BlocklistManager {
     // Stored as reversed domains for efficient subdomain matching
     // *.example.com -> com.example
     wildcards: Arc<RwLock<HashSet<String>>>,
+
+    // Suffix rules from suffix-mode lists: block the domain itself and
+    // every subdomain (example.com -> example.com, a.example.com, ...)
+    suffixes: Arc<RwLock<HashSet<String>>>,
+
+    // Regex rules (`/pattern/` entries), matched against the normalized name
+    regexes: Arc<RwLock<Vec<Regex>>>,
+
+    // Temporary blocks (`example.com` or `*.example.com`) and when they
+    // expire. Expired entries never match; `sweep_expired` drops them.
+    temporary: Arc<RwLock<HashMap<String, SystemTime>>>,
+
+    // Rules from sources tagged with a category, kept per category so one
+    // can be switched off without reloading anything
+    categories: Arc<RwLock<HashMap<String, CategoryRules>>>,
+
+    // Categories whose rules are ignored by matching
+    disabled_categories: Arc<RwLock<HashSet<String>>>,
+
+    // Allow rules (`@@example.com`, `@@*.example.com`), stored without the
+    // `@@`. They override block rules that are less specific, whichever
+    // source or category they came from.
+    allowed: Arc<RwLock<HashSet<String>>>,
+
+    // Deepest parent (in labels) looked up by wildcard, suffix and allow
+    // matching; regexes only see that many trailing labels of a name
+    max_match_labels: AtomicUsize,
+}
+
+/// Exact, wildcard and regex rules loaded from one category's sources
+#[derive(Default)]
+struct CategoryRules {
+    exact: HashSet<String>,
+    wildcards: HashSet<String>,
+    regexes: Vec<Regex>,
+}
+
+impl CategoryRules {
+    /// The rule (if any) blocking an already normalized domain
+    fn matching_rule(&self, normalized: &str, max_labels: usize) -> Option<MatchKind> {
+        if self.exact.contains(normalized) {
+            return Some(MatchKind::Exact(normalized.to_string()));
+        }
+        if let Some(base) =
+            BlocklistManager::matching_wildcard(normalized, &self.wildcards, max_labels)
+        {
+            return Some(MatchKind::Wildcard(base.clone()));
+        }
+        let capped = capped_name(normalized, max_labels);
+        self.regexes
+            .iter()
+            .find(|re| re.is_match(capped))
+            .map(|re| MatchKind::Regex(re.as_str().to_string()))
+    }
+}
+
+/// A single blocklist entry, classified by syntax
+enum Rule {
+    /// `example.com`
+    Exact(String),
+    /// `*.example.com`, stored as the base `example.com`
+    Wildcard(String),
+    /// `/^ads[0-9]+\./`, stored without the slashes
+    Regex(String),
+    /// `@@example.com` or `@@*.example.com`, stored without the `@@`
+    Allow(String),
+}
+
+/// The rule responsible for blocking a domain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchKind {
+    /// Listed as an exact entry
+    Exact(String),
+    /// Covered by the wildcard `*.<base>`
+    Wildcard(String),
+    /// Covered by a suffix-list entry (apex and subdomains)
+    Suffix(String),
+    /// Matched the `/pattern/` rule
+    Regex(String),
+    /// Covered by a temporary block that has not expired yet
+    Temporary(String),
+    /// Blocked by a custom `BlockPolicy`, with its reason
+    Policy(String),
+    /// Matched a rule from a categorized source: the category and the rule
+    Category(String, Box<MatchKind>),
+}
+
+/// How a domain and the names under it are treated (see
+/// `BlocklistManager::wildcard_coverage`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WildcardCoverage {
+    /// The rule blocking the domain itself, if any
+    pub domain: Option<MatchKind>,
+    /// The wildcard or suffix rule blocking every subdomain, if any
+    pub subdomains: Option<MatchKind>,
+}
+
+/// Number of loaded rules of each kind (see `BlocklistManager::counts`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleCounts {
+    pub exact: usize,
+    /// `*.example.com` entries
+    pub wildcard: usize,
+    /// Entries of suffix-mode lists
+    pub suffix: usize,
+    /// `/pattern/` entries
+    pub regex: usize,
+}
+
+impl RuleCounts {
+    /// All rules together, what `BlocklistManager::count` returns
+    pub fn total(&self) -> usize {
+        self.exact + self.wildcard + self.suffix + self.regex
+    }
+}
+
+impl fmt::Display for RuleCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} exact, {} wildcard, {} suffix, {} regex",
+            self.exact, self.wildcard, self.suffix, self.regex
+        )
+    }
+}
+
+/// What `BlocklistManager::add_domains` did with its input
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddReport {
+    /// The entries that were new, in input order
+    pub added: Vec<String>,
+    /// Entries already present, or repeated in the input
+    pub duplicates: usize,
+    /// Entries that aren't a usable rule: an empty name, one containing
+    /// whitespace, or a regex that doesn't compile
+    pub invalid: usize,
+}
+
+impl fmt::Display for AddReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "added {}, {} duplicate{}, {} invalid",
+            self.added.len(),
+            self.duplicates,
+            if self.duplicates == 1 { "" } else { "s" },
+            self.invalid
+        )
+    }
+}
+
+impl MatchKind {
+    /// How narrowly the rule targets the name it matched, for weighing it
+    /// against allow rules: an exact entry beats any wildcard or suffix,
+    /// and those rank by the number of labels in their base. Regexes and
+    /// policies rank lowest.
+    fn specificity(&self) -> usize {
+        let labels = |name: &str| name.split('.').count();
+        match self {
+            MatchKind::Exact(domain) => labels(domain) + 1,
+            MatchKind::Wildcard(base) | MatchKind::Suffix(base) => labels(base),
+            MatchKind::Temporary(rule) => match rule.strip_prefix("*.") {
+                Some(base) => labels(base),
+                None => labels(rule) + 1,
+            },
+            MatchKind::Regex(_) | MatchKind::Policy(_) => 0,
+            MatchKind::Category(_, rule) => rule.specificity(),
+        }
+    }
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchKind::Exact(domain) => write!(f, "exact entry {}", domain),
+            MatchKind::Wildcard(base) => write!(f, "wildcard *.{}", base),
+            MatchKind::Suffix(suffix) => write!(f, "suffix rule {}", suffix),
+            MatchKind::Regex(pattern) => write!(f, "regex /{}/", pattern),
+            MatchKind::Temporary(rule) => write!(f, "temporary block {}", rule),
+            MatchKind::Policy(reason) => write!(f, "policy {}", reason),
+            MatchKind::Category(category, rule) => write!(f, "{} (category {})", rule, category),
+        }
+    }
 }
 
 impl Default for BlocklistManager {
@@ -29,84 +287,378 @@ impl BlocklistManager {
             domains: Arc::new(RwLock::new(Trie::new())),
             exact_matches: Arc::new(RwLock::new(HashSet::new())),
             wildcards: Arc::new(RwLock::new(HashSet::new())),
+            suffixes: Arc::new(RwLock::new(HashSet::new())),
+            regexes: Arc::new(RwLock::new(Vec::new())),
+            temporary: Arc::new(RwLock::new(HashMap::new())),
+            categories: Arc::new(RwLock::new(HashMap::new())),
+            disabled_categories: Arc::new(RwLock::new(HashSet::new())),
+            allowed: Arc::new(RwLock::new(HashSet::new())),
+            max_match_labels: AtomicUsize::new(MAX_LABELS),
         }
     }
 
-    /// Parse a domain entry and determine if it's a wildcard
-    /// Returns (is_wildcard, normalized_domain)
-    fn parse_domain(domain: &str) -> (bool, String) {
-        let normalized = domain.trim().to_lowercase();
+    /// Look at most `labels` labels deep when matching (`max_match_labels`):
+    /// deeper names are matched by their shallower parents only, bounding
+    /// the work an adversarial query can cause
+    pub fn set_max_match_labels(&self, labels: usize) {
+        self.max_match_labels
+            .store(labels.clamp(1, MAX_LABELS), Ordering::Relaxed);
+    }
+
+    /// Parse a blocklist entry into an exact, wildcard, regex or allow rule
+    fn parse_rule(domain: &str) -> Rule {
+        let trimmed = domain.trim();
 
+        // Allow rule: @@example.com or @@*.example.com
+        if let Some(allowed) = trimmed.strip_prefix("@@") {
+            return Rule::Allow(allowed.trim().trim_end_matches('.').to_lowercase());
+        }
+
+        // Regex rule: /pattern/ (case is significant in the pattern itself)
+        if trimmed.len() > 2 && trimmed.starts_with('/') && trimmed.ends_with('/') {
+            return Rule::Regex(trimmed[1..trimmed.len() - 1].to_string());
+        }
+
+        let normalized = trimmed.to_lowercase();
         if normalized.starts_with("*.") {
             // Wildcard domain: *.example.com -> example.com (reversed later)
             let base = normalized.trim_start_matches("*.").to_string();
-            (true, base)
+            Rule::Wildcard(base)
         } else {
-            (false, normalized)
+            Rule::Exact(normalized)
         }
     }
 
-    /// Check if a domain matches any wildcard patterns
-    fn matches_wildcard(domain: &str, wildcards: &HashSet<String>) -> bool {
-        // For domain "sub.example.com", check if any wildcard base matches
-        // Need to check: example.com, sub.example.com against wildcard bases
-
-        let parts: Vec<&str> = domain.split('.').collect();
+    /// Find the wildcard base (if any) that a domain falls under
+    fn matching_wildcard<'a>(
+        domain: &str,
+        wildcards: &'a HashSet<String>,
+        max_labels: usize,
+    ) -> Option<&'a String> {
+        // Only parents count: `*.example.com` leaves example.com itself alone.
+        // For "a.b.example.com", try b.example.com, example.com, com.
+        if wildcards.is_empty() {
+            return None;
+        }
+        parent_domains(domain, max_labels).find_map(|parent| wildcards.get(parent))
+    }
 
-        // Try each suffix of the domain
-        // For "a.b.example.com", try: example.com, b.example.com, a.b.example.com
-        for i in 0..parts.len() {
-            let suffix = parts[i..].join(".");
-            if wildcards.contains(&suffix) {
-                // Found a matching wildcard base
-                // Only match if this is actually a subdomain (not the exact domain)
-                if i > 0 {
-                    return true;
-                }
-            }
+    /// Find the suffix rule (if any) that a domain equals or is a subdomain of
+    fn matching_suffix<'a>(
+        domain: &str,
+        suffixes: &'a HashSet<String>,
+        max_labels: usize,
+    ) -> Option<&'a String> {
+        if suffixes.is_empty() {
+            return None;
         }
+        std::iter::once(domain)
+            .chain(parent_domains(domain, max_labels))
+            .find_map(|suffix| suffixes.get(suffix))
+    }
 
-        false
+    /// Normalize a suffix-list entry (case, stray `*.` and trailing dot)
+    fn parse_suffix(entry: &str) -> String {
+        entry
+            .trim()
+            .to_lowercase()
+            .trim_start_matches("*.")
+            .trim_end_matches('.')
+            .to_string()
     }
 
     /// Check if a domain is blocked
     pub async fn is_blocked(&self, domain: &str) -> bool {
+        self.matching_rule(domain).await.is_some()
+    }
+
+    /// Find the rule that blocks a domain, or None if it is allowed.
+    /// Rules are checked in order: exact, wildcard, suffix, regex, enabled
+    /// categories, then temporary blocks. When an allow rule (`@@...`)
+    /// covers the domain, the most specific matching rule wins instead, an
+    /// allow rule on a tie: with `*.example.com` blocked and
+    /// `@@*.safe.example.com` allowed, `a.safe.example.com` is allowed and
+    /// `a.ads.example.com` blocked.
+    pub async fn matching_rule(&self, domain: &str) -> Option<MatchKind> {
+        self.matching_rule_at(domain, SystemTime::now()).await
+    }
+
+    /// `matching_rule` with temporary blocks evaluated as of `now`
+    async fn matching_rule_at(&self, domain: &str, now: SystemTime) -> Option<MatchKind> {
         // Normalize domain: remove trailing dot if present
         let normalized = domain.trim_end_matches('.').to_lowercase();
+        let max_labels = self.max_match_labels.load(Ordering::Relaxed);
+
+        let allowed = Self::allow_specificity(&normalized, &*self.allowed.read().await, max_labels);
+        match allowed {
+            None => self
+                .blocking_rules_at(&normalized, now, max_labels, false)
+                .await
+                .pop(),
+            Some(allowed) => self
+                .blocking_rules_at(&normalized, now, max_labels, true)
+                .await
+                .into_iter()
+                .max_by_key(MatchKind::specificity)
+                .filter(|rule| rule.specificity() > allowed),
+        }
+    }
+
+    /// Specificity (see `MatchKind::specificity`) of the most specific allow
+    /// rule covering an already normalized domain
+    fn allow_specificity(
+        normalized: &str,
+        allowed: &HashSet<String>,
+        max_labels: usize,
+    ) -> Option<usize> {
+        if allowed.is_empty() {
+            return None;
+        }
+        if allowed.contains(normalized) {
+            return Some(normalized.split('.').count() + 1);
+        }
+        parent_domains(normalized, max_labels)
+            .find(|parent| allowed.contains(&format!("*.{}", parent)))
+            .map(|parent| parent.split('.').count())
+    }
+
+    /// The block rules matching an already normalized domain, in the order
+    /// `matching_rule` checks them: only the first unless `all` is set
+    async fn blocking_rules_at(
+        &self,
+        normalized: &str,
+        now: SystemTime,
+        max_labels: usize,
+        all: bool,
+    ) -> Vec<MatchKind> {
+        let mut rules = Vec::new();
 
         // Check exact match first (fastest)
         let exact = self.exact_matches.read().await;
-        if exact.contains(&normalized) {
-            return true;
+        if exact.contains(normalized) {
+            rules.push(MatchKind::Exact(normalized.to_string()));
+            if !all {
+                return rules;
+            }
         }
 
         // Check wildcard matches
         let wildcards = self.wildcards.read().await;
-        if Self::matches_wildcard(&normalized, &wildcards) {
-            return true;
+        if let Some(base) = Self::matching_wildcard(normalized, &wildcards, max_labels) {
+            rules.push(MatchKind::Wildcard(base.clone()));
+            if !all {
+                return rules;
+            }
+        }
+
+        // Check suffix rules (apex + subdomains)
+        let suffixes = self.suffixes.read().await;
+        if let Some(suffix) = Self::matching_suffix(normalized, &suffixes, max_labels) {
+            rules.push(MatchKind::Suffix(suffix.clone()));
+            if !all {
+                return rules;
+            }
+        }
+
+        // Check regex rules, against at most `max_labels` trailing labels
+        let regexes = self.regexes.read().await;
+        let capped = capped_name(normalized, max_labels);
+        if let Some(re) = regexes.iter().find(|re| re.is_match(capped)) {
+            rules.push(MatchKind::Regex(re.as_str().to_string()));
+            if !all {
+                return rules;
+            }
         }
 
         // Check in trie (for future use or backward compatibility)
         let trie = self.domains.read().await;
-        trie.get(&normalized).is_some()
+        if trie.get(normalized).is_some() {
+            rules.push(MatchKind::Exact(normalized.to_string()));
+            if !all {
+                return rules;
+            }
+        }
+
+        // Check rules of categories that are switched on
+        let categories = self.categories.read().await;
+        let disabled = self.disabled_categories.read().await;
+        for (category, category_rules) in categories
+            .iter()
+            .filter(|(category, _)| !disabled.contains(*category))
+        {
+            if let Some(rule) = category_rules.matching_rule(normalized, max_labels) {
+                rules.push(MatchKind::Category(category.clone(), Box::new(rule)));
+                if !all {
+                    return rules;
+                }
+            }
+        }
+
+        // Check unexpired temporary blocks
+        let temporary = self.temporary.read().await;
+        rules.extend(
+            temporary
+                .iter()
+                .filter(|(_, expires)| **expires > now)
+                .filter(|(rule, _)| match Self::parse_rule(rule) {
+                    Rule::Exact(exact) => exact == normalized,
+                    Rule::Wildcard(base) => {
+                        normalized.len() > base.len()
+                            && normalized.ends_with(&base)
+                            && normalized[..normalized.len() - base.len()].ends_with('.')
+                    }
+                    Rule::Regex(_) | Rule::Allow(_) => false,
+                })
+                .map(|(rule, _)| MatchKind::Temporary(rule.clone()))
+                .take(if all { usize::MAX } else { 1 }),
+        );
+        rules
+    }
+
+    /// Which rules cover `domain` and every name under it. A subdomain is
+    /// covered by `*.domain` or a wildcard on a parent, or by a suffix rule
+    /// on the domain or a parent; categories that are switched off don't count.
+    pub async fn wildcard_coverage(&self, domain: &str) -> WildcardCoverage {
+        let normalized = domain.trim_end_matches('.').to_lowercase();
+        let domain = self.matching_rule(&normalized).await;
+        let parts: Vec<&str> = normalized.split('.').collect();
+        let bases: Vec<String> = (0..parts.len()).map(|i| parts[i..].join(".")).collect();
+
+        let wildcards = self.wildcards.read().await;
+        let suffixes = self.suffixes.read().await;
+        let categories = self.categories.read().await;
+        let disabled = self.disabled_categories.read().await;
+        let subdomains = bases
+            .iter()
+            .find_map(|base| wildcards.get(base).cloned().map(MatchKind::Wildcard))
+            .or_else(|| {
+                bases
+                    .iter()
+                    .find_map(|base| suffixes.get(base).cloned().map(MatchKind::Suffix))
+            })
+            .or_else(|| {
+                categories
+                    .iter()
+                    .filter(|(category, _)| !disabled.contains(*category))
+                    .find_map(|(category, rules)| {
+                        let base = bases.iter().find_map(|base| rules.wildcards.get(base))?;
+                        Some(MatchKind::Category(
+                            category.clone(),
+                            Box::new(MatchKind::Wildcard(base.clone())),
+                        ))
+                    })
+            });
+        WildcardCoverage { domain, subdomains }
+    }
+
+    /// Block a domain (or `*.` wildcard) for a limited time
+    pub async fn add_domain_with_ttl(&self, domain: String, ttl: Duration) -> Result<()> {
+        let expires = SystemTime::now().checked_add(ttl).ok_or_else(|| {
+            anyhow::anyhow!("block of {}s ends too far in the future", ttl.as_secs())
+        })?;
+        self.add_domain_until(domain, expires).await
+    }
+
+    /// Block a domain (or `*.` wildcard) until `expires`. Re-adding a
+    /// domain replaces its expiry.
+    pub async fn add_domain_until(&self, domain: String, expires: SystemTime) -> Result<()> {
+        let rule = match Self::parse_rule(&domain) {
+            Rule::Exact(exact) => exact,
+            Rule::Wildcard(base) => format!("*.{}", base),
+            Rule::Regex(_) => anyhow::bail!("temporary blocks do not support regex rules"),
+            Rule::Allow(_) => anyhow::bail!("temporary blocks do not support allow rules"),
+        };
+        self.temporary.write().await.insert(rule, expires);
+        Ok(())
+    }
+
+    /// Drop expired temporary blocks, returning how many were removed
+    pub async fn sweep_expired(&self) -> usize {
+        self.sweep_expired_at(SystemTime::now()).await
+    }
+
+    async fn sweep_expired_at(&self, now: SystemTime) -> usize {
+        let mut temporary = self.temporary.write().await;
+        let before = temporary.len();
+        temporary.retain(|_, expires| *expires > now);
+        before - temporary.len()
+    }
+
+    /// Add several rules (in any form `add_domain` takes), reporting which
+    /// were new and how many were duplicates or invalid
+    pub async fn add_domains(&self, domains: Vec<String>) -> AddReport {
+        fn usable(name: &str) -> bool {
+            !name.is_empty() && !name.contains(char::is_whitespace)
+        }
+
+        let mut exact = self.exact_matches.write().await;
+        let mut trie = self.domains.write().await;
+        let mut wildcards = self.wildcards.write().await;
+        let mut regexes = self.regexes.write().await;
+        let mut allowed = self.allowed.write().await;
+
+        let mut report = AddReport::default();
+        for domain in domains {
+            let added = match Self::parse_rule(&domain) {
+                Rule::Wildcard(base) if usable(&base) => wildcards.insert(base),
+                Rule::Allow(rule) if usable(rule.trim_start_matches("*.")) => allowed.insert(rule),
+                Rule::Exact(normalized) if usable(&normalized) => {
+                    trie.insert(normalized.clone(), ());
+                    exact.insert(normalized)
+                }
+                Rule::Regex(pattern) => match Regex::new(&pattern) {
+                    Ok(_) if regexes.iter().any(|r| r.as_str() == pattern) => false,
+                    Ok(re) => {
+                        regexes.push(re);
+                        true
+                    }
+                    Err(_) => {
+                        report.invalid += 1;
+                        continue;
+                    }
+                },
+                _ => {
+                    report.invalid += 1;
+                    continue;
+                }
+            };
+            if added {
+                report.added.push(domain);
+            } else {
+                report.duplicates += 1;
+            }
+        }
+        report
     }
 
     /// Add a domain to the blocklist
-    /// Supports both exact domains and wildcards (*.example.com)
+    /// Supports exact domains, wildcards (*.example.com) and regex rules
+    /// (/pattern/); an invalid regex is an error
     pub async fn add_domain(&self, domain: String) -> Result<()> {
-        let (is_wildcard, normalized) = Self::parse_domain(&domain);
-
-        if is_wildcard {
-            // Add to wildcard collection
-            let mut wildcards = self.wildcards.write().await;
-            wildcards.insert(normalized);
-        } else {
-            // Add to exact matches
-            let mut exact = self.exact_matches.write().await;
-            exact.insert(normalized.clone());
+        match Self::parse_rule(&domain) {
+            Rule::Wildcard(base) => {
+                // Add to wildcard collection
+                let mut wildcards = self.wildcards.write().await;
+                wildcards.insert(base);
+            }
+            Rule::Exact(normalized) => {
+                // Add to exact matches
+                let mut exact = self.exact_matches.write().await;
+                exact.insert(normalized.clone());
 
-            let mut trie = self.domains.write().await;
-            trie.insert(normalized, ());
+                let mut trie = self.domains.write().await;
+                trie.insert(normalized, ());
+            }
+            Rule::Regex(pattern) => {
+                let re = Regex::new(&pattern)?;
+                let mut regexes = self.regexes.write().await;
+                if !regexes.iter().any(|r| r.as_str() == pattern) {
+                    regexes.push(re);
+                }
+            }
+            Rule::Allow(allowed) => {
+                self.allowed.write().await.insert(allowed);
+            }
         }
 
         Ok(())
@@ -114,75 +666,512 @@ impl BlocklistManager {
 
     /// Remove a domain from the blocklist
     pub async fn remove_domain(&self, domain: &str) -> Result<()> {
-        let (is_wildcard, normalized) = Self::parse_domain(domain);
-
-        if is_wildcard {
-            let mut wildcards = self.wildcards.write().await;
-            wildcards.remove(&normalized);
-        } else {
-            let mut exact = self.exact_matches.write().await;
-            exact.remove(&normalized);
+        match Self::parse_rule(domain) {
+            Rule::Wildcard(base) => {
+                let mut wildcards = self.wildcards.write().await;
+                wildcards.remove(&base);
+            }
+            Rule::Exact(normalized) => {
+                let mut exact = self.exact_matches.write().await;
+                exact.remove(&normalized);
 
-            let mut trie = self.domains.write().await;
-            trie.remove(&normalized);
+                let mut trie = self.domains.write().await;
+                trie.remove(&normalized);
+            }
+            Rule::Regex(pattern) => {
+                let mut regexes = self.regexes.write().await;
+                regexes.retain(|r| r.as_str() != pattern);
+            }
+            Rule::Allow(allowed) => {
+                self.allowed.write().await.remove(&allowed);
+            }
         }
 
         Ok(())
     }
 
     /// Load domains from a list
-    /// Supports both exact domains and wildcards (*.example.com)
+    /// Supports exact domains, wildcards (*.example.com) and regex rules
+    /// (/pattern/); invalid regexes are logged and skipped
     pub async fn load_domains(&self, domains: Vec<String>) -> Result<()> {
         let mut exact = self.exact_matches.write().await;
         let mut trie = self.domains.write().await;
         let mut wildcards = self.wildcards.write().await;
+        let mut regexes = self.regexes.write().await;
+        let mut allowed = self.allowed.write().await;
 
         for domain in domains {
-            let (is_wildcard, normalized) = Self::parse_domain(&domain);
+            match Self::parse_rule(&domain) {
+                Rule::Wildcard(base) => {
+                    wildcards.insert(base);
+                }
+                Rule::Allow(rule) => {
+                    allowed.insert(rule);
+                }
+                Rule::Exact(normalized) => {
+                    exact.insert(normalized.clone());
+                    trie.insert(normalized, ());
+                }
+                Rule::Regex(pattern) => match Regex::new(&pattern) {
+                    Ok(re) => {
+                        if !regexes.iter().any(|r| r.as_str() == pattern) {
+                            regexes.push(re);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(rule = %domain, error = %e, "Skipping invalid regex rule")
+                    }
+                },
+            }
+        }
 
-            if is_wildcard {
-                wildcards.insert(normalized);
-            } else {
-                exact.insert(normalized.clone());
-                trie.insert(normalized, ());
+        Ok(())
+    }
+
+    /// Load domains from a source tagged with `category`. The rules only
+    /// match while the category is enabled (see `set_category_enabled`).
+    /// Invalid regexes are logged and skipped.
+    pub async fn load_category(&self, category: &str, domains: Vec<String>) -> Result<()> {
+        let mut categories = self.categories.write().await;
+        let rules = categories.entry(category.to_string()).or_default();
+        let mut allowed = self.allowed.write().await;
+
+        for domain in domains {
+            match Self::parse_rule(&domain) {
+                Rule::Allow(rule) => {
+                    allowed.insert(rule);
+                }
+                Rule::Wildcard(base) => {
+                    rules.wildcards.insert(base);
+                }
+                Rule::Exact(normalized) => {
+                    rules.exact.insert(normalized);
+                }
+                Rule::Regex(pattern) => match Regex::new(&pattern) {
+                    Ok(re) => {
+                        if !rules.regexes.iter().any(|r| r.as_str() == pattern) {
+                            rules.regexes.push(re);
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(rule = %domain, error = %e, "Skipping invalid regex rule")
+                    }
+                },
             }
         }
 
         Ok(())
     }
 
-    /// Get the number of blocked domains (exact + wildcards)
+    /// Switch a category's rules on or off for matching
+    pub async fn set_category_enabled(&self, category: &str, enabled: bool) {
+        let mut disabled = self.disabled_categories.write().await;
+        if enabled {
+            disabled.remove(category);
+        } else {
+            disabled.insert(category.to_string());
+        }
+    }
+
+    /// Replace the set of disabled categories
+    pub async fn set_disabled_categories(&self, categories: HashSet<String>) {
+        *self.disabled_categories.write().await = categories;
+    }
+
+    /// Load suffix rules from a suffix-mode list
+    /// Each entry blocks itself and all of its subdomains
+    pub async fn load_suffixes(&self, entries: Vec<String>) -> Result<()> {
+        let mut suffixes = self.suffixes.write().await;
+
+        for entry in entries {
+            let normalized = Self::parse_suffix(&entry);
+            if !normalized.is_empty() {
+                suffixes.insert(normalized);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the normalized rule sets to a compact binary index at `path`,
+    /// tagged with `key` (an opaque description of the sources it was built
+    /// from). The file is written atomically.
+    pub async fn save_index(&self, path: &Path, key: &str) -> Result<()> {
+        let exact = self.exact_matches.read().await;
+        let wildcards = self.wildcards.read().await;
+        let suffixes = self.suffixes.read().await;
+        let regexes = self.regexes.read().await;
+        let allowed = self.allowed.read().await;
+
+        let tmp_path = path.with_extension("tmp");
+        let mut out = BufWriter::new(std::fs::File::create(&tmp_path)?);
+        out.write_all(INDEX_MAGIC)?;
+        write_index_str(&mut out, key)?;
+        for set in [&*exact, &*wildcards, &*suffixes, &*allowed] {
+            out.write_all(&(set.len() as u32).to_le_bytes())?;
+            for entry in set {
+                write_index_str(&mut out, entry)?;
+            }
+        }
+        out.write_all(&(regexes.len() as u32).to_le_bytes())?;
+        for re in regexes.iter() {
+            write_index_str(&mut out, re.as_str())?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Load rules from an index written by `save_index`. Returns false, and
+    /// loads nothing, if the index was saved under a different `key`.
+    /// Like `load_domains`, this adds to the current rules.
+    pub async fn load_index(&self, path: &Path, key: &str) -> Result<bool> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = IndexReader { bytes: &bytes };
+        if reader.take(INDEX_MAGIC.len())? != INDEX_MAGIC {
+            anyhow::bail!("not a blocklist index: {}", path.display());
+        }
+        if reader.read_str()? != key {
+            return Ok(false);
+        }
+        // Parse everything before touching the live sets, so a corrupt
+        // index leaves the blocklist unchanged
+        let index_exact = reader.read_set()?;
+        let index_wildcards = reader.read_set()?;
+        let index_suffixes = reader.read_set()?;
+        let index_allowed = reader.read_set()?;
+        let mut index_regexes = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            index_regexes.push(Regex::new(&reader.read_str()?)?);
+        }
+
+        let mut exact = self.exact_matches.write().await;
+        let mut trie = self.domains.write().await;
+        let mut wildcards = self.wildcards.write().await;
+        let mut suffixes = self.suffixes.write().await;
+        let mut regexes = self.regexes.write().await;
+        let mut allowed = self.allowed.write().await;
+
+        for domain in index_exact {
+            trie.insert(domain.clone(), ());
+            exact.insert(domain);
+        }
+        wildcards.extend(index_wildcards);
+        suffixes.extend(index_suffixes);
+        allowed.extend(index_allowed);
+        for re in index_regexes {
+            if !regexes.iter().any(|r| r.as_str() == re.as_str()) {
+                regexes.push(re);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Get the number of blocked domains (exact + wildcards + suffixes + regexes,
+    /// plus the rules of enabled categories; temporary blocks are not counted)
     pub async fn count(&self) -> usize {
+        self.counts().await.total()
+    }
+
+    /// The rules behind `count`, by kind; enabled categories add to each
+    pub async fn counts(&self) -> RuleCounts {
         let exact = self.exact_matches.read().await;
         let wildcards = self.wildcards.read().await;
-        exact.len() + wildcards.len()
+        let suffixes = self.suffixes.read().await;
+        let regexes = self.regexes.read().await;
+        let categories = self.categories.read().await;
+        let disabled = self.disabled_categories.read().await;
+
+        let mut counts = RuleCounts {
+            exact: exact.len(),
+            wildcard: wildcards.len(),
+            suffix: suffixes.len(),
+            regex: regexes.len(),
+        };
+        for (_, rules) in categories
+            .iter()
+            .filter(|(category, _)| !disabled.contains(*category))
+        {
+            counts.exact += rules.exact.len();
+            counts.wildcard += rules.wildcards.len();
+            counts.regex += rules.regexes.len();
+        }
+        counts
     }
 
-    /// Clear all domains from the blocklist
+    /// Every rule that `count` counts, sorted, as it would be written in a
+    /// list: wildcards keep their `*.`, regexes their slashes
+    pub async fn all_domains(&self) -> Vec<String> {
+        let exact = self.exact_matches.read().await;
+        let wildcards = self.wildcards.read().await;
+        let suffixes = self.suffixes.read().await;
+        let regexes = self.regexes.read().await;
+        let categories = self.categories.read().await;
+        let disabled = self.disabled_categories.read().await;
+
+        let mut domains = BTreeSet::new();
+        let mut add = |exact: &HashSet<String>, wildcards: &HashSet<String>, regexes: &[Regex]| {
+            domains.extend(exact.iter().cloned());
+            domains.extend(wildcards.iter().map(|base| format!("*.{}", base)));
+            domains.extend(regexes.iter().map(|re| format!("/{}/", re.as_str())));
+        };
+        add(&exact, &wildcards, &regexes);
+        for (_, rules) in categories
+            .iter()
+            .filter(|(category, _)| !disabled.contains(*category))
+        {
+            add(&rules.exact, &rules.wildcards, &rules.regexes);
+        }
+        domains.extend(suffixes.iter().cloned());
+        domains.into_iter().collect()
+    }
+
+    /// Clear all domains from the blocklist (which categories are disabled
+    /// is kept)
     pub async fn clear(&self) -> Result<()> {
         let mut exact = self.exact_matches.write().await;
         let mut trie = self.domains.write().await;
         let mut wildcards = self.wildcards.write().await;
+        let mut suffixes = self.suffixes.write().await;
+        let mut regexes = self.regexes.write().await;
+        let mut temporary = self.temporary.write().await;
+        let mut categories = self.categories.write().await;
+        let mut allowed = self.allowed.write().await;
 
         exact.clear();
+        allowed.clear();
+        categories.clear();
         wildcards.clear();
+        suffixes.clear();
+        regexes.clear();
+        temporary.clear();
         *trie = Trie::new();
 
         Ok(())
     }
 
-    /// Reload blocklist (clear and load new domains)
+    /// Reload blocklist (clear and load new domains). The new rules are
+    /// parsed off to the side and swapped in at once (see `replace_with`),
+    /// so lookups never see an empty or half-loaded list. Which categories
+    /// are disabled is kept.
     pub async fn reload(&self, domains: Vec<String>) -> Result<()> {
-        self.clear().await?;
-        self.load_domains(domains).await?;
+        let fresh = BlocklistManager::new();
+        fresh.load_domains(domains).await?;
+        fresh
+            .set_disabled_categories(self.disabled_categories.read().await.clone())
+            .await;
+        fresh.set_max_match_labels(self.max_match_labels.load(Ordering::Relaxed));
+        self.replace_with(fresh).await;
         Ok(())
     }
+
+    /// Replace every rule (and the disabled categories and match depth)
+    /// with those of `fresh`, holding all the write locks for the swap only.
+    /// A concurrent lookup sees either the old rules or the new ones.
+    pub async fn replace_with(&self, fresh: BlocklistManager) {
+        let mut fresh_exact = fresh.exact_matches.write().await;
+        let mut fresh_trie = fresh.domains.write().await;
+        let mut fresh_wildcards = fresh.wildcards.write().await;
+        let mut fresh_suffixes = fresh.suffixes.write().await;
+        let mut fresh_regexes = fresh.regexes.write().await;
+        let mut fresh_temporary = fresh.temporary.write().await;
+        let mut fresh_categories = fresh.categories.write().await;
+        let mut fresh_disabled = fresh.disabled_categories.write().await;
+        let mut fresh_allowed = fresh.allowed.write().await;
+
+        // Same lock order as `clear`
+        let mut exact = self.exact_matches.write().await;
+        let mut trie = self.domains.write().await;
+        let mut wildcards = self.wildcards.write().await;
+        let mut suffixes = self.suffixes.write().await;
+        let mut regexes = self.regexes.write().await;
+        let mut temporary = self.temporary.write().await;
+        let mut categories = self.categories.write().await;
+        let mut disabled = self.disabled_categories.write().await;
+        let mut allowed = self.allowed.write().await;
+
+        std::mem::swap(&mut *exact, &mut *fresh_exact);
+        std::mem::swap(&mut *trie, &mut *fresh_trie);
+        std::mem::swap(&mut *wildcards, &mut *fresh_wildcards);
+        std::mem::swap(&mut *suffixes, &mut *fresh_suffixes);
+        std::mem::swap(&mut *regexes, &mut *fresh_regexes);
+        std::mem::swap(&mut *temporary, &mut *fresh_temporary);
+        std::mem::swap(&mut *categories, &mut *fresh_categories);
+        std::mem::swap(&mut *disabled, &mut *fresh_disabled);
+        std::mem::swap(&mut *allowed, &mut *fresh_allowed);
+        self.max_match_labels.store(
+            fresh.max_match_labels.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_category_toggle_changes_decisions() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec!["ads.example.com".to_string()])
+            .await
+            .unwrap();
+        manager
+            .load_category(
+                "social",
+                vec!["facebook.com".to_string(), "*.fbcdn.net".to_string()],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            manager.matching_rule("facebook.com").await,
+            Some(MatchKind::Category(
+                "social".to_string(),
+                Box::new(MatchKind::Exact("facebook.com".to_string()))
+            ))
+        );
+        assert!(manager.is_blocked("static.fbcdn.net").await);
+        assert_eq!(manager.count().await, 3);
+
+        manager.set_category_enabled("social", false).await;
+        assert!(!manager.is_blocked("facebook.com").await);
+        assert!(!manager.is_blocked("static.fbcdn.net").await);
+        assert!(manager.is_blocked("ads.example.com").await);
+        assert_eq!(manager.count().await, 1);
+
+        manager.set_category_enabled("social", true).await;
+        assert!(manager.is_blocked("facebook.com").await);
+    }
+
+    #[tokio::test]
+    async fn test_temporary_block_expires() {
+        let manager = BlocklistManager::new();
+        let start = SystemTime::now();
+        let expires = start + Duration::from_secs(2 * 3600);
+        manager
+            .add_domain_until("distraction.com".to_string(), expires)
+            .await
+            .unwrap();
+        manager
+            .add_domain_until("*.videos.com".to_string(), expires)
+            .await
+            .unwrap();
+
+        let hour_in = start + Duration::from_secs(3600);
+        assert_eq!(
+            manager.matching_rule_at("distraction.com", hour_in).await,
+            Some(MatchKind::Temporary("distraction.com".to_string()))
+        );
+        assert!(manager
+            .matching_rule_at("cdn.videos.com", hour_in)
+            .await
+            .is_some());
+        assert!(manager
+            .matching_rule_at("videos.com", hour_in)
+            .await
+            .is_none());
+
+        let after = start + Duration::from_secs(3 * 3600);
+        assert!(manager
+            .matching_rule_at("distraction.com", after)
+            .await
+            .is_none());
+        assert_eq!(manager.sweep_expired_at(hour_in).await, 0);
+        assert_eq!(manager.sweep_expired_at(after).await, 2);
+
+        assert!(manager
+            .add_domain_with_ttl("/ads/".to_string(), Duration::from_secs(60))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_matching_rule_reports_responsible_rule() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec![
+                "ads.example.com".to_string(),
+                "*.tracker.com".to_string(),
+                "/^telemetry[0-9]+\\./".to_string(),
+            ])
+            .await
+            .unwrap();
+        manager
+            .load_suffixes(vec!["doubleclick.net".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            manager.matching_rule("ADS.example.com.").await,
+            Some(MatchKind::Exact("ads.example.com".to_string()))
+        );
+        assert_eq!(
+            manager.matching_rule("a.b.tracker.com").await,
+            Some(MatchKind::Wildcard("tracker.com".to_string()))
+        );
+        assert_eq!(
+            manager.matching_rule("stats.doubleclick.net").await,
+            Some(MatchKind::Suffix("doubleclick.net".to_string()))
+        );
+        assert_eq!(
+            manager.matching_rule("telemetry42.vendor.io").await,
+            Some(MatchKind::Regex("^telemetry[0-9]+\\.".to_string()))
+        );
+        assert_eq!(manager.matching_rule("tracker.com").await, None);
+        assert_eq!(
+            MatchKind::Wildcard("tracker.com".to_string()).to_string(),
+            "wildcard *.tracker.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_domains_reports_added_duplicates_and_invalid() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec!["ads.com".to_string()])
+            .await
+            .unwrap();
+
+        let report = manager
+            .add_domains(
+                [
+                    "tracker.net",
+                    "ADS.com",
+                    "*.cdn.example",
+                    "/^ad[0-9]+\\./",
+                    "tracker.net",
+                    "@@ok.ads.com",
+                    "bad domain.com",
+                    "/[unclosed/",
+                    "*.",
+                ]
+                .map(String::from)
+                .to_vec(),
+            )
+            .await;
+        assert_eq!(
+            report.added,
+            [
+                "tracker.net",
+                "*.cdn.example",
+                "/^ad[0-9]+\\./",
+                "@@ok.ads.com"
+            ]
+        );
+        assert_eq!(report.duplicates, 2);
+        assert_eq!(report.invalid, 3);
+        assert_eq!(report.to_string(), "added 4, 2 duplicates, 3 invalid");
+
+        assert!(manager.is_blocked("tracker.net").await);
+        assert!(manager.is_blocked("img.cdn.example").await);
+        assert!(manager.is_blocked("ad1.example.org").await);
+        assert!(!manager.is_blocked("ok.ads.com").await);
+    }
+
     #[tokio::test]
     async fn test_blocklist() {
         let manager = BlocklistManager::new();
@@ -214,6 +1203,46 @@ mod tests {
         assert!(manager.is_blocked("domain2.com").await);
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_reload_is_atomic_for_concurrent_lookups() {
+        let list = |generation: usize| -> Vec<String> {
+            let mut domains: Vec<String> = (0..5_000)
+                .map(|i| format!("gen{}-{}.example.com", generation, i))
+                .collect();
+            domains.push("always.example.com".to_string());
+            domains.push("*.always.net".to_string());
+            domains
+        };
+        let manager = Arc::new(BlocklistManager::new());
+        manager.load_domains(list(0)).await.unwrap();
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let lookups = {
+            let manager = Arc::clone(&manager);
+            let done = Arc::clone(&done);
+            tokio::spawn(async move {
+                let mut checked = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    assert!(manager.is_blocked("always.example.com").await);
+                    assert!(manager.is_blocked("cdn.always.net").await);
+                    checked += 1;
+                    tokio::task::yield_now().await;
+                }
+                checked
+            })
+        };
+
+        for generation in 1..=20 {
+            manager.reload(list(generation)).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        assert!(lookups.await.unwrap() > 0);
+        assert!(manager.is_blocked("gen20-0.example.com").await);
+        assert!(!manager.is_blocked("gen19-0.example.com").await);
+    }
+
     #[tokio::test]
     async fn test_wildcard_basic() {
         let manager = BlocklistManager::new();
@@ -237,6 +1266,33 @@ mod tests {
         assert!(!manager.is_blocked("example.org").await);
     }
 
+    #[tokio::test]
+    async fn test_wildcard_coverage() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec!["*.example.com".to_string(), "plain.org".to_string()])
+            .await
+            .unwrap();
+        let wildcard = Some(MatchKind::Wildcard("example.com".to_string()));
+
+        // The base itself is allowed, everything under it is caught
+        let coverage = manager.wildcard_coverage("example.com").await;
+        assert_eq!(coverage.domain, None);
+        assert_eq!(coverage.subdomains, wildcard);
+
+        let coverage = manager.wildcard_coverage("www.example.com").await;
+        assert_eq!(coverage.domain, wildcard);
+        assert_eq!(coverage.subdomains, wildcard);
+
+        // An exact entry doesn't cover subdomains
+        let coverage = manager.wildcard_coverage("plain.org").await;
+        assert_eq!(
+            coverage.domain,
+            Some(MatchKind::Exact("plain.org".to_string()))
+        );
+        assert_eq!(coverage.subdomains, None);
+    }
+
     #[tokio::test]
     async fn test_wildcard_multi_level() {
         let manager = BlocklistManager::new();
@@ -279,6 +1335,162 @@ mod tests {
         assert_eq!(manager.count().await, 2);
     }
 
+    #[tokio::test]
+    async fn test_most_specific_allow_or_block_rule_wins() {
+        let manager = BlocklistManager::new();
+        let rules = crate::BlocklistDownloader::parse_blocklist(
+            "*.example.com\n\
+             @@*.safe.example.com\n\
+             *.ads.safe.example.com\n\
+             @@ok.ads.safe.example.com\n\
+             pinned.safe.example.com\n\
+             @@*.tracker.net\n\
+             /^ads/\n",
+        );
+        manager.load_domains(rules).await.unwrap();
+        manager
+            .load_suffixes(vec!["metrics.safe.example.com".to_string()])
+            .await
+            .unwrap();
+
+        // Depth 1: the allow wildcard carves a hole in the block wildcard
+        assert!(!manager.is_blocked("a.safe.example.com").await);
+        assert!(!manager.is_blocked("a.b.safe.example.com").await);
+        assert!(manager.is_blocked("a.ads.example.com").await);
+        // `*.safe.example.com` doesn't cover the apex itself
+        assert!(manager.is_blocked("safe.example.com").await);
+
+        // Depth 2: a narrower block wildcard inside the hole
+        assert_eq!(
+            manager.matching_rule("x.ads.safe.example.com").await,
+            Some(MatchKind::Wildcard("ads.safe.example.com".to_string()))
+        );
+        // Depth 3: and an exact allow inside that
+        assert!(!manager.is_blocked("ok.ads.safe.example.com").await);
+        assert!(manager.is_blocked("sub.ok.ads.safe.example.com").await);
+
+        // Exact and suffix rules more specific than the allow wildcard block
+        assert_eq!(
+            manager.matching_rule("pinned.safe.example.com").await,
+            Some(MatchKind::Exact("pinned.safe.example.com".to_string()))
+        );
+        assert_eq!(
+            manager.matching_rule("a.metrics.safe.example.com").await,
+            Some(MatchKind::Suffix("metrics.safe.example.com".to_string()))
+        );
+
+        // Regexes are the least specific rule, so any allow rule beats them
+        assert!(manager.is_blocked("ads.other.org").await);
+        assert!(!manager.is_blocked("ads.tracker.net").await);
+    }
+
+    #[tokio::test]
+    async fn test_deeply_nested_names_match() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec!["*.ads.example.com".to_string()])
+            .await
+            .unwrap();
+        manager
+            .load_suffixes(vec!["tracker.net".to_string()])
+            .await
+            .unwrap();
+
+        let deep = |labels: usize, base: &str| format!("{}{}", "x.".repeat(labels), base);
+        assert_eq!(
+            manager.matching_rule(&deep(120, "ads.example.com")).await,
+            Some(MatchKind::Wildcard("ads.example.com".to_string()))
+        );
+        assert!(manager.is_blocked(&deep(500, "ads.example.com")).await);
+        assert!(manager.is_blocked(&deep(500, "tracker.net")).await);
+        assert!(!manager.is_blocked(&deep(500, "example.com")).await);
+        assert!(!manager.is_blocked("ads.example.com").await);
+
+        // Parents come out longest first; those over 127 labels are skipped
+        let parents: Vec<&str> = parent_domains("a.b.example.com", MAX_LABELS).collect();
+        assert_eq!(parents, ["b.example.com", "example.com", "com"]);
+        let name = deep(200, "com");
+        let mut parents = parent_domains(&name, MAX_LABELS);
+        assert_eq!(parents.next().unwrap().split('.').count(), MAX_LABELS);
+        assert_eq!(parents.count(), MAX_LABELS - 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_match_labels_caps_lookup_depth() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(
+                [
+                    "*.example.com",
+                    "*.d.e.f.example.com",
+                    "/^a\\./",
+                    "/\\.f\\.example\\.com$/",
+                ]
+                .map(String::from)
+                .to_vec(),
+            )
+            .await
+            .unwrap();
+        manager.set_max_match_labels(4);
+
+        // 8 labels: still caught by the shallow wildcard
+        let deep = "a.b.c.d.e.f.example.com";
+        assert_eq!(
+            manager.matching_rule(deep).await,
+            Some(MatchKind::Wildcard("example.com".to_string()))
+        );
+        // ...but parents over 4 labels are never looked up
+        let parents: Vec<&str> = parent_domains(deep, 4).collect();
+        assert_eq!(
+            parents,
+            ["e.f.example.com", "f.example.com", "example.com", "com"]
+        );
+        manager.remove_domain("*.example.com").await.unwrap();
+        assert_eq!(
+            manager.matching_rule(deep).await,
+            Some(MatchKind::Regex("\\.f\\.example\\.com$".to_string()))
+        );
+        // Regexes only see the last 4 labels: `^a\.` can't match
+        assert_eq!(capped_name(deep, 4), "e.f.example.com");
+        assert!(manager.is_blocked("a.example.com").await);
+
+        // Within the cap, deeper rules apply as usual
+        manager.set_max_match_labels(MAX_LABELS);
+        assert_eq!(
+            manager.matching_rule(deep).await,
+            Some(MatchKind::Wildcard("d.e.f.example.com".to_string()))
+        );
+    }
+
+    /// Wildcard lookups on 100-label names against the old per-suffix
+    /// `join`, which allocated one string per label:
+    /// `cargo test --release bench_deep -- --ignored --nocapture`
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_deep_name_wildcard_matching() {
+        fn joined_suffixes<'a>(domain: &str, wildcards: &'a HashSet<String>) -> Option<&'a String> {
+            let parts: Vec<&str> = domain.split('.').collect();
+            (1..parts.len()).find_map(|i| wildcards.get(&parts[i..].join(".")))
+        }
+
+        let wildcards: HashSet<String> = ["ads.example.com".to_string()].into();
+        let name = format!("{}example.org", "x.".repeat(100));
+        let rounds = 10_000;
+        let time = |lookup: &dyn Fn() -> bool| {
+            let started = std::time::Instant::now();
+            for _ in 0..rounds {
+                assert!(!std::hint::black_box(lookup()));
+            }
+            started.elapsed() / rounds
+        };
+        let joined = time(&|| joined_suffixes(&name, &wildcards).is_some());
+        let sliced =
+            time(&|| BlocklistManager::matching_wildcard(&name, &wildcards, MAX_LABELS).is_some());
+        println!(
+            "100 labels: join {joined:?} (101 allocations) vs slices {sliced:?} (none) per lookup"
+        );
+    }
+
     #[tokio::test]
     async fn test_wildcard_removal() {
         let manager = BlocklistManager::new();
@@ -336,4 +1548,164 @@ mod tests {
 
         assert_eq!(manager.count().await, 4);
     }
+
+    #[tokio::test]
+    async fn test_suffix_matches_apex_and_subdomains() {
+        let manager = BlocklistManager::new();
+
+        manager
+            .load_suffixes(vec![
+                "Tracker.com".to_string(),
+                "ads.example.org".to_string(),
+            ])
+            .await
+            .unwrap();
+
+        // Apex and any depth of subdomain
+        assert!(manager.is_blocked("tracker.com").await);
+        assert!(manager.is_blocked("cdn.tracker.com.").await);
+        assert!(manager.is_blocked("a.b.tracker.com").await);
+        assert!(manager.is_blocked("ads.example.org").await);
+        assert!(manager.is_blocked("x.ads.example.org").await);
+
+        // Parents and lookalikes are not matched
+        assert!(!manager.is_blocked("example.org").await);
+        assert!(!manager.is_blocked("nottracker.com").await);
+
+        assert_eq!(manager.count().await, 2);
+        manager.clear().await.unwrap();
+        assert!(!manager.is_blocked("tracker.com").await);
+    }
+
+    #[tokio::test]
+    async fn test_index_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("blocklist-index.bin");
+
+        let original = BlocklistManager::new();
+        original
+            .load_domains(vec![
+                "exact.com".to_string(),
+                "*.wildcard.com".to_string(),
+                "Mixed.Case.org".to_string(),
+                "/^ads[0-9]+\\./".to_string(),
+            ])
+            .await
+            .unwrap();
+        original
+            .load_suffixes(vec!["suffix.net".to_string()])
+            .await
+            .unwrap();
+        original.save_index(&index, "sources-v1").await.unwrap();
+
+        let restored = BlocklistManager::new();
+        assert!(restored.load_index(&index, "sources-v1").await.unwrap());
+        assert_eq!(restored.count().await, original.count().await);
+
+        for domain in [
+            "exact.com",
+            "sub.exact.com",
+            "wildcard.com",
+            "a.wildcard.com",
+            "mixed.case.org",
+            "suffix.net",
+            "deep.sub.suffix.net",
+            "ads42.example.com",
+            "other.com",
+        ] {
+            assert_eq!(
+                restored.is_blocked(domain).await,
+                original.is_blocked(domain).await,
+                "decision differs for {domain}"
+            );
+        }
+
+        // An index built for other sources is not loaded
+        let other = BlocklistManager::new();
+        assert!(!other.load_index(&index, "sources-v2").await.unwrap());
+        assert_eq!(other.count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_index_rejects_corrupt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("blocklist-index.bin");
+        std::fs::write(&index, b"SKBHIDX2\x05\x00").unwrap();
+
+        let manager = BlocklistManager::new();
+        assert!(manager.load_index(&index, "key").await.is_err());
+        assert_eq!(manager.count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_counts_by_rule_kind() {
+        let manager = BlocklistManager::new();
+        manager
+            .load_domains(vec![
+                "ads.example.com".to_string(),
+                "track.example.com".to_string(),
+                "*.doubleclick.net".to_string(),
+                r"/^ad[0-9]+\./".to_string(),
+            ])
+            .await
+            .unwrap();
+        manager
+            .load_suffixes(vec!["tracker.org".to_string()])
+            .await
+            .unwrap();
+        manager
+            .load_category(
+                "social",
+                vec!["facebook.com".to_string(), "*.tiktok.com".to_string()],
+            )
+            .await
+            .unwrap();
+
+        let counts = manager.counts().await;
+        assert_eq!(
+            counts,
+            RuleCounts {
+                exact: 3,
+                wildcard: 2,
+                suffix: 1,
+                regex: 1,
+            }
+        );
+        assert_eq!(manager.count().await, counts.total());
+        assert_eq!(counts.to_string(), "3 exact, 2 wildcard, 1 suffix, 1 regex");
+
+        // A disabled category drops out of the breakdown
+        manager.set_category_enabled("social", false).await;
+        let counts = manager.counts().await;
+        assert_eq!((counts.exact, counts.wildcard), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_regex_rules() {
+        let manager = BlocklistManager::new();
+
+        manager
+            .load_domains(vec![
+                r"/^ad[sx]?[0-9]+\.example\.com$/".to_string(),
+                "/(unclosed/".to_string(),
+            ])
+            .await
+            .unwrap();
+
+        // The invalid pattern is skipped, the valid one matches
+        assert_eq!(manager.count().await, 1);
+        assert!(manager.is_blocked("ads1.example.com").await);
+        assert!(manager.is_blocked("AD22.Example.com.").await);
+        assert!(!manager.is_blocked("ads.example.com").await);
+        assert!(!manager.is_blocked("x.ads1.example.com").await);
+
+        // add_domain reports invalid patterns instead of skipping them
+        assert!(manager.add_domain("/[/".to_string()).await.is_err());
+
+        manager
+            .remove_domain(r"/^ad[sx]?[0-9]+\.example\.com$/")
+            .await
+            .unwrap();
+        assert!(!manager.is_blocked("ads1.example.com").await);
+    }
 }
@@ -1,12 +1,17 @@
 use crate::config::Upstream;
+use crate::loader::SourceCache;
 use crate::{BlocklistDownloader, BlocklistManager, Config, DnsServer, Result, UpdateScheduler};
+use anyhow::Context;
 use clap::{Parser, Subcommand};
 use colored::*;
 use futures::stream::StreamExt;
 use signal_hook::consts::signal::*;
 use signal_hook_tokio::Signals;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 // Platform-specific default config path
 #[cfg(target_os = "linux")]
@@ -21,6 +26,19 @@ const DEFAULT_CONFIG_PATH: &str = "C:\\ProgramData\\Skypier\\blackhole.toml";
 #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
 const DEFAULT_CONFIG_PATH: &str = "blackhole.toml";
 
+/// Exit status of `test` when the domain is blocked (allowed is 0)
+pub const EXIT_BLOCKED: u8 = 1;
+
+/// Exit status for any error, including a failed `update` download
+pub const EXIT_ERROR: u8 = 2;
+
+/// Exit status of `status` when the server isn't running (LSB convention)
+pub const EXIT_STOPPED: u8 = 3;
+
+/// Quiet period after a SIGHUP before the reload runs; further SIGHUPs within
+/// it are folded into the same reload (a scripted `add` loop sends one each)
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
 /// ASCII art logo, shared by the console banner and the TUI dashboard
 pub(crate) const BANNER: &str = r#"
        ____  __           __    __          __
@@ -64,26 +82,360 @@ fn format_upstream_list(upstreams: &[Upstream]) -> String {
     }
 }
 
-/// Find the PID of the running skypier-blackhole server
-fn find_server_pid() -> Result<Option<u32>> {
-    let output = std::process::Command::new("pgrep")
-        .arg("-f")
-        .arg("skypier-blackhole.*start")
-        .output()?;
+/// Parse the `--listen` value into a socket address
+fn parse_listen(value: &str) -> std::result::Result<SocketAddr, String> {
+    value.parse().map_err(|_| {
+        format!("'{value}' is not ADDR:PORT (e.g. 0.0.0.0:5353, or [::1]:5353 for IPv6)")
+    })
+}
+
+/// Parse a `block --for` duration such as `90s`, `30m`, `2h` or `1d`
+fn parse_block_duration(value: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("'{value}' is not a duration (e.g. 90s, 30m, 2h, 1d)");
+    let split = value.len().saturating_sub(1);
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(invalid()),
+    };
+    match amount.checked_mul(unit_secs) {
+        Some(secs) if secs > crate::dns::MAX_PAUSE.as_secs() => Err(format!(
+            "'{value}' is longer than the {}d maximum",
+            crate::dns::MAX_PAUSE.as_secs() / 86400
+        )),
+        Some(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Read `add -` input: one entry per line, skipping blanks, `#` comments
+/// and repeats
+fn read_domain_lines(reader: impl std::io::BufRead) -> Result<Vec<String>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut domains = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        if seen.insert(entry.to_string()) {
+            domains.push(entry.to_string());
+        }
+    }
+    Ok(domains)
+}
+
+/// `test --count-only` output: `blocked` or `allowed` for each domain, in
+/// order. Returns whether any was blocked.
+async fn write_verdicts(
+    blocklist: &BlocklistManager,
+    domains: &[String],
+    out: &mut impl std::io::Write,
+) -> Result<bool> {
+    let mut any_blocked = false;
+    for domain in domains {
+        let blocked = blocklist.is_blocked(domain).await;
+        writeln!(out, "{}", if blocked { "blocked" } else { "allowed" })?;
+        any_blocked |= blocked;
+    }
+    Ok(any_blocked)
+}
+
+/// Prompt loop of `repl`: test each domain read from `input` against the
+/// loaded blocklist, until EOF or `quit`/`exit`
+async fn run_repl(
+    blocklist: &BlocklistManager,
+    input: impl std::io::BufRead,
+    mut output: impl std::io::Write,
+) -> Result<()> {
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{} ", ">".bright_cyan())?;
+        output.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            writeln!(output)?;
+            break;
+        };
+        let domain = line.trim();
+        match domain {
+            "" => continue,
+            "quit" | "exit" => break,
+            _ => {}
+        }
+        match blocklist.matching_rule(domain).await {
+            Some(rule) => writeln!(
+                output,
+                "  {} {} {} {}",
+                "[x]".bright_red(),
+                domain.bright_yellow(),
+                "BLOCKED".bright_red().bold(),
+                format!("({})", rule).bright_black()
+            )?,
+            None => writeln!(
+                output,
+                "  {} {} {}",
+                "[ok]".bright_green(),
+                domain.bright_yellow(),
+                "ALLOWED".bright_green().bold()
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Print the rules of `list --show-domains`, the first `limit` of them if set
+fn write_domains(
+    domains: &[String],
+    limit: Option<usize>,
+    mut output: impl std::io::Write,
+) -> Result<()> {
+    let shown = limit.unwrap_or(domains.len()).min(domains.len());
+    for domain in &domains[..shown] {
+        writeln!(output, "    {}", domain)?;
+    }
+    if shown < domains.len() {
+        writeln!(
+            output,
+            "    {}",
+            format!("... and {} more", domains.len() - shown).bright_black()
+        )?;
+    }
+    Ok(())
+}
+
+/// Signal loop of `start`. Returns on SIGTERM/SIGINT or when the stream ends;
+/// SIGHUPs run `reload` once no other SIGHUP has arrived for `debounce`
+async fn handle_signals<S, F, Fut>(mut signals: S, debounce: Duration, mut reload: F)
+where
+    S: futures::Stream<Item = i32> + Unpin,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let mut reload_pending = false;
+    loop {
+        let signal = if reload_pending {
+            match tokio::time::timeout(debounce, signals.next()).await {
+                Ok(signal) => signal,
+                Err(_) => {
+                    reload_pending = false;
+                    reload().await;
+                    continue;
+                }
+            }
+        } else {
+            signals.next().await
+        };
+
+        match signal {
+            Some(SIGHUP) => {
+                tracing::debug!("Received SIGHUP, reload scheduled");
+                reload_pending = true;
+            }
+            Some(SIGTERM | SIGINT) => {
+                tracing::info!("Received shutdown signal, stopping server...");
+                // Server will stop when main task exits
+                break;
+            }
+            Some(_) => unreachable!(),
+            None => break,
+        }
+    }
+}
+
+/// Reload the blocklist sources and swap the result into the running
+/// server's manager in one step, returning the new rule count. Only the
+/// files changed since they were last read through `sources` are re-read.
+/// Entries removed from the files stop matching. A failed `reload_check` is logged,
+/// and with `keep_previous` leaves the current lists in place.
+async fn reload_blocklist(
+    config: &Config,
+    blocklist: &BlocklistManager,
+    sources: &SourceCache,
+) -> Result<usize> {
+    let fresh = BlocklistManager::new();
+    crate::loader::load_blocklist_cached(config, &fresh, sources).await?;
+    if let Err(e) = crate::loader::check_reload(config, &fresh).await {
+        if config.blocklist.reload_check.keep_previous {
+            return Err(e.context("Keeping the previous blocklist"));
+        }
+        tracing::error!("{:#}; serving the reloaded lists anyway", e);
+    }
+    blocklist.replace_with(fresh).await;
+    Ok(blocklist.count().await)
+}
+
+/// Ask the running server (`pid`) to reload its blocklists: over the
+/// control socket when it is listening, otherwise with SIGHUP
+async fn request_reload(config: &Config, pid: u32) -> Result<()> {
+    let socket = std::path::Path::new(&config.server.control_socket);
+    if crate::control::request(socket, "reload").await?.is_none() {
+        send_signal(pid, SIGHUP)?;
+        std::thread::sleep(std::time::Duration::from_millis(300));
+    }
+    Ok(())
+}
+
+/// Ask the running server to reload over the control socket and wait up to
+/// `timeout` for it to finish, returning the new domain count
+async fn reload_and_wait(config: &Config, timeout: Duration) -> Result<usize> {
+    let socket = std::path::Path::new(&config.server.control_socket);
+    let reply = tokio::time::timeout(timeout, crate::control::request(socket, "reload"))
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Timed out after {}s waiting for the reload",
+                timeout.as_secs()
+            )
+        })??
+        .with_context(|| {
+            format!(
+                "No control socket at {}; --wait needs a server listening there",
+                socket.display()
+            )
+        })?;
+    reply
+        .strip_suffix(" domains")
+        .and_then(|count| count.parse().ok())
+        .with_context(|| format!("Unexpected reload reply: {reply:?}"))
+}
 
-    if output.status.success() && !output.stdout.is_empty() {
-        let pid_str = String::from_utf8_lossy(&output.stdout);
-        let pids: Vec<&str> = pid_str.trim().lines().collect();
+/// Busiest clients `status` lists
+const STATUS_TOP_CLIENTS: usize = 5;
+
+/// Answer one control socket request of the running server
+async fn answer_control(
+    request: &str,
+    config: &Config,
+    blocklist: &BlocklistManager,
+    sources: &SourceCache,
+    server: &DnsServer,
+) -> Result<String> {
+    let metrics = server.metrics();
+    match request.split_once(' ').unwrap_or((request, "")) {
+        ("reload", "") => {
+            tracing::info!("Reload requested over the control socket");
+            let count = reload_blocklist(config, blocklist, sources).await?;
+            Ok(format!("{} domains", count))
+        }
+        ("pause", secs) => {
+            let secs: u64 = secs
+                .parse()
+                .with_context(|| format!("pause needs a number of seconds, got {secs:?}"))?;
+            server.pause(Duration::from_secs(secs))?;
+            Ok(format!("{secs}s"))
+        }
+        ("flush_cache", "") => Ok(format!("{} entries", server.flush_cache())),
+        ("resume", "") => Ok(if server.resume() {
+            String::new()
+        } else {
+            "not paused".to_string()
+        }),
+        ("upstreams", "") => Ok(metrics
+            .upstream_stats()
+            .iter()
+            .map(|(upstream, stats)| format!("{upstream}: {stats}"))
+            .collect::<Vec<_>>()
+            .join("; ")),
+        ("clients", "") => Ok(metrics
+            .top_clients(STATUS_TOP_CLIENTS)
+            .iter()
+            .map(|(ip, stats)| format!("{ip}: {stats}"))
+            .collect::<Vec<_>>()
+            .join("; ")),
+        _ => anyhow::bail!("unknown command {:?}", request),
+    }
+}
 
-        // Return the first PID (there should only be one server)
-        if let Some(pid) = pids.first() {
-            if let Ok(pid_num) = pid.parse::<u32>() {
-                return Ok(Some(pid_num));
+/// Run one JSON-RPC control method against the running server
+async fn call_control(
+    method: crate::control::Method,
+    config: &Config,
+    blocklist: &BlocklistManager,
+    sources: &SourceCache,
+    server: &DnsServer,
+) -> Result<serde_json::Value> {
+    use crate::control::Method;
+    use serde_json::json;
+
+    match method {
+        Method::Reload => {
+            tracing::info!("Reload requested over the control socket");
+            let count = reload_blocklist(config, blocklist, sources).await?;
+            Ok(json!({ "domains": count }))
+        }
+        Method::Stats => {
+            let metrics = server.metrics();
+            Ok(json!({
+                "domains": blocklist.count().await,
+                "queries": metrics.total_queries(),
+                "blocked": metrics.blocked_queries(),
+                "allowed": metrics.allowed_queries(),
+                "stale_served": metrics.stale_served(),
+                "webhook_dropped": metrics.webhook_dropped(),
+                "cached_answers": metrics.cached_answers(),
+                "unique_clients": metrics.unique_clients(),
+                "uptime_secs": metrics.uptime().as_secs(),
+            }))
+        }
+        Method::FlushCache => Ok(json!({ "flushed": server.flush_cache() })),
+        Method::Add(domain) => {
+            crate::loader::validate_custom_entry(&domain)
+                .with_context(|| format!("Refusing to add '{domain}'"))?;
+            // Rules the custom list already has are not written again
+            let current = BlocklistManager::new();
+            current
+                .load_domains(crate::loader::read_custom_list(config)?)
+                .await?;
+            let report = current.add_domains(vec![domain]).await;
+            if report.added.is_empty() {
+                return Ok(json!({ "added": false, "domains": blocklist.count().await }));
             }
+            crate::loader::append_custom_domains(config, &report.added)?;
+            let count = reload_blocklist(config, blocklist, sources).await?;
+            Ok(json!({ "added": true, "domains": count }))
         }
+        Method::Remove(domain) => {
+            if crate::loader::remove_custom_domain(config, &domain)?.is_none() {
+                return Ok(json!({ "removed": false, "domains": blocklist.count().await }));
+            }
+            let count = reload_blocklist(config, blocklist, sources).await?;
+            Ok(json!({ "removed": true, "domains": count }))
+        }
+        Method::List => Ok(json!(crate::loader::read_custom_list(config)?)),
     }
+}
+
+/// Number of custom-list domains `prune --resolve` looks up at once
+const PRUNE_CONCURRENCY: usize = 16;
+
+/// The `domains` an upstream answers NXDOMAIN for, in list order. Any other
+/// outcome (an answer, SERVFAIL, a timeout) keeps the domain, so a flaky
+/// upstream can't empty the list.
+async fn find_dead_domains(server: &DnsServer, domains: &[String]) -> Vec<String> {
+    futures::stream::iter(domains)
+        .map(|domain| async move {
+            match server.resolve_rcode(domain).await {
+                Ok(rcode) => (domain, rcode == hickory_proto::op::ResponseCode::NXDomain),
+                Err(e) => {
+                    tracing::debug!(domain = %domain, error = %e, "Lookup failed, keeping the domain");
+                    (domain, false)
+                }
+            }
+        })
+        .buffered(PRUNE_CONCURRENCY)
+        .filter_map(|(domain, dead)| async move { dead.then(|| domain.clone()) })
+        .collect()
+        .await
+}
 
-    Ok(None)
+/// Find the PID of the running skypier-blackhole server via its PID file
+fn find_server_pid(config: &Config) -> Result<Option<u32>> {
+    crate::pidfile::read(std::path::Path::new(&config.server.pid_file))
 }
 
 /// Send a signal to the running server
@@ -94,6 +446,7 @@ fn send_signal(pid: u32, signal: i32) -> Result<()> {
     let sig = match signal {
         SIGTERM => Signal::SIGTERM,
         SIGHUP => Signal::SIGHUP,
+        SIGKILL => Signal::SIGKILL,
         _ => anyhow::bail!("Unsupported signal"),
     };
 
@@ -102,6 +455,41 @@ fn send_signal(pid: u32, signal: i32) -> Result<()> {
     Ok(())
 }
 
+/// How `stop` got the server to exit
+#[derive(Debug, PartialEq, Eq)]
+enum StopOutcome {
+    /// It shut down on SIGTERM
+    Stopped,
+    /// It was still running after the grace period and got SIGKILL
+    Killed,
+}
+
+/// SIGTERM the server, give it `grace` to shut down (polling `is_alive`,
+/// which must also say no once the PID belongs to another process), then
+/// SIGKILL it
+fn stop_server(
+    pid: u32,
+    grace: Duration,
+    mut is_alive: impl FnMut() -> bool,
+) -> Result<StopOutcome> {
+    if !is_alive() {
+        return Ok(StopOutcome::Stopped);
+    }
+    send_signal(pid, SIGTERM)?;
+    let deadline = std::time::Instant::now() + grace;
+    while std::time::Instant::now() < deadline {
+        if !is_alive() {
+            return Ok(StopOutcome::Stopped);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    if !is_alive() {
+        return Ok(StopOutcome::Stopped);
+    }
+    send_signal(pid, SIGKILL)?;
+    Ok(StopOutcome::Killed)
+}
+
 #[derive(Parser)]
 #[command(name = "skypier-blackhole")]
 #[command(author, version, about, long_about = None)]
@@ -110,6 +498,14 @@ pub struct Cli {
     #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
     pub config: String,
 
+    /// Increase log verbosity (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only log errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -121,10 +517,22 @@ pub enum Commands {
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
+        /// Listen on this address instead of the configured one
+        /// (e.g. 0.0.0.0:5353 or [::1]:53)
+        #[arg(long, value_name = "ADDR:PORT", value_parser = parse_listen)]
+        listen: Option<SocketAddr>,
+        /// Answer blocked queries with this IP (e.g. 0.0.0.0) instead of the
+        /// configured blocked_response
+        #[arg(long, value_name = "IP")]
+        sink: Option<IpAddr>,
     },
 
     /// Stop the DNS server
     Stop {
+        /// Seconds to wait for a graceful shutdown before sending SIGKILL
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
@@ -132,6 +540,15 @@ pub enum Commands {
 
     /// Reload blocklists without restarting
     Reload {
+        /// Wait for the server to finish the reload and report the new
+        /// domain count (needs the control socket)
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for the reload with --wait
+        #[arg(long, default_value_t = 30, requires = "wait")]
+        timeout: u64,
+
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
@@ -144,10 +561,29 @@ pub enum Commands {
         config: String,
     },
 
+    /// Drop the answers the running server keeps for `serve_stale`, e.g.
+    /// after an upstream record changed
+    FlushCache {
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Follow the running server's blocked queries as they happen
+    Watch {
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
     /// Add a domain to the blocklist
     Add {
-        /// Domain to add (e.g., ads.example.com or *.tracker.com)
+        /// Domain to add (e.g., ads.example.com or *.tracker.com), or `-` to
+        /// read one domain per line from stdin
         domain: String,
+        /// Add the entry even if it does not look like a valid domain
+        #[arg(long)]
+        force: bool,
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
@@ -162,8 +598,70 @@ pub enum Commands {
         config: String,
     },
 
+    /// Find custom-list domains that no longer exist (NXDOMAIN upstream)
+    Prune {
+        /// Look each exact domain of the custom list up through the upstreams
+        #[arg(long)]
+        resolve: bool,
+
+        /// Remove the dead domains from the custom list instead of only
+        /// listing them
+        #[arg(long, requires = "resolve")]
+        apply: bool,
+
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Block a domain temporarily (e.g. `block youtube.com --for 2h`)
+    Block {
+        /// Domain to block (e.g., videos.example.com or *.example.com)
+        domain: String,
+        /// How long to block it: a number with unit s, m, h or d, up to 7d
+        #[arg(long = "for", value_name = "DURATION", value_parser = parse_block_duration)]
+        duration: Duration,
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Stop blocking for a while: the running server forwards every query
+    /// until the time is up or `resume` is run
+    Pause {
+        /// How long to pause: a number with unit s, m, h or d, up to 7d
+        #[arg(long = "for", value_name = "DURATION", value_parser = parse_block_duration)]
+        duration: Duration,
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// End a `pause` early
+    Resume {
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Switch categorized blocklist sources on or off (e.g.
+    /// `category disable social`)
+    Category {
+        #[command(subcommand)]
+        action: CategoryAction,
+        /// Path to configuration file
+        #[arg(short, long, global = true, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
     /// List blocklist statistics
     List {
+        /// Also print every loaded rule, sorted
+        #[arg(long)]
+        show_domains: bool,
+        /// Print at most this many rules with --show-domains
+        #[arg(long, requires = "show_domains")]
+        limit: Option<usize>,
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
@@ -178,13 +676,77 @@ pub enum Commands {
 
     /// Test if a domain is blocked
     Test {
-        /// Domain to test
+        /// Domains to test (the blocklist is loaded once for all of them)
+        #[arg(required = true)]
+        domains: Vec<String>,
+        /// Also report whether its subdomains are caught by a wildcard
+        #[arg(long)]
+        coverage: bool,
+        /// Print just `blocked` or `allowed` per domain, one line each, for
+        /// scripts (also silences the log like `-q`)
+        #[arg(long, conflicts_with = "coverage")]
+        count_only: bool,
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Load the blocklist once and test domains typed at a prompt
+    Repl {
+        /// Path to configuration file
+        #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
+        config: String,
+    },
+
+    /// Compare two blocklist files (plain or hosts format)
+    Diff {
+        /// Current blocklist file
+        a: String,
+        /// Candidate blocklist file
+        b: String,
+        /// Write the removed (`-domain`) and added (`+domain`) sets to a file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Clean up a blocklist file in place: lowercase, punycode, strip
+    /// comments and invalid lines, dedup and sort
+    Normalize {
+        /// Blocklist file to rewrite
+        file: String,
+        /// Show the normalized list instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Count blocked and allowed queries in a server log and show the
+    /// most blocked domains
+    Analyze {
+        /// Log file to read (the server's console output)
+        logfile: String,
+        /// How many of the most blocked domains to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+
+    /// Send a test query to each configured upstream and report its
+    /// round-trip time
+    PingUpstream {
+        /// Domain to query
+        #[arg(default_value = "example.com")]
         domain: String,
         /// Path to configuration file
         #[arg(short, long, default_value_t = DEFAULT_CONFIG_PATH.to_string())]
         config: String,
     },
 
+    /// Download a remote list and report what it contains, without
+    /// adding it or touching the cache
+    CheckSource {
+        /// URL of the list to check
+        url: String,
+    },
+
     /// Start the DNS server with an interactive terminal dashboard
     Tui {
         /// Path to configuration file
@@ -193,6 +755,22 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum CategoryAction {
+    /// Block the category's domains again
+    Enable {
+        /// Category name, as set with `category = "..."` on a list source
+        name: String,
+    },
+    /// Stop blocking the category's domains (no re-download needed)
+    Disable {
+        /// Category name, as set with `category = "..."` on a list source
+        name: String,
+    },
+    /// Show the configured categories and whether each is enabled
+    List,
+}
+
 impl Cli {
     /// Whether the TUI dashboard was requested (it owns the terminal, so the
     /// console logger must not be installed)
@@ -200,24 +778,91 @@ impl Cli {
         matches!(self.command, Some(Commands::Tui { .. }))
     }
 
-    pub async fn execute(&self) -> Result<()> {
+    /// Log level requested with `-q`/`-v`, overriding `RUST_LOG` and the
+    /// config's `log_level`; None when neither flag was given
+    pub fn log_level(&self) -> Option<&'static str> {
+        let count_only = matches!(
+            self.command,
+            Some(Commands::Test {
+                count_only: true,
+                ..
+            })
+        );
+        if self.quiet || count_only {
+            return Some("error");
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some("info"),
+            2 => Some("debug"),
+            _ => Some("trace"),
+        }
+    }
+
+    /// Run the command and return the process exit status; see the
+    /// `EXIT_*` constants for the non-zero ones
+    pub async fn execute(&self) -> Result<ExitCode> {
         match &self.command {
             Some(Commands::Tui {
                 config: config_path,
-            }) => crate::tui::run(config_path).await,
+            }) => crate::tui::run(config_path, self.log_level())
+                .await
+                .map(|()| ExitCode::SUCCESS),
             Some(Commands::Start {
                 config: config_path,
+                listen,
+                sink,
             }) => {
                 print_banner();
 
-                let config = Config::load_or_prompt_default(config_path)?;
+                let mut config = Config::load_or_prompt_default(config_path)?;
+                if let Some(listen) = listen {
+                    config.set_listen(*listen);
+                }
+                if let Some(sink) = sink {
+                    config.set_sink(*sink);
+                    config.validate()?;
+                }
+                if let Err(e) = crate::logger::log_to_files(&config.logging) {
+                    tracing::warn!("{:#}; logging to the console only", e);
+                }
                 tracing::info!("Starting DNS server...");
 
+                if let Some(pid) = find_server_pid(&config)? {
+                    anyhow::bail!(
+                        "Server already running (PID {pid}, from {})",
+                        config.server.pid_file
+                    );
+                }
+                if let Some(ip) = config.server.block_page_ip {
+                    // The page may simply start after us; blocked sites still
+                    // get the address, they just can't load it yet
+                    let addr = SocketAddr::new(ip, config.server.block_page_port);
+                    match crate::dns::check_block_page(addr).await {
+                        Ok(()) => tracing::info!(addr = %addr, "Block page server reachable"),
+                        Err(e) => {
+                            tracing::warn!("{:#}; blocked sites show an error until it is up", e)
+                        }
+                    }
+                }
+                // Removed again on shutdown when the guard drops
+                let _pid_file = match crate::pidfile::PidFile::create(std::path::Path::new(
+                    &config.server.pid_file,
+                )) {
+                    Ok(pid_file) => Some(pid_file),
+                    Err(e) => {
+                        tracing::warn!("{:#}; stop/reload/status won't find this server", e);
+                        None
+                    }
+                };
+
                 // Create blocklist manager
                 let blocklist = Arc::new(BlocklistManager::new());
 
-                // Load initial blocklist
-                crate::loader::load_blocklist(&config, &blocklist).await?;
+                // Load initial blocklist, remembering what each source held
+                // so reloads skip the unchanged ones
+                let sources = Arc::new(SourceCache::default());
+                crate::loader::load_blocklist_cached(&config, &blocklist, &sources).await?;
                 tracing::info!("Blocklist manager initialized");
 
                 // Create and start update scheduler
@@ -235,42 +880,82 @@ impl Cli {
                 let server = DnsServer::new(config.clone(), Arc::clone(&blocklist))?;
 
                 // Setup signal handling for graceful shutdown and reload
-                let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+                let signals = Signals::new([SIGTERM, SIGINT, SIGHUP])?;
                 let signals_handle = signals.handle();
 
                 let config_clone = config.clone();
                 let blocklist_clone = Arc::clone(&blocklist);
+                let sources_clone = Arc::clone(&sources);
 
                 // Spawn signal handler task
-                let signal_task = tokio::spawn(async move {
-                    while let Some(signal) = signals.next().await {
-                        match signal {
-                            SIGTERM | SIGINT => {
-                                tracing::info!("Received shutdown signal, stopping server...");
-                                // Server will stop when main task exits
-                                break;
-                            }
-                            SIGHUP => {
-                                tracing::info!("Received SIGHUP, reloading blocklists...");
-                                match crate::loader::load_blocklist(&config_clone, &blocklist_clone)
-                                    .await
-                                {
-                                    Ok(_) => {
-                                        let count = blocklist_clone.count().await;
-                                        tracing::info!(
-                                            "Blocklist reloaded successfully with {} domains",
-                                            count
-                                        );
-                                    }
-                                    Err(e) => {
-                                        tracing::error!("Failed to reload blocklist: {}", e);
-                                    }
+                let signal_task =
+                    tokio::spawn(handle_signals(signals, RELOAD_DEBOUNCE, move || {
+                        let config = config_clone.clone();
+                        let blocklist = Arc::clone(&blocklist_clone);
+                        let sources = Arc::clone(&sources_clone);
+                        async move {
+                            tracing::info!("Received SIGHUP, reloading blocklists...");
+                            match reload_blocklist(&config, &blocklist, &sources).await {
+                                Ok(count) => {
+                                    tracing::info!(
+                                        "Blocklist reloaded successfully with {} domains",
+                                        count
+                                    );
+                                }
+                                Err(e) => {
+                                    tracing::error!("Failed to reload blocklist: {}", e);
                                 }
                             }
-                            _ => unreachable!(),
                         }
+                    }));
+
+                // Control socket for `reload`, `pause`, `resume`, `flush_cache`,
+                // `upstreams`, `clients`, `watch` and the JSON-RPC methods;
+                // removed on shutdown when the task is dropped. Signals work
+                // without it.
+                let control_task = match crate::control::ControlSocket::bind(std::path::Path::new(
+                    &config.server.control_socket,
+                )) {
+                    Ok(socket) => {
+                        let socket = socket.with_events(server.block_events());
+                        let config = config.clone();
+                        let blocklist = Arc::clone(&blocklist);
+                        let sources = Arc::clone(&sources);
+                        let control_server = server.clone();
+                        let (rpc_config, rpc_blocklist, rpc_sources, rpc_server) = (
+                            config.clone(),
+                            Arc::clone(&blocklist),
+                            Arc::clone(&sources),
+                            server.clone(),
+                        );
+                        Some(tokio::spawn(socket.serve(
+                            move |request| {
+                                let config = config.clone();
+                                let blocklist = Arc::clone(&blocklist);
+                                let sources = Arc::clone(&sources);
+                                let server = control_server.clone();
+                                async move {
+                                    answer_control(&request, &config, &blocklist, &sources, &server)
+                                        .await
+                                }
+                            },
+                            move |method| {
+                                let config = rpc_config.clone();
+                                let blocklist = Arc::clone(&rpc_blocklist);
+                                let sources = Arc::clone(&rpc_sources);
+                                let server = rpc_server.clone();
+                                async move {
+                                    call_control(method, &config, &blocklist, &sources, &server)
+                                        .await
+                                }
+                            },
+                        )))
+                    }
+                    Err(e) => {
+                        tracing::warn!("{:#}; reload falls back to SIGHUP", e);
+                        None
                     }
-                });
+                };
 
                 // Start DNS server (blocks until error or signal)
                 let server_task = tokio::spawn(async move { server.start().await });
@@ -298,14 +983,19 @@ impl Cli {
                     tracing::warn!("Failed to stop scheduler: {}", e);
                 }
                 signals_handle.close();
+                if let Some(control_task) = control_task {
+                    control_task.abort();
+                    let _ = control_task.await;
+                }
                 tracing::info!("Server shutdown complete");
 
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Stop {
+                timeout,
                 config: config_path,
             }) => {
-                let _config = Config::load(config_path)?;
+                let config = Config::load_or_default(config_path)?;
                 println!(
                     "{}",
                     "Stopping Skypier Blackhole DNS Server"
@@ -314,8 +1004,9 @@ impl Cli {
                 );
                 println!();
 
-                match find_server_pid()? {
-                    Some(pid) => {
+                let pid_file = std::path::Path::new(&config.server.pid_file);
+                match crate::pidfile::read_process(pid_file)? {
+                    Some((pid, started)) => {
                         println!(
                             "  {} Server PID: {}",
                             "[*]".bright_blue(),
@@ -323,24 +1014,30 @@ impl Cli {
                         );
                         println!("  {} Sending SIGTERM...", "[*]".bright_yellow());
 
-                        send_signal(pid, SIGTERM)?;
-
-                        // Wait a bit for graceful shutdown
-                        std::thread::sleep(std::time::Duration::from_millis(500));
-
-                        // Check if still running
-                        match find_server_pid()? {
-                            Some(_) => {
+                        // A PID reused after the server exited is not ours
+                        // to SIGKILL
+                        let grace = Duration::from_secs(*timeout);
+                        let is_server = || crate::pidfile::is_same_process(pid, started);
+                        match stop_server(pid, grace, is_server)? {
+                            StopOutcome::Stopped => {
                                 println!(
-                                    "  {} Server is taking longer to stop (this is normal)",
-                                    "[..]".bright_yellow()
+                                    "  {} Server stopped successfully",
+                                    "[ok]".bright_green().bold()
                                 );
                             }
-                            None => {
+                            StopOutcome::Killed => {
                                 println!(
-                                    "  {} Server stopped successfully",
-                                    "[ok]".bright_green().bold()
+                                    "  {} Server still running after {}s, sent SIGKILL",
+                                    "[!]".bright_yellow(),
+                                    timeout
+                                );
+                                println!(
+                                    "  {} Server killed; in-flight queries were dropped",
+                                    "[x]".bright_red().bold()
                                 );
+                                // Clears the PID file the killed server left
+                                std::thread::sleep(Duration::from_millis(100));
+                                find_server_pid(&config)?;
                             }
                         }
                     }
@@ -350,30 +1047,46 @@ impl Cli {
                 }
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Reload {
+                wait,
+                timeout,
                 config: config_path,
             }) => {
-                let _config = Config::load(config_path)?;
+                let config = Config::load_or_default(config_path)?;
                 println!("{}", "Reloading Blocklists".bright_cyan().bold());
                 println!();
 
-                match find_server_pid()? {
+                match find_server_pid(&config)? {
                     Some(pid) => {
                         println!(
                             "  {} Server PID: {}",
                             "[*]".bright_blue(),
                             pid.to_string().bright_cyan()
                         );
-                        println!("  {} Sending SIGHUP (hot-reload)...", "[*]".bright_yellow());
-
-                        send_signal(pid, SIGHUP)?;
-
-                        std::thread::sleep(std::time::Duration::from_millis(300));
+                        println!("  {} Requesting hot-reload...", "[*]".bright_yellow());
+
+                        if *wait {
+                            match reload_and_wait(&config, Duration::from_secs(*timeout)).await {
+                                Ok(count) => println!(
+                                    "  {} Reload complete: {} domains loaded",
+                                    "[ok]".bright_green().bold(),
+                                    count.to_string().bright_yellow().bold()
+                                ),
+                                Err(e) => {
+                                    println!("  {} {:#}", "[x]".bright_red().bold(), e);
+                                    println!();
+                                    return Ok(ExitCode::from(EXIT_ERROR));
+                                }
+                            }
+                            println!();
+                            return Ok(ExitCode::SUCCESS);
+                        }
+                        request_reload(&config, pid).await?;
 
                         println!(
-                            "  {} Reload signal sent successfully",
+                            "  {} Reload requested successfully",
                             "[ok]".bright_green().bold()
                         );
                         println!(
@@ -392,18 +1105,19 @@ impl Cli {
                 }
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Status {
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
+                let config = Config::load_or_default(config_path)?;
                 println!("{}", "Skypier Blackhole Status".bright_magenta().bold());
                 println!("{}", "=".repeat(50).bright_black());
                 println!();
 
                 // Check if server is running
-                match find_server_pid()? {
+                let pid = find_server_pid(&config)?;
+                match pid {
                     Some(pid) => {
                         println!(
                             "  {} Server Status: {}",
@@ -431,13 +1145,18 @@ impl Cli {
                 if std::path::Path::new(&config.blocklist.custom_list).exists() {
                     let blocklist = BlocklistManager::new();
                     crate::loader::load_blocklist(&config, &blocklist).await?;
-                    let count = blocklist.count().await;
+                    let counts = blocklist.counts().await;
 
                     println!("  {} Blocklist Statistics:", "[*]".bright_cyan());
                     println!(
                         "    {} Total domains blocked: {}",
                         "-".bright_white(),
-                        count.to_string().bright_yellow().bold()
+                        counts.total().to_string().bright_yellow().bold()
+                    );
+                    println!(
+                        "    {} Rules: {}",
+                        "-".bright_white(),
+                        counts.to_string().bright_black()
                     );
                     println!(
                         "    {} Custom list: {}",
@@ -445,16 +1164,12 @@ impl Cli {
                         config.blocklist.custom_list.bright_blue()
                     );
 
-                    if !config.blocklist.local_lists.is_empty() {
+                    let local_lists = config.blocklist.enabled_local_lists();
+                    if !local_lists.is_empty() {
                         println!(
                             "    {} Local lists: {}",
                             "-".bright_white(),
-                            config
-                                .blocklist
-                                .local_lists
-                                .len()
-                                .to_string()
-                                .bright_yellow()
+                            local_lists.len().to_string().bright_yellow()
                         );
                     }
                 } else {
@@ -465,16 +1180,58 @@ impl Cli {
                     );
                 }
 
+                // Forwarding counters of the running server, if it has a
+                // control socket
+                if pid.is_some() {
+                    let socket = std::path::Path::new(&config.server.control_socket);
+                    if let Some(reply) = crate::control::request(socket, "upstreams").await? {
+                        println!();
+                        println!("  {} Upstream Statistics:", "[*]".bright_cyan());
+                        if reply.is_empty() {
+                            println!(
+                                "    {} {}",
+                                "-".bright_white(),
+                                "No queries forwarded yet".bright_black()
+                            );
+                        }
+                        for entry in reply.split("; ").filter(|e| !e.is_empty()) {
+                            let (upstream, stats) = entry.split_once(": ").unwrap_or((entry, ""));
+                            println!(
+                                "    {} {}: {}",
+                                "-".bright_white(),
+                                upstream.bright_green(),
+                                stats
+                            );
+                        }
+                    }
+                    if let Some(reply) = crate::control::request(socket, "clients").await? {
+                        println!();
+                        println!("  {} Top Clients:", "[*]".bright_cyan());
+                        if reply.is_empty() {
+                            println!(
+                                "    {} {}",
+                                "-".bright_white(),
+                                "No queries received yet".bright_black()
+                            );
+                        }
+                        for entry in reply.split("; ").filter(|e| !e.is_empty()) {
+                            let (client, stats) = entry.split_once(": ").unwrap_or((entry, ""));
+                            println!(
+                                "    {} {}: {}",
+                                "-".bright_white(),
+                                client.bright_green(),
+                                stats
+                            );
+                        }
+                    }
+                }
+
                 println!();
                 println!("  {} Configuration:", "[*]".bright_cyan());
                 println!(
                     "    {} Listen: {}",
                     "-".bright_white(),
-                    format!(
-                        "{}:{}",
-                        config.server.listen_addr, config.server.listen_port
-                    )
-                    .bright_green()
+                    config.server.listen_display().bright_green()
                 );
                 println!(
                     "    {} Upstream DNS: {}",
@@ -486,35 +1243,85 @@ impl Cli {
                 println!("{}", "=".repeat(50).bright_black());
                 println!();
 
-                Ok(())
+                Ok(match pid {
+                    Some(_) => ExitCode::SUCCESS,
+                    None => ExitCode::from(EXIT_STOPPED),
+                })
             }
             Some(Commands::Add {
                 domain,
+                force,
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
-                println!(
-                    "{} {}",
-                    "Adding domain:".bright_green().bold(),
-                    domain.bright_cyan()
-                );
+                let config = Config::load_or_default(config_path)?;
+
+                let from_stdin = domain == "-";
+                let domains = if from_stdin {
+                    println!("{}", "Adding domains from stdin".bright_green().bold());
+                    read_domain_lines(std::io::stdin().lock())?
+                } else {
+                    println!(
+                        "{} {}",
+                        "Adding domain:".bright_green().bold(),
+                        domain.bright_cyan()
+                    );
+                    vec![domain.clone()]
+                };
                 println!();
 
-                // Add to custom blocklist file
-                crate::loader::append_custom_domain(&config, domain)?;
+                // A single suspicious entry is refused outright; in a batch
+                // from stdin it is skipped so the rest still goes in
+                let mut skipped = 0;
+                let mut accepted = Vec::with_capacity(domains.len());
+                for entry in domains {
+                    if let Err(e) = crate::loader::validate_custom_entry(&entry) {
+                        println!(
+                            "  {} Suspicious entry '{}': {}",
+                            "[!]".bright_yellow(),
+                            entry,
+                            e
+                        );
+                        if !force {
+                            if !from_stdin {
+                                anyhow::bail!(
+                                    "Refusing to add '{entry}' (use --force to add it anyway)"
+                                );
+                            }
+                            skipped += 1;
+                            continue;
+                        }
+                    }
+                    accepted.push(entry);
+                }
+
+                // Rules the custom list already has are not written again
+                let current = BlocklistManager::new();
+                current
+                    .load_domains(crate::loader::read_custom_list(&config)?)
+                    .await?;
+                let mut report = current.add_domains(accepted).await;
+                report.invalid += skipped;
+                if report.added.is_empty() {
+                    println!("  {} Nothing to add ({})", "[i]".bright_yellow(), report);
+                    println!();
+                    return Ok(ExitCode::SUCCESS);
+                }
+
+                // Add to custom blocklist file in one write
+                crate::loader::append_custom_domains(&config, &report.added)?;
 
                 println!(
-                    "  {} Domain added to: {}",
+                    "  {} Custom list {}: {}",
                     "[ok]".bright_green(),
-                    config.blocklist.custom_list.bright_blue()
+                    config.blocklist.custom_list.bright_blue(),
+                    report
                 );
 
                 // Trigger reload if server is running
-                match find_server_pid()? {
+                match find_server_pid(&config)? {
                     Some(pid) => {
                         println!("  {} Reloading server...", "[*]".bright_cyan());
-                        send_signal(pid, SIGHUP)?;
-                        std::thread::sleep(std::time::Duration::from_millis(300));
+                        request_reload(&config, pid).await?;
                         println!(
                             "  {} Server reloaded, domain is now blocked",
                             "[ok]".bright_green().bold()
@@ -529,13 +1336,13 @@ impl Cli {
                 }
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Remove {
                 domain,
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
+                let config = Config::load_or_default(config_path)?;
                 println!(
                     "{} {}",
                     "Removing domain:".bright_red().bold(),
@@ -545,6 +1352,14 @@ impl Cli {
 
                 if crate::loader::remove_custom_domain(&config, domain)?.is_none() {
                     println!("  {} Domain not found in blocklist", "[i]".bright_yellow());
+                    let entries = crate::loader::read_custom_list(&config)?;
+                    let suggestions = crate::loader::similar_entries(&entries, domain);
+                    if !suggestions.is_empty() {
+                        println!("  {} Did you mean:", "[?]".bright_cyan());
+                        for suggestion in suggestions {
+                            println!("    {} {}", "-".bright_white(), suggestion.bright_yellow());
+                        }
+                    }
                 } else {
                     println!(
                         "  {} Domain removed from: {}",
@@ -553,11 +1368,10 @@ impl Cli {
                     );
 
                     // Trigger reload if server is running
-                    match find_server_pid()? {
+                    match find_server_pid(&config)? {
                         Some(pid) => {
                             println!("  {} Reloading server...", "[*]".bright_cyan());
-                            send_signal(pid, SIGHUP)?;
-                            std::thread::sleep(std::time::Duration::from_millis(300));
+                            request_reload(&config, pid).await?;
                             println!(
                                 "  {} Server reloaded, domain is now allowed",
                                 "[ok]".bright_green().bold()
@@ -573,50 +1387,338 @@ impl Cli {
                 }
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
-            Some(Commands::List {
+            Some(Commands::Prune {
+                resolve,
+                apply,
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
-                println!("{}", "Blocklist Statistics".bright_cyan().bold());
+                let config = Config::load_or_default(config_path)?;
+                println!("{}", "Pruning Custom List".bright_cyan().bold());
                 println!("{}", "=".repeat(50).bright_black());
                 println!();
 
-                let blocklist = BlocklistManager::new();
-                crate::loader::load_blocklist(&config, &blocklist).await?;
-                let total = blocklist.count().await;
+                if !*resolve {
+                    println!(
+                        "  {} Pass {} to look the custom-list domains up upstream",
+                        "[i]".bright_blue(),
+                        "--resolve".bright_white()
+                    );
+                    println!();
+                    return Ok(ExitCode::SUCCESS);
+                }
 
+                // Only exact entries name something that can be looked up
+                let content = match fs::read_to_string(&config.blocklist.custom_list) {
+                    Ok(content) => content,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                let domains: Vec<String> = content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| {
+                        !line.starts_with("*.") && BlocklistDownloader::is_valid_domain(line)
+                    })
+                    .map(str::to_string)
+                    .collect();
                 println!(
-                    "  {} Total Blocked Domains: {}",
-                    "[*]".bright_red(),
-                    total.to_string().bright_yellow().bold()
+                    "  {} Resolving {} domains from {}",
+                    "[*]".bright_blue(),
+                    domains.len().to_string().bright_yellow(),
+                    config.blocklist.custom_list.bright_blue()
                 );
-                println!();
-
-                // Count by source
-                println!("  {} Sources:", "[*]".bright_cyan());
 
-                if std::path::Path::new(&config.blocklist.custom_list).exists() {
-                    let content = fs::read_to_string(&config.blocklist.custom_list)?;
-                    let count = content
-                        .lines()
-                        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-                        .count();
+                let server = DnsServer::new(config.clone(), Arc::new(BlocklistManager::new()))?;
+                let dead = find_dead_domains(&server, &domains).await;
+                println!();
+                if dead.is_empty() {
+                    println!("  {} No NXDOMAIN entries found", "[ok]".bright_green());
+                    println!();
+                    return Ok(ExitCode::SUCCESS);
+                }
+                for domain in &dead {
                     println!(
-                        "    {} Custom list: {} domains",
-                        "-".bright_white(),
-                        count.to_string().bright_green()
+                        "  {} {}  {}",
+                        "[x]".bright_red(),
+                        domain,
+                        "NXDOMAIN".bright_black()
                     );
+                }
+                println!();
+
+                if !*apply {
                     println!(
-                        "      {} {}",
-                        ">".bright_black(),
-                        config.blocklist.custom_list.bright_blue()
+                        "  {} {} dead domains; run again with {} to remove them",
+                        "[i]".bright_blue(),
+                        dead.len().to_string().bright_yellow(),
+                        "--apply".bright_white()
                     );
+                    println!();
+                    return Ok(ExitCode::SUCCESS);
                 }
-
-                for (idx, local_list) in config.blocklist.local_lists.iter().enumerate() {
-                    if std::path::Path::new(local_list).exists() {
+                for domain in &dead {
+                    crate::loader::remove_custom_domain(&config, domain)?;
+                }
+                println!(
+                    "  {} Removed {} domains from {}",
+                    "[ok]".bright_green(),
+                    dead.len().to_string().bright_yellow(),
+                    config.blocklist.custom_list.bright_blue()
+                );
+                if let Some(pid) = find_server_pid(&config)? {
+                    println!("  {} Reloading server...", "[*]".bright_cyan());
+                    request_reload(&config, pid).await?;
+                }
+
+                println!();
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Block {
+                domain,
+                duration,
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                println!(
+                    "{} {}",
+                    "Blocking domain:".bright_red().bold(),
+                    domain.bright_cyan()
+                );
+                println!();
+
+                crate::loader::validate_custom_entry(domain)?;
+                if domain.starts_with('/') {
+                    anyhow::bail!("Temporary blocks do not support regex rules");
+                }
+                let expires = crate::loader::append_temporary_block(&config, domain, *duration)?;
+                println!(
+                    "  {} Blocked until {}",
+                    "[ok]".bright_green(),
+                    chrono::DateTime::<chrono::Local>::from(expires)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                        .bright_yellow()
+                );
+
+                // Trigger reload if server is running
+                match find_server_pid(&config)? {
+                    Some(pid) => {
+                        println!("  {} Reloading server...", "[*]".bright_cyan());
+                        request_reload(&config, pid).await?;
+                        println!(
+                            "  {} Server reloaded, domain is now blocked",
+                            "[ok]".bright_green().bold()
+                        );
+                    }
+                    None => {
+                        println!(
+                            "  {} Server not running - the block applies on next start if still active",
+                            "[i]".bright_yellow()
+                        );
+                    }
+                }
+
+                println!();
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Pause {
+                duration,
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let socket = std::path::Path::new(&config.server.control_socket);
+                let request = format!("pause {}", duration.as_secs());
+                if crate::control::request(socket, &request).await?.is_none() {
+                    println!(
+                        "  {} No server listening on {}",
+                        "[x]".bright_red().bold(),
+                        socket.display().to_string().bright_blue()
+                    );
+                    return Ok(ExitCode::from(EXIT_ERROR));
+                }
+                let until = std::time::SystemTime::now()
+                    .checked_add(*duration)
+                    .context("Pause ends too far in the future")?;
+                println!(
+                    "  {} Blocking paused until {} (run {} to end it early)",
+                    "[ok]".bright_green(),
+                    chrono::DateTime::<chrono::Local>::from(until)
+                        .format("%H:%M:%S")
+                        .to_string()
+                        .bright_yellow(),
+                    "resume".bright_white()
+                );
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Resume {
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let socket = std::path::Path::new(&config.server.control_socket);
+                match crate::control::request(socket, "resume").await? {
+                    None => {
+                        println!(
+                            "  {} No server listening on {}",
+                            "[x]".bright_red().bold(),
+                            socket.display().to_string().bright_blue()
+                        );
+                        return Ok(ExitCode::from(EXIT_ERROR));
+                    }
+                    Some(reply) if reply == "not paused" => {
+                        println!("  {} Blocking was not paused", "[i]".bright_blue())
+                    }
+                    Some(_) => println!("  {} Blocking resumed", "[ok]".bright_green()),
+                }
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Category {
+                action,
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let known = config.blocklist.categories();
+
+                let (name, enabled) = match action {
+                    CategoryAction::Enable { name } => (name, true),
+                    CategoryAction::Disable { name } => (name, false),
+                    CategoryAction::List => {
+                        println!("{}", "Blocklist Categories".bright_cyan().bold());
+                        println!();
+                        if known.is_empty() {
+                            println!("  {} No categories configured", "[i]".bright_yellow());
+                            println!(
+                                "  {} Tag a source with {}",
+                                "->".bright_white(),
+                                "{ url = \"...\", category = \"ads\" }".bright_blue()
+                            );
+                        }
+                        let disabled = crate::loader::read_disabled_categories(&config)?;
+                        for category in &known {
+                            let state = if disabled.contains(category) {
+                                "disabled".bright_red()
+                            } else {
+                                "enabled".bright_green()
+                            };
+                            println!(
+                                "  {} {} ({})",
+                                "-".bright_white(),
+                                category.bright_cyan(),
+                                state
+                            );
+                        }
+                        println!();
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                };
+
+                if !known.contains(name) {
+                    let configured = known.iter().cloned().collect::<Vec<_>>().join(", ");
+                    anyhow::bail!(
+                        "Unknown category '{name}' (configured: {})",
+                        if configured.is_empty() {
+                            "none"
+                        } else {
+                            &configured
+                        }
+                    );
+                }
+
+                let state = if enabled { "enabled" } else { "disabled" };
+                if !crate::loader::set_category_enabled(&config, name, enabled)? {
+                    println!(
+                        "  {} Category {} is already {}",
+                        "[i]".bright_yellow(),
+                        name.bright_cyan(),
+                        state
+                    );
+                    println!();
+                    return Ok(ExitCode::SUCCESS);
+                }
+                println!(
+                    "  {} Category {} {}",
+                    "[ok]".bright_green(),
+                    name.bright_cyan(),
+                    state
+                );
+
+                // Trigger reload if server is running
+                match find_server_pid(&config)? {
+                    Some(pid) => {
+                        println!("  {} Reloading server...", "[*]".bright_cyan());
+                        request_reload(&config, pid).await?;
+                        println!("  {} Server reloaded", "[ok]".bright_green().bold());
+                    }
+                    None => {
+                        println!(
+                            "  {} Server not running - the change applies on next start",
+                            "[i]".bright_yellow()
+                        );
+                    }
+                }
+
+                println!();
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::List {
+                show_domains,
+                limit,
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                println!("{}", "Blocklist Statistics".bright_cyan().bold());
+                println!("{}", "=".repeat(50).bright_black());
+                println!();
+
+                let blocklist = BlocklistManager::new();
+                let sources = crate::loader::load_blocklist(&config, &blocklist).await?;
+                let counts = blocklist.counts().await;
+                let total = counts.total();
+                let entries: usize = sources.iter().filter_map(|s| s.domains).sum();
+                let duplicates = crate::loader::duplicate_count(&sources, total);
+
+                println!(
+                    "  {} Total Blocked Domains: {}",
+                    "[*]".bright_red(),
+                    total.to_string().bright_yellow().bold()
+                );
+                println!(
+                    "  {} Rules: {}",
+                    "[*]".bright_cyan(),
+                    counts.to_string().bright_white()
+                );
+                println!(
+                    "  {} Entries across sources: {} ({} duplicates)",
+                    "[*]".bright_cyan(),
+                    entries.to_string().bright_green(),
+                    duplicates.to_string().bright_yellow()
+                );
+                println!();
+
+                // Count by source
+                println!("  {} Sources:", "[*]".bright_cyan());
+
+                if std::path::Path::new(&config.blocklist.custom_list).exists() {
+                    let content = fs::read_to_string(&config.blocklist.custom_list)?;
+                    let count = content
+                        .lines()
+                        .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+                        .count();
+                    println!(
+                        "    {} Custom list: {} domains",
+                        "-".bright_white(),
+                        count.to_string().bright_green()
+                    );
+                    println!(
+                        "      {} {}",
+                        ">".bright_black(),
+                        config.blocklist.custom_list.bright_blue()
+                    );
+                }
+
+                for (idx, local_list) in config.blocklist.enabled_local_lists().iter().enumerate() {
+                    if std::path::Path::new(local_list).exists() {
                         let content = fs::read_to_string(local_list)?;
                         let count = content
                             .lines()
@@ -632,31 +1734,39 @@ impl Cli {
                     }
                 }
 
-                if !config.blocklist.remote_lists.is_empty() {
+                let remote_lists = config.blocklist.enabled_remote_lists();
+                if !remote_lists.is_empty() {
                     println!();
                     println!(
                         "  {} Remote Sources (not yet downloaded):",
                         "[*]".bright_cyan()
                     );
-                    for url in &config.blocklist.remote_lists {
+                    for url in &remote_lists {
                         println!("    {} {}", "-".bright_white(), url.bright_blue());
                     }
                 }
 
+                if *show_domains {
+                    println!();
+                    println!("  {} Domains:", "[*]".bright_cyan());
+                    write_domains(&blocklist.all_domains().await, *limit, std::io::stdout())?;
+                }
+
                 println!();
                 println!("{}", "=".repeat(50).bright_black());
                 println!();
 
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Update {
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
+                let config = Config::load_or_default(config_path)?;
                 println!("{}", "Updating Blocklists".bright_cyan().bold());
                 println!();
 
-                if config.blocklist.remote_lists.is_empty() {
+                let remote_lists = config.blocklist.enabled_remote_lists();
+                if remote_lists.is_empty() {
                     println!("  {} No remote sources configured", "[!]".bright_yellow());
                     println!();
                     println!(
@@ -672,11 +1782,11 @@ impl Cli {
                     );
                     println!("    {}", "]".bright_blue());
                     println!();
-                    return Ok(());
+                    return Ok(ExitCode::SUCCESS);
                 }
 
                 println!("  {} Remote sources:", "[*]".bright_cyan());
-                for url in &config.blocklist.remote_lists {
+                for url in &remote_lists {
                     println!("    {} {}", "-".bright_white(), url.bright_blue());
                 }
                 println!();
@@ -685,60 +1795,110 @@ impl Cli {
                 println!("  {} Downloading blocklists...", "[*]".bright_yellow());
                 let downloader = BlocklistDownloader::new()?;
 
-                match downloader
-                    .download_multiple(&config.blocklist.remote_lists)
-                    .await
-                {
-                    Ok(domains) => {
+                // Each category is downloaded into its own cache file
+                let mut downloads = Vec::new();
+                let mut failure = None;
+                for (category, urls) in config.blocklist.enabled_remote_groups() {
+                    match downloader.download_multiple(&urls).await {
+                        Ok(domains) => downloads.push((category, domains)),
+                        Err(e) => {
+                            failure = Some(e);
+                            break;
+                        }
+                    }
+                }
+
+                match failure.map_or(Ok(downloads), Err) {
+                    Ok(downloads) => {
+                        let total: usize = downloads.iter().map(|(_, domains)| domains.len()).sum();
                         println!(
                             "  {} Downloaded {} unique domains",
                             "[ok]".bright_green(),
-                            domains.len().to_string().bright_yellow().bold()
+                            total.to_string().bright_yellow().bold()
                         );
 
-                        // Save to a cache file
-                        let cache_dir = std::path::Path::new(&config.blocklist.custom_list)
-                            .parent()
-                            .unwrap_or(std::path::Path::new("/tmp"));
-                        let cache_file = cache_dir.join("remote-blocklist-cache.txt");
-
-                        println!(
-                            "  {} Saving to cache: {}",
-                            "[*]".bright_cyan(),
-                            cache_file.display().to_string().bright_blue()
-                        );
-
-                        let content = domains.join("\n") + "\n";
-                        std::fs::write(&cache_file, content)?;
+                        // Save to cache files, unless a download is much
+                        // smaller than what it would replace
+                        for (category, domains) in &downloads {
+                            if let Some(current) = crate::loader::suspicious_shrink(
+                                &config,
+                                category.as_deref(),
+                                domains.len(),
+                            ) {
+                                println!(
+                                    "  {} Kept the cache of {} domains: the download has only {}",
+                                    "[!]".bright_yellow(),
+                                    current.to_string().bright_yellow(),
+                                    domains.len().to_string().bright_yellow()
+                                );
+                                continue;
+                            }
+                            let cache_file = crate::loader::write_remote_cache(
+                                &config,
+                                category.as_deref(),
+                                domains,
+                            )?;
+                            println!(
+                                "  {} Saved to cache: {}",
+                                "[*]".bright_cyan(),
+                                cache_file.display().to_string().bright_blue()
+                            );
+                        }
 
                         println!("  {} Cache saved successfully", "[ok]".bright_green());
 
-                        // Trigger reload if server is running
-                        match find_server_pid()? {
+                        if config.blocklist.use_index {
+                            crate::loader::rebuild_index(&config).await?;
+                            println!(
+                                "  {} Blocklist index rebuilt: {}",
+                                "[ok]".bright_green(),
+                                crate::loader::index_path(&config)
+                                    .display()
+                                    .to_string()
+                                    .bright_blue()
+                            );
+                        }
+
+                        // The cache is the outcome: reload a running server,
+                        // otherwise `start` loads it. Either way the update
+                        // succeeded.
+                        match find_server_pid(&config)? {
                             Some(pid) => {
                                 println!();
                                 println!(
                                     "  {} Reloading server with new blocklists...",
                                     "[*]".bright_cyan()
                                 );
-                                send_signal(pid, SIGHUP)?;
-                                std::thread::sleep(std::time::Duration::from_millis(500));
-                                println!(
-                                    "  {} Server reloaded successfully",
-                                    "[ok]".bright_green().bold()
-                                );
-                                println!(
-                                    "  {} {} domains now active",
-                                    "[*]".bright_red(),
-                                    domains.len().to_string().bright_yellow().bold()
-                                );
+                                match request_reload(&config, pid).await {
+                                    Ok(()) => {
+                                        println!(
+                                            "  {} Server reloaded successfully",
+                                            "[ok]".bright_green().bold()
+                                        );
+                                        println!(
+                                            "  {} {} domains now active",
+                                            "[*]".bright_red(),
+                                            total.to_string().bright_yellow().bold()
+                                        );
+                                    }
+                                    Err(e) => {
+                                        println!(
+                                            "  {} Couldn't reload the server: {:#}",
+                                            "[!]".bright_yellow(),
+                                            e
+                                        );
+                                        println!(
+                                            "  {} The new cache is loaded on its next reload or start",
+                                            "->".bright_white()
+                                        );
+                                    }
+                                }
                             }
                             None => {
                                 println!();
-                                println!("  {} Server not running", "[i]".bright_yellow());
                                 println!(
-                                    "  {} Start the server to apply new blocklists:",
-                                    "->".bright_white()
+                                    "  {} Server not running; the new cache is loaded when it starts:",
+                                    "[i]".bright_blue()
                                 );
                                 println!("    {}", "skypier-blackhole start".bright_green());
                             }
@@ -754,63 +1914,413 @@ impl Cli {
                             "  {} Check your internet connection and URLs",
                             "[i]".bright_yellow()
                         );
+                        println!();
+                        return Ok(ExitCode::from(EXIT_ERROR));
                     }
                 }
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             Some(Commands::Test {
-                domain,
+                domains,
+                coverage,
+                count_only,
                 config: config_path,
             }) => {
-                let config = Config::load(config_path)?;
-                println!(
-                    "{} {}",
-                    "Testing domain:".bright_cyan().bold(),
-                    domain.bright_yellow()
-                );
-                println!();
+                let config = Config::load_or_default(config_path)?;
 
+                // Loaded once, however many domains are tested
                 let blocklist = BlocklistManager::new();
                 crate::loader::load_blocklist(&config, &blocklist).await?;
 
-                let is_blocked = blocklist.is_blocked(domain).await;
+                let any_blocked = if *count_only {
+                    write_verdicts(&blocklist, domains, &mut std::io::stdout().lock()).await?
+                } else {
+                    let mut any_blocked = false;
+                    for domain in domains {
+                        println!(
+                            "{} {}",
+                            "Testing domain:".bright_cyan().bold(),
+                            domain.bright_yellow()
+                        );
+                        println!();
 
-                if is_blocked {
+                        let matched = blocklist.matching_rule(domain).await;
+                        let blocked = matched.is_some();
+
+                        if let Some(rule) = matched {
+                            println!(
+                                "  {} Status: {}",
+                                "[x]".bright_red(),
+                                "BLOCKED".bright_red().bold()
+                            );
+                            println!(
+                                "  {} Matched: {}",
+                                "[*]".bright_cyan(),
+                                rule.to_string().bright_yellow()
+                            );
+                            println!(
+                                "  {} This domain will be blocked by the DNS server",
+                                "[i]".bright_blue()
+                            );
+                            println!(
+                                "  {} DNS queries will receive: {}",
+                                "->".bright_white(),
+                                "REFUSED".bright_yellow()
+                            );
+                        } else {
+                            println!(
+                                "  {} Status: {}",
+                                "[ok]".bright_green(),
+                                "ALLOWED".bright_green().bold()
+                            );
+                            println!(
+                                "  {} This domain will be resolved normally",
+                                "[i]".bright_blue()
+                            );
+                            println!(
+                                "  {} DNS queries will be forwarded to upstream: {}",
+                                "->".bright_white(),
+                                format_upstream_list(&config.server.upstream_dns).bright_cyan()
+                            );
+                        }
+
+                        if *coverage {
+                            let report = blocklist.wildcard_coverage(domain).await;
+                            println!();
+                            match report.subdomains {
+                                Some(rule) => println!(
+                                    "  {} Subdomains of {}: {} ({})",
+                                    "[x]".bright_red(),
+                                    domain.bright_yellow(),
+                                    "covered".bright_red().bold(),
+                                    rule.to_string().bright_yellow()
+                                ),
+                                None => println!(
+                                    "  {} Subdomains of {}: {} by a wildcard or suffix rule",
+                                    "[ok]".bright_green(),
+                                    domain.bright_yellow(),
+                                    "not covered".bright_green().bold()
+                                ),
+                            }
+                        }
+
+                        println!();
+                        any_blocked |= blocked;
+                    }
+                    any_blocked
+                };
+                Ok(if any_blocked {
+                    ExitCode::from(EXIT_BLOCKED)
+                } else {
+                    ExitCode::SUCCESS
+                })
+            }
+            Some(Commands::Repl {
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let blocklist = BlocklistManager::new();
+                crate::loader::load_blocklist(&config, &blocklist).await?;
+                println!(
+                    "  {} Loaded {} domains; enter one per line, {} or Ctrl-D to leave",
+                    "[*]".bright_cyan(),
+                    blocklist.count().await.to_string().bright_yellow().bold(),
+                    "quit".bright_green()
+                );
+
+                run_repl(
+                    &blocklist,
+                    std::io::BufReader::new(std::io::stdin()),
+                    std::io::stdout(),
+                )
+                .await?;
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Diff { a, b, output }) => {
+                let content_a = fs::read_to_string(a)
+                    .with_context(|| format!("Failed to read blocklist: {}", a))?;
+                let content_b = fs::read_to_string(b)
+                    .with_context(|| format!("Failed to read blocklist: {}", b))?;
+                let diff = crate::loader::diff_lists(&content_a, &content_b);
+
+                println!("{}", "Blocklist Diff".bright_cyan().bold());
+                println!("{}", "=".repeat(50).bright_black());
+                println!();
+                println!("  {} A: {}", "[*]".bright_blue(), a.bright_blue());
+                println!("  {} B: {}", "[*]".bright_blue(), b.bright_blue());
+                println!();
+
+                println!(
+                    "  {} Only in A: {}",
+                    "[-]".bright_red(),
+                    diff.only_a.len().to_string().bright_yellow().bold()
+                );
+                for domain in &diff.only_a {
+                    println!("    {} {}", "-".bright_red(), domain);
+                }
+                println!(
+                    "  {} Only in B: {}",
+                    "[+]".bright_green(),
+                    diff.only_b.len().to_string().bright_yellow().bold()
+                );
+                for domain in &diff.only_b {
+                    println!("    {} {}", "+".bright_green(), domain);
+                }
+                println!(
+                    "  {} Common: {}",
+                    "[=]".bright_white(),
+                    diff.common.to_string().bright_yellow().bold()
+                );
+
+                if let Some(output) = output {
+                    let mut content = String::new();
+                    for domain in &diff.only_a {
+                        content.push_str(&format!("-{}\n", domain));
+                    }
+                    for domain in &diff.only_b {
+                        content.push_str(&format!("+{}\n", domain));
+                    }
+                    fs::write(output, content)
+                        .with_context(|| format!("Failed to write diff: {}", output))?;
+                    println!();
                     println!(
-                        "  {} Status: {}",
-                        "[x]".bright_red(),
-                        "BLOCKED".bright_red().bold()
+                        "  {} Diff written to: {}",
+                        "[ok]".bright_green(),
+                        output.bright_blue()
                     );
+                }
+
+                println!();
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Normalize { file, dry_run }) => {
+                let content = fs::read_to_string(file)
+                    .with_context(|| format!("Failed to read blocklist: {}", file))?;
+                let (normalized, report) = crate::loader::normalize_list(&content);
+
+                if *dry_run {
+                    print!("{}", normalized);
+                    eprintln!("{} {} (dry run)", "[i]".bright_blue(), report);
+                    return Ok(ExitCode::SUCCESS);
+                }
+                if normalized != content {
+                    crate::loader::write_atomically(std::path::Path::new(file), &normalized)
+                        .with_context(|| format!("Failed to write blocklist: {}", file))?;
+                }
+                println!(
+                    "  {} Normalized {}: {}",
+                    "[ok]".bright_green(),
+                    file.bright_blue(),
+                    report
+                );
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::FlushCache {
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let socket = std::path::Path::new(&config.server.control_socket);
+                let Some(reply) = crate::control::request(socket, "flush_cache").await? else {
                     println!(
-                        "  {} This domain will be blocked by the DNS server",
-                        "[i]".bright_blue()
+                        "  {} No server listening on {}",
+                        "[x]".bright_red().bold(),
+                        socket.display().to_string().bright_blue()
                     );
+                    return Ok(ExitCode::from(EXIT_ERROR));
+                };
+                let evicted = reply.strip_suffix(" entries").unwrap_or(&reply);
+                println!(
+                    "  {} Flushed {} cached answers",
+                    "[ok]".bright_green(),
+                    evicted.bright_yellow()
+                );
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Watch {
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                let socket = std::path::Path::new(&config.server.control_socket);
+                let Some(mut events) = crate::control::watch(socket).await? else {
                     println!(
-                        "  {} DNS queries will receive: {}",
-                        "->".bright_white(),
-                        "REFUSED".bright_yellow()
+                        "  {} No server listening on {}",
+                        "[x]".bright_red().bold(),
+                        socket.display().to_string().bright_blue()
                     );
+                    return Ok(ExitCode::from(EXIT_ERROR));
+                };
+                println!(
+                    "{} {}",
+                    "Watching blocked queries".bright_cyan().bold(),
+                    "(Ctrl-C to stop)".bright_black()
+                );
+                println!("{}", "=".repeat(50).bright_black());
+
+                while let Some(line) = events.next_line().await? {
+                    match line.parse::<crate::webhook::BlockEvent>() {
+                        Ok(event) => println!(
+                            "{}  {}  {}  {}",
+                            event.timestamp.bright_black(),
+                            event.client_ip.to_string().bright_blue(),
+                            event.domain.bright_red(),
+                            event.rule.bright_black()
+                        ),
+                        Err(_) => println!("{}", line),
+                    }
+                }
+                println!("  {} Server closed the connection", "[i]".bright_blue());
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::Analyze { logfile, top }) => {
+                let file = fs::File::open(logfile)
+                    .with_context(|| format!("Failed to read log: {}", logfile))?;
+                let summary = crate::logger::analyze_log(std::io::BufReader::new(file))
+                    .with_context(|| format!("Failed to read log: {}", logfile))?;
+
+                println!("{}", "Log Analysis".bright_cyan().bold());
+                println!("{}", "=".repeat(50).bright_black());
+                println!();
+                println!("  {} Log: {}", "[*]".bright_blue(), logfile.bright_blue());
+                println!();
+                println!(
+                    "  {} Blocked: {}",
+                    "[x]".bright_red(),
+                    summary.blocked.to_string().bright_yellow().bold()
+                );
+                println!(
+                    "  {} Allowed: {}{}",
+                    "[ok]".bright_green(),
+                    summary.allowed.to_string().bright_yellow().bold(),
+                    if summary.sampled {
+                        " (estimated from sampled lines)"
+                    } else {
+                        ""
+                    }
+                );
+                println!(
+                    "  {} Block rate: {}",
+                    "[i]".bright_blue(),
+                    format!("{:.1}%", summary.block_rate())
+                        .bright_yellow()
+                        .bold()
+                );
+
+                let top_blocked = summary.top_blocked(*top);
+                if !top_blocked.is_empty() {
+                    println!();
+                    println!("  {}", "Top blocked domains:".bright_white().bold());
+                    for (domain, count) in top_blocked {
+                        println!("    {:>8}  {}", count.to_string().bright_yellow(), domain);
+                    }
+                }
+
+                println!();
+                Ok(ExitCode::SUCCESS)
+            }
+            Some(Commands::PingUpstream {
+                domain,
+                config: config_path,
+            }) => {
+                let config = Config::load_or_default(config_path)?;
+                println!("{}", "Upstream Latency".bright_cyan().bold());
+                println!("{}", "=".repeat(50).bright_black());
+                println!();
+                println!(
+                    "  {} Querying {} (A)",
+                    "[*]".bright_blue(),
+                    domain.bright_yellow()
+                );
+                println!();
+
+                let server = DnsServer::new(config, Arc::new(BlocklistManager::new()))?;
+                let pings = server.ping_upstreams(domain).await?;
+                let mut failed = 0;
+                for ping in &pings {
+                    match &ping.result {
+                        Ok((latency, rcode)) => println!(
+                            "  {} {}  {}  {}",
+                            "[ok]".bright_green(),
+                            ping.upstream.bright_blue(),
+                            format!("{:.1} ms", latency.as_secs_f64() * 1000.0).bright_yellow(),
+                            rcode.to_string().bright_black()
+                        ),
+                        Err(e) => {
+                            failed += 1;
+                            println!(
+                                "  {} {}  {}",
+                                "[x]".bright_red(),
+                                ping.upstream.bright_blue(),
+                                e.bright_red()
+                            );
+                        }
+                    }
+                }
+
+                println!();
+                if failed > 0 {
+                    Ok(ExitCode::from(EXIT_ERROR))
                 } else {
+                    Ok(ExitCode::SUCCESS)
+                }
+            }
+            Some(Commands::CheckSource { url }) => {
+                println!("{}", "Source Check".bright_cyan().bold());
+                println!("{}", "=".repeat(50).bright_black());
+                println!();
+                println!("  {} URL: {}", "[*]".bright_blue(), url.bright_blue());
+
+                let downloader = BlocklistDownloader::new()?;
+                let (_, report) = downloader.download_with_report(url).await?;
+
+                if report.status != 0 {
                     println!(
-                        "  {} Status: {}",
+                        "  {} HTTP status: {}",
                         "[ok]".bright_green(),
-                        "ALLOWED".bright_green().bold()
-                    );
-                    println!(
-                        "  {} This domain will be resolved normally",
-                        "[i]".bright_blue()
-                    );
-                    println!(
-                        "  {} DNS queries will be forwarded to upstream: {}",
-                        "->".bright_white(),
-                        format_upstream_list(&config.server.upstream_dns).bright_cyan()
+                        report.status.to_string().bright_green()
                     );
+                } else {
+                    println!("  {} Read local file", "[ok]".bright_green());
                 }
+                println!(
+                    "  {} Format: {}",
+                    "[*]".bright_cyan(),
+                    report
+                        .detected_format()
+                        .map(|format| format.to_string())
+                        .unwrap_or_else(|| "unknown (no rules)".to_string())
+                        .bright_yellow()
+                );
+                println!(
+                    "  {} Lines: {} ({} comments/blank)",
+                    "[*]".bright_cyan(),
+                    report.lines.to_string().bright_yellow(),
+                    report.comments
+                );
+                println!(
+                    "  {} Valid rules: {} ({} entries)",
+                    "[ok]".bright_green(),
+                    report.valid.to_string().bright_green().bold(),
+                    report.entries
+                );
+                let skipped = report.skipped.to_string();
+                println!(
+                    "  {} Skipped: {}",
+                    if report.skipped > 0 {
+                        "[!]".bright_yellow()
+                    } else {
+                        "[ok]".bright_green()
+                    },
+                    if report.skipped > 0 {
+                        skipped.bright_yellow()
+                    } else {
+                        skipped.bright_green()
+                    }
+                );
 
                 println!();
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             }
             None => {
                 // Default action: show banner and help
@@ -832,8 +2342,645 @@ impl Cli {
                 );
                 println!();
 
-                Ok(())
+                Ok(ExitCode::SUCCESS)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level_for(args: &[&str]) -> Option<&'static str> {
+        let mut argv = vec!["skypier-blackhole"];
+        argv.extend_from_slice(args);
+        Cli::try_parse_from(argv).unwrap().log_level()
+    }
+
+    #[test]
+    fn test_verbosity_flags_map_to_log_levels() {
+        assert_eq!(level_for(&["status"]), None);
+        assert_eq!(level_for(&["-v", "status"]), Some("info"));
+        assert_eq!(level_for(&["-vv", "status"]), Some("debug"));
+        assert_eq!(level_for(&["status", "-vvv"]), Some("trace"));
+        assert_eq!(level_for(&["-vvvv", "status"]), Some("trace"));
+        assert_eq!(level_for(&["--quiet", "status"]), Some("error"));
+        assert_eq!(level_for(&["status", "-q"]), Some("error"));
+    }
+
+    #[test]
+    fn test_stop_escalates_to_sigkill_after_timeout() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let stop = |script: &str, grace: Duration| {
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", script])
+                .spawn()
+                .unwrap();
+            // Let the shell install its trap
+            std::thread::sleep(Duration::from_millis(200));
+            let started = std::time::Instant::now();
+            // try_wait reaps the child, so it doesn't linger as a zombie
+            let outcome =
+                stop_server(child.id(), grace, || child.try_wait().unwrap().is_none()).unwrap();
+            let status = child.wait().unwrap();
+            (outcome, started.elapsed(), status)
+        };
+
+        let (outcome, elapsed, _) = stop("sleep 30", Duration::from_secs(5));
+        assert_eq!(outcome, StopOutcome::Stopped);
+        assert!(elapsed < Duration::from_secs(5));
+
+        // Ignores SIGTERM, like a wedged server
+        let grace = Duration::from_millis(500);
+        let (outcome, elapsed, status) = stop("trap '' TERM; while :; do sleep 0.1; done", grace);
+        assert_eq!(outcome, StopOutcome::Killed);
+        assert!(elapsed >= grace);
+        assert_eq!(status.signal(), Some(SIGKILL));
+    }
+
+    #[tokio::test]
+    async fn test_test_command_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        fs::write(&config.blocklist.custom_list, "ads.example.com\n").unwrap();
+        let config_path = dir.path().join("blackhole.toml").display().to_string();
+        config.save(&config_path).unwrap();
+
+        let exit_code = |domain: &str| {
+            let cli =
+                Cli::try_parse_from(["skypier-blackhole", "test", domain, "-c", &config_path])
+                    .unwrap();
+            async move { cli.execute().await.unwrap() }
+        };
+        assert_eq!(
+            exit_code("ads.example.com").await,
+            ExitCode::from(EXIT_BLOCKED)
+        );
+        assert_eq!(exit_code("example.org").await, ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn test_update_without_server_writes_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("remote.txt");
+        fs::write(&list, "0.0.0.0 ads.example.com\ntracker.example.net\n").unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        config.blocklist.remote_lists = vec![format!("file://{}", list.display()).into()];
+        config.server.pid_file = dir.path().join("blackhole.pid").display().to_string();
+        config.server.control_socket = dir.path().join("ctl.sock").display().to_string();
+        let config_path = dir.path().join("blackhole.toml").display().to_string();
+        config.save(&config_path).unwrap();
+
+        let cli = Cli::try_parse_from(["skypier-blackhole", "update", "-c", &config_path]).unwrap();
+        assert_eq!(cli.execute().await.unwrap(), ExitCode::SUCCESS);
+
+        // What `start` would load
+        let blocklist = BlocklistManager::new();
+        crate::loader::load_blocklist(&config, &blocklist)
+            .await
+            .unwrap();
+        assert!(blocklist.is_blocked("ads.example.com").await);
+        assert!(blocklist.is_blocked("tracker.example.net").await);
+    }
+
+    #[tokio::test]
+    async fn test_count_only_prints_bare_verdicts() {
+        let cli = Cli::try_parse_from([
+            "skypier-blackhole",
+            "test",
+            "--count-only",
+            "ads.example.com",
+            "example.org",
+        ])
+        .unwrap();
+        assert_eq!(cli.log_level(), Some("error"));
+
+        let blocklist = BlocklistManager::new();
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let domains = ["ads.example.com", "example.org"].map(String::from);
+        let mut out = Vec::new();
+        assert!(write_verdicts(&blocklist, &domains, &mut out)
+            .await
+            .unwrap());
+        assert_eq!(String::from_utf8(out).unwrap(), "blocked\nallowed\n");
+    }
+
+    #[tokio::test]
+    async fn test_reload_failing_check_keeps_previous_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        config.blocklist.reload_check.must_block = vec!["sentinel.example.com".to_string()];
+        config.blocklist.reload_check.must_allow = vec!["example.org".to_string()];
+        config.blocklist.reload_check.keep_previous = true;
+
+        fs::write(
+            &config.blocklist.custom_list,
+            "sentinel.example.com\nads.example.com\n",
+        )
+        .unwrap();
+        let blocklist = BlocklistManager::new();
+        let sources = SourceCache::default();
+        assert_eq!(
+            reload_blocklist(&config, &blocklist, &sources)
+                .await
+                .unwrap(),
+            2
+        );
+
+        // The sentinel went missing and example.org got blocked
+        fs::write(
+            &config.blocklist.custom_list,
+            "*.example.org\nexample.org\n",
+        )
+        .unwrap();
+        let err = format!(
+            "{:#}",
+            reload_blocklist(&config, &blocklist, &sources)
+                .await
+                .unwrap_err()
+        );
+        assert!(err.contains("sentinel.example.com is not blocked"), "{err}");
+        assert!(err.contains("example.org is blocked"), "{err}");
+        assert!(blocklist.is_blocked("sentinel.example.com").await);
+        assert!(!blocklist.is_blocked("example.org").await);
+
+        // Without keep_previous the new lists go live anyway
+        config.blocklist.reload_check.keep_previous = false;
+        assert_eq!(
+            reload_blocklist(&config, &blocklist, &sources)
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(blocklist.is_blocked("example.org").await);
+    }
+
+    #[tokio::test]
+    async fn test_flush_cache_empties_stale_answers() {
+        use hickory_proto::op::{Message, MessageType, Query};
+        use hickory_proto::rr::{Name, RData, Record, RecordType};
+        use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+        use std::str::FromStr;
+
+        // Upstream answering every A query
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = upstream.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_queries(request.queries().to_vec());
+                response.add_answer(Record::from_rdata(
+                    request.queries()[0].name().clone(),
+                    300,
+                    RData::A(std::net::Ipv4Addr::new(192, 0, 2, 1).into()),
+                ));
+                let _ = upstream.send_to(&response.to_bytes().unwrap(), src).await;
             }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream_addr)];
+        config.server.serve_stale = Some(3600);
+        config.server.control_socket = dir.path().join("ctl.sock").display().to_string();
+        let config_path = dir.path().join("blackhole.toml").display().to_string();
+        config.save(&config_path).unwrap();
+
+        let blocklist = Arc::new(BlocklistManager::new());
+        let server = DnsServer::new(config.clone(), Arc::clone(&blocklist)).unwrap();
+        let socket = crate::control::ControlSocket::bind(std::path::Path::new(
+            &config.server.control_socket,
+        ))
+        .unwrap();
+        let (serving, sources) = (server.clone(), Arc::new(SourceCache::default()));
+        let task =
+            tokio::spawn(
+                socket.serve(
+                    move |request| {
+                        let (config, blocklist) = (config.clone(), Arc::clone(&blocklist));
+                        let (server, sources) = (serving.clone(), Arc::clone(&sources));
+                        async move {
+                            answer_control(&request, &config, &blocklist, &sources, &server).await
+                        }
+                    },
+                    |_| async { anyhow::bail!("no methods") },
+                ),
+            );
+
+        let client: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        for name in ["example.com.", "example.org."] {
+            let mut query = Message::new();
+            query.set_recursion_desired(true);
+            query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+            let response = server.answer(query, client).await;
+            assert_eq!(response.answers().len(), 1);
         }
+        assert_eq!(server.metrics().cached_answers(), 2);
+
+        let cli =
+            Cli::try_parse_from(["skypier-blackhole", "flush-cache", "-c", &config_path]).unwrap();
+        assert_eq!(cli.execute().await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(server.metrics().cached_answers(), 0);
+        // Nothing left to evict
+        assert_eq!(server.flush_cache(), 0);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_reload_wait_reports_new_domain_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        config.server.control_socket = dir.path().join("ctl.sock").display().to_string();
+        fs::write(&config.blocklist.custom_list, "ads.example.com\n").unwrap();
+
+        let wait = Duration::from_secs(5);
+        assert!(reload_and_wait(&config, wait).await.is_err());
+
+        let socket = crate::control::ControlSocket::bind(std::path::Path::new(
+            &config.server.control_socket,
+        ))
+        .unwrap();
+        let server_config = config.clone();
+        let blocklist = Arc::new(BlocklistManager::new());
+        let serving = Arc::clone(&blocklist);
+        let server = DnsServer::new(config.clone(), Arc::clone(&blocklist)).unwrap();
+        let sources = Arc::new(SourceCache::default());
+        let task =
+            tokio::spawn(
+                socket.serve(
+                    move |request| {
+                        let config = server_config.clone();
+                        let blocklist = Arc::clone(&serving);
+                        let server = server.clone();
+                        let sources = Arc::clone(&sources);
+                        async move {
+                            answer_control(&request, &config, &blocklist, &sources, &server).await
+                        }
+                    },
+                    |_| async { anyhow::bail!("no methods") },
+                ),
+            );
+
+        assert_eq!(reload_and_wait(&config, wait).await.unwrap(), 1);
+        fs::write(
+            &config.blocklist.custom_list,
+            "ads.example.com\ntracker.example.net\n*.metrics.example.org\n",
+        )
+        .unwrap();
+        assert_eq!(reload_and_wait(&config, wait).await.unwrap(), 3);
+        // The count is sent once the new lists are live
+        assert!(blocklist.is_blocked("tracker.example.net").await);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_json_rpc_control_methods() {
+        use serde_json::{json, Value};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        config.server.control_socket = dir.path().join("ctl.sock").display().to_string();
+        config.server.serve_stale = Some(3600);
+        fs::write(&config.blocklist.custom_list, "ads.example.com\n").unwrap();
+        let path = std::path::PathBuf::from(&config.server.control_socket);
+
+        let socket = crate::control::ControlSocket::bind(&path).unwrap();
+        let blocklist = Arc::new(BlocklistManager::new());
+        let server = DnsServer::new(config.clone(), Arc::clone(&blocklist)).unwrap();
+        let sources = Arc::new(SourceCache::default());
+        let (server_config, serving) = (config.clone(), Arc::clone(&blocklist));
+        let task = tokio::spawn(socket.serve(
+            |_| async { anyhow::bail!("no commands") },
+            move |method| {
+                let config = server_config.clone();
+                let blocklist = Arc::clone(&serving);
+                let sources = Arc::clone(&sources);
+                let server = server.clone();
+                async move { call_control(method, &config, &blocklist, &sources, &server).await }
+            },
+        ));
+        let call = |method: &'static str, params: Value| {
+            let path = path.clone();
+            async move {
+                crate::control::call(&path, method, params)
+                    .await
+                    .map(Option::unwrap)
+            }
+        };
+
+        assert_eq!(
+            call("reload", Value::Null).await.unwrap(),
+            json!({ "domains": 1 })
+        );
+        let stats = call("stats", Value::Null).await.unwrap();
+        assert_eq!(stats["domains"], 1);
+        assert_eq!(stats["queries"], 0);
+        assert_eq!(
+            call("flush_cache", Value::Null).await.unwrap(),
+            json!({ "flushed": 0 })
+        );
+
+        let tracker = json!({ "domain": "tracker.example.net" });
+        assert_eq!(
+            call("add", tracker.clone()).await.unwrap(),
+            json!({ "added": true, "domains": 2 })
+        );
+        assert!(blocklist.is_blocked("tracker.example.net").await);
+        assert_eq!(call("add", tracker.clone()).await.unwrap()["added"], false);
+        assert_eq!(
+            call("list", Value::Null).await.unwrap(),
+            json!(["ads.example.com", "tracker.example.net"])
+        );
+        assert_eq!(
+            call("remove", tracker.clone()).await.unwrap(),
+            json!({ "removed": true, "domains": 1 })
+        );
+        assert!(!blocklist.is_blocked("tracker.example.net").await);
+        assert_eq!(call("remove", tracker).await.unwrap()["removed"], false);
+
+        // Bad calls get JSON-RPC errors
+        let err = call("add", json!({ "domain": "https://x.com/" })).await;
+        assert!(format!("{:#}", err.unwrap_err()).contains("-32000"));
+        let err = call("add", Value::Null).await.unwrap_err();
+        assert!(err.to_string().contains("-32602"));
+        let err = call("bogus", Value::Null).await.unwrap_err();
+        assert!(err.to_string().contains("-32601"));
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_prune_flags_only_nxdomain_entries() {
+        use hickory_proto::op::{Message, MessageType, ResponseCode};
+        use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+        // Upstream: gone.example.com doesn't exist, flaky.example.com fails
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let name = request.queries()[0].name().to_utf8();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_response_code(match name.as_str() {
+                        "gone.example.com." => ResponseCode::NXDomain,
+                        "flaky.example.com." => ResponseCode::ServFail,
+                        _ => ResponseCode::NoError,
+                    })
+                    .add_queries(request.queries().to_vec());
+                socket
+                    .send_to(&response.to_bytes().unwrap(), src)
+                    .await
+                    .unwrap();
+            }
+        });
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let domains: Vec<String> = ["ads.example.com", "gone.example.com", "flaky.example.com"]
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            find_dead_domains(&server, &domains).await,
+            vec!["gone.example.com".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repl_answers_each_line() {
+        let blocklist = BlocklistManager::new();
+        blocklist
+            .load_domains(vec![
+                "ads.example.com".to_string(),
+                "*.tracker.net".to_string(),
+            ])
+            .await
+            .unwrap();
+
+        let input = "ads.example.com\n\nexample.org\npixel.tracker.net\nquit\nnever.example\n";
+        let mut output = Vec::new();
+        run_repl(&blocklist, std::io::Cursor::new(input), &mut output)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let answers: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("BLOCKED") || line.contains("ALLOWED"))
+            .collect();
+
+        assert_eq!(answers.len(), 3);
+        assert!(answers[0].contains("ads.example.com") && answers[0].contains("BLOCKED"));
+        assert!(answers[1].contains("example.org") && answers[1].contains("ALLOWED"));
+        assert!(answers[2].contains("pixel.tracker.net") && answers[2].contains("*.tracker.net"));
+        assert!(!output.contains("never.example"));
+    }
+
+    #[tokio::test]
+    async fn test_list_show_domains_prints_loaded_rules() {
+        let blocklist = BlocklistManager::new();
+        blocklist
+            .load_domains(vec![
+                "tracker.example.com".to_string(),
+                "*.doubleclick.net".to_string(),
+                "ads.example.com".to_string(),
+                "ADS.example.com".to_string(),
+            ])
+            .await
+            .unwrap();
+        let domains = blocklist.all_domains().await;
+
+        let mut output = Vec::new();
+        write_domains(&domains, None, &mut output).unwrap();
+        let printed: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect();
+        assert_eq!(
+            printed,
+            [
+                "*.doubleclick.net",
+                "ads.example.com",
+                "tracker.example.com"
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_domains(&domains, Some(1), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().next().unwrap().trim(), "*.doubleclick.net");
+        assert!(output.contains("... and 2 more"));
+    }
+
+    #[test]
+    fn test_listen_override() {
+        let cli = Cli::try_parse_from(["skypier-blackhole", "start", "--listen", "0.0.0.0:5353"])
+            .unwrap();
+        let Some(Commands::Start {
+            listen: Some(listen),
+            ..
+        }) = cli.command
+        else {
+            panic!("expected start with --listen");
+        };
+
+        let mut config = Config::default();
+        config.set_listen(listen);
+        assert_eq!(config.server.listen_addr, "0.0.0.0");
+        assert_eq!(config.server.listen_port, 5353);
+    }
+
+    #[test]
+    fn test_sink_override() {
+        let cli = Cli::try_parse_from(["skypier-blackhole", "start", "--sink", "0.0.0.0"]).unwrap();
+        let Some(Commands::Start {
+            sink: Some(sink), ..
+        }) = cli.command
+        else {
+            panic!("expected start with --sink");
+        };
+
+        let mut config = Config::default();
+        config.set_sink(sink);
+        assert!(matches!(
+            config.server.blocked_response,
+            crate::config::BlockedResponse::Ip(ip) if ip == IpAddr::from([0, 0, 0, 0])
+        ));
+        assert!(Cli::try_parse_from(["skypier-blackhole", "start", "--sink", "nowhere"]).is_err());
+    }
+
+    #[test]
+    fn test_listen_override_rejects_malformed_value() {
+        for bad in ["0.0.0.0", "localhost:53", "0.0.0.0:99999", "::1:53"] {
+            let err = Cli::try_parse_from(["skypier-blackhole", "start", "--listen", bad])
+                .err()
+                .unwrap_or_else(|| panic!("'{bad}' should be rejected"));
+            assert!(err.to_string().contains("ADDR:PORT"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_block_duration_parsing() {
+        let cli = Cli::try_parse_from(["skypier-blackhole", "block", "videos.com", "--for", "2h"])
+            .unwrap();
+        let Some(Commands::Block { duration, .. }) = cli.command else {
+            panic!("expected block");
+        };
+        assert_eq!(duration, Duration::from_secs(7200));
+        assert_eq!(parse_block_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_block_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(
+            parse_block_duration("7d"),
+            Ok(Duration::from_secs(7 * 86400))
+        );
+        for bad in [
+            "",
+            "2",
+            "h",
+            "0m",
+            "2w",
+            "-1h",
+            "1.5h",
+            "8d",
+            "18446744073709551615s",
+        ] {
+            assert!(
+                parse_block_duration(bad).is_err(),
+                "'{bad}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_from_stdin_appends_each_domain_once() {
+        let input = "ads.example.com\n\n# trackers\n*.tracker.com\n  ads.example.com  \n";
+        let domains = read_domain_lines(std::io::Cursor::new(input)).unwrap();
+        assert_eq!(domains, vec!["ads.example.com", "*.tracker.com"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.blocklist.custom_list = dir.path().join("custom.txt").display().to_string();
+        crate::loader::append_custom_domains(&config, &domains).unwrap();
+
+        let content = fs::read_to_string(&config.blocklist.custom_list).unwrap();
+        assert_eq!(content, "ads.example.com\n*.tracker.com\n");
+    }
+
+    #[tokio::test]
+    async fn test_sighup_burst_reloads_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reloads);
+        let task = tokio::spawn(handle_signals(rx, Duration::from_millis(100), move || {
+            let counter = Arc::clone(&counter);
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+
+        for _ in 0..5 {
+            tx.unbounded_send(SIGHUP).unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(reloads.load(Ordering::SeqCst), 0);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+
+        tx.unbounded_send(SIGTERM).unwrap();
+        task.await.unwrap();
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_category_command_parses() {
+        let cli = Cli::try_parse_from([
+            "skypier-blackhole",
+            "category",
+            "disable",
+            "social",
+            "-c",
+            "/tmp/b.toml",
+        ])
+        .unwrap();
+        let Some(Commands::Category {
+            action: CategoryAction::Disable { name },
+            config,
+        }) = cli.command
+        else {
+            panic!("expected category disable");
+        };
+        assert_eq!(name, "social");
+        assert_eq!(config, "/tmp/b.toml");
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        assert!(Cli::try_parse_from(["skypier-blackhole", "-q", "-v", "status"]).is_err());
     }
 }
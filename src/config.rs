@@ -1,6 +1,7 @@
 use crate::Result;
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::fs;
 use std::io::{self, IsTerminal, Write};
@@ -9,6 +10,7 @@ use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub server: ServerConfig,
     pub blocklist: BlocklistConfig,
@@ -17,6 +19,7 @@ pub struct Config {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     /// Listen address for DNS server
     #[serde(default = "default_listen_addr")]
@@ -33,6 +36,315 @@ pub struct ServerConfig {
     /// Response to return for blocked domains
     #[serde(default = "default_blocked_response")]
     pub blocked_response: BlockedResponse,
+
+    /// Per-query-type overrides of `blocked_response`, keyed by record type
+    /// name (e.g. `A`, `HTTPS`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub blocked_response_by_type: BTreeMap<String, BlockedResponse>,
+
+    /// Permit a sink IP (`{ ip = "..." }` responses) outside the loopback,
+    /// private and link-local ranges; a public one is usually a typo
+    #[serde(default)]
+    pub allow_public_sink: bool,
+
+    /// Address of a block page server: blocked A/AAAA queries are answered
+    /// with it (whatever `blocked_response` says) so browsers land on a page
+    /// explaining the block. `start` warns if it does not accept
+    /// connections on `block_page_port`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_page_ip: Option<IpAddr>,
+
+    /// Port of the block page server, checked by `start` (443 for a page
+    /// served over HTTPS)
+    #[serde(default = "default_block_page_port")]
+    pub block_page_port: u16,
+
+    /// Lower bound (seconds) for TTLs in forwarded answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ttl: Option<u32>,
+
+    /// Upper bound (seconds) for TTLs in forwarded answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ttl: Option<u32>,
+
+    /// What to do with a client's EDNS Client Subnet option when forwarding
+    #[serde(default)]
+    pub ecs_policy: EcsPolicy,
+
+    /// What to do when an upstream answers a different question (name,
+    /// type or class) than it was asked
+    #[serde(default)]
+    pub on_question_mismatch: QuestionMismatch,
+
+    /// Maximum number of queries handled at once; further packets wait
+    #[serde(default = "default_max_concurrent_queries")]
+    pub max_concurrent_queries: usize,
+
+    /// Attach a TXT record explaining the block to blocked responses
+    #[serde(default)]
+    pub blocked_explain: bool,
+
+    /// Answer blocked queries as an authoritative server (AA bit, plus RA
+    /// and the query's RD) for stubs that distrust non-authoritative blocks
+    #[serde(default)]
+    pub blocked_authoritative: bool,
+
+    /// Answer ANY queries with a minimal HINFO record (RFC 8482) instead of
+    /// forwarding them, so the resolver is useless for ANY amplification
+    #[serde(default)]
+    pub block_any_queries: bool,
+
+    /// Answer REFUSED to queries that don't set RD (recursion desired)
+    /// instead of resolving them upstream; blocked names, and answers still
+    /// fresh in the `serve_stale` cache, are still served
+    #[serde(default)]
+    pub honor_rd: bool,
+
+    /// Answer the major search engines and YouTube with a CNAME to their
+    /// enforced safe-search endpoints
+    #[serde(default)]
+    pub safe_search: bool,
+
+    /// Also accept DNS-over-HTTPS queries (`POST /dns-query`) on this
+    /// address, as plain HTTP for a TLS-terminating proxy in front
+    #[serde(default)]
+    pub doh_listen: Option<SocketAddr>,
+
+    /// Proxies in front of `doh_listen` whose `X-Forwarded-For` header is
+    /// believed: their queries count as coming from the forwarded address,
+    /// for `client_upstreams`, per-client policy and client stats. Without
+    /// this, every DoH query looks like it came from the proxy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub doh_trusted_proxies: Vec<IpRange>,
+
+    /// Resolve test names through the pipeline before serving
+    #[serde(default)]
+    pub self_test: SelfTestConfig,
+
+    /// A/AAAA answers inside these networks are stripped from forwarded
+    /// responses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_ip_ranges: Vec<IpRange>,
+
+    /// Answer NXDOMAIN for `use-application-dns.net` so browsers leave
+    /// DNS-over-HTTPS off and keep using this resolver
+    #[serde(default = "default_true")]
+    pub doh_canary: bool,
+
+    /// Compress repeated names in responses so large answer sets fit the
+    /// UDP budget
+    #[serde(default = "default_true")]
+    pub compress_responses: bool,
+
+    /// Drop the additional section (glue and other unsolicited records,
+    /// keeping EDNS) from forwarded responses
+    #[serde(default)]
+    pub strip_additional: bool,
+
+    /// Largest UDP response sent as is; bigger ones go out truncated (TC
+    /// set, records dropped) so the client retries over TCP. Unset uses the
+    /// client's EDNS payload size, or 512 bytes without EDNS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_udp_response_size: Option<u16>,
+
+    /// When every upstream attempt fails, answer from the last good reply
+    /// if it expired at most this many seconds ago (served with a 30s TTL).
+    /// Unset disables serving stale answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serve_stale: Option<u32>,
+
+    /// Most answers kept for `serve_stale`; the least recently used one is
+    /// dropped when a new answer comes in
+    #[serde(default = "default_cache_max_entries")]
+    pub cache_max_entries: usize,
+
+    /// POST a JSON event here when a domain is blocked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+
+    /// Only report blocks of these domains (and their subdomains) to the
+    /// webhook; required with `webhook_url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhook_domains: Vec<String>,
+
+    /// Where `start` records its PID, read by `stop`, `reload` and `status`
+    #[serde(default = "default_pid_file")]
+    pub pid_file: String,
+
+    /// Plain resolver (`ip:port`) used only to look up DoH upstreams given
+    /// by hostname, once at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootstrap_dns: Option<SocketAddr>,
+
+    /// Unix socket `start` listens on for `reload`; owner-only permissions
+    #[serde(default = "default_control_socket")]
+    pub control_socket: String,
+
+    /// Send DNS Cookies (RFC 7873) to upstreams and drop answers whose
+    /// cookie doesn't match the one sent
+    #[serde(default)]
+    pub upstream_cookies: bool,
+
+    /// Randomize the case of the query name sent upstream (DNS 0x20) and
+    /// drop answers that don't echo it exactly
+    #[serde(default)]
+    pub randomize_case: bool,
+
+    /// Upstreams for particular clients; the first entry whose `clients`
+    /// contain the query's source address wins, others use `upstream_dns`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub client_upstreams: Vec<ClientUpstreams>,
+}
+
+/// Clients (addresses or CIDR ranges) routed to their own upstreams
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientUpstreams {
+    pub clients: Vec<IpRange>,
+    pub upstream_dns: Vec<Upstream>,
+}
+
+/// Startup self-test run by `DnsServer::start` before it serves queries
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SelfTestConfig {
+    /// Run the self-test at startup
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Domain that must resolve through the upstream
+    #[serde(default = "default_control_domain")]
+    pub control_domain: String,
+
+    /// Domain that must be blocked by the loaded lists (skipped if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentinel_domain: Option<String>,
+
+    /// Refuse to start if the self-test fails (otherwise just warn)
+    #[serde(default)]
+    pub fail_closed: bool,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        SelfTestConfig {
+            enabled: false,
+            control_domain: default_control_domain(),
+            sentinel_domain: None,
+            fail_closed: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The socket address to listen on. Built from the parsed IP and port
+    /// rather than by joining strings, so IPv6 literals (`::1`, or `[::1]`)
+    /// work.
+    pub fn listen_socket_addr(&self) -> Result<SocketAddr> {
+        let ip = self
+            .listen_addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .with_context(|| {
+                format!(
+                    "Invalid listen_addr '{}': expected an IP address",
+                    self.listen_addr
+                )
+            })?;
+        Ok(SocketAddr::new(ip, self.listen_port))
+    }
+
+    /// The listen address for display, with IPv6 literals bracketed
+    /// (`[::1]:53`)
+    pub fn listen_display(&self) -> String {
+        match self.listen_socket_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => format!("{}:{}", self.listen_addr, self.listen_port),
+        }
+    }
+
+    /// The blocked response for a query type: its entry in
+    /// `blocked_response_by_type` (case-insensitive), else NODATA for HTTPS
+    /// and SVCB, else `blocked_response`. An empty HTTPS answer lets browsers
+    /// fall straight back to A/AAAA, where REFUSED can make them wait.
+    pub fn blocked_response_for(&self, record_type: &str) -> &BlockedResponse {
+        if let Some((_, response)) = self
+            .blocked_response_by_type
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(record_type))
+        {
+            return response;
+        }
+        if record_type.eq_ignore_ascii_case("HTTPS") || record_type.eq_ignore_ascii_case("SVCB") {
+            return &BlockedResponse::NoData;
+        }
+        &self.blocked_response
+    }
+
+    /// The upstreams serving queries from `client`: those of the first
+    /// `client_upstreams` entry covering it, else `upstream_dns`
+    pub fn upstreams_for(&self, client: Option<IpAddr>) -> &[Upstream] {
+        self.route_for(client).map_or(&self.upstream_dns, |route| {
+            &self.client_upstreams[route].upstream_dns
+        })
+    }
+
+    /// Index of the `client_upstreams` entry serving `client`; None if it
+    /// uses `upstream_dns`
+    pub fn route_for(&self, client: Option<IpAddr>) -> Option<usize> {
+        let ip = client?;
+        self.client_upstreams
+            .iter()
+            .position(|route| route.clients.iter().any(|range| range.contains(ip)))
+    }
+
+    /// Every configured upstream: `upstream_dns`, then the per-client ones
+    pub fn all_upstreams(&self) -> impl Iterator<Item = &Upstream> {
+        self.upstream_dns.iter().chain(
+            self.client_upstreams
+                .iter()
+                .flat_map(|route| &route.upstream_dns),
+        )
+    }
+}
+
+/// Handling of the EDNS Client Subnet (RFC 7871) option on forwarded queries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EcsPolicy {
+    /// Never send client subnet information upstream
+    #[default]
+    Strip,
+    /// Forward the client's subnet option as received
+    Passthrough,
+}
+
+/// Handling of upstream answers for another question than was asked, a
+/// sign of spoofing or a broken upstream; the answer itself is never used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuestionMismatch {
+    /// Ask the other upstreams in turn, SERVFAIL if none answers properly
+    #[default]
+    Retry,
+    /// Answer SERVFAIL right away
+    Servfail,
+}
+
+/// Rotation of the log files (`log_rotation`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    /// A new file each day, named `<log_path>.YYYY-MM-DD`
+    #[default]
+    Daily,
+    /// A new file each hour, named `<log_path>.YYYY-MM-DD-HH`
+    Hourly,
+    /// Move the file to `<log_path>.1` once it reaches `log_max_size_mb`
+    Size,
+    /// A single file that is never rotated (e.g. left to logrotate)
+    Never,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -42,12 +354,40 @@ pub enum BlockedResponse {
     Refused,
     /// Return NXDOMAIN (domain doesn't exist)
     NxDomain,
+    /// Return NOERROR with no answers (name exists, no records of this type)
+    NoData,
     /// Return a specific IP address (e.g., 0.0.0.0)
     Ip(IpAddr),
 }
 
+/// Whether a sink IP stays on the local network: unspecified, loopback,
+/// private (RFC 1918, shared RFC 6598, IPv6 unique local) or link-local
+fn is_local_sink(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || (a == 100 && b & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_unspecified()
+                || ip.is_loopback()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|v4| is_local_sink(v4.into()))
+        }
+    }
+}
+
 /// An upstream resolver, either plain UDP (`1.1.1.1:53`) or DNS over HTTPS
-/// (`https://dns.quad9.net/dns-query@9.9.9.9:443`).
+/// (`https://dns.quad9.net/dns-query@9.9.9.9:443`, or without the `@`
+/// address when `bootstrap_dns` is set).
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(try_from = "String", into = "String")]
 pub enum Upstream {
@@ -55,6 +395,9 @@ pub enum Upstream {
     Udp(SocketAddr),
     /// DNS over HTTPS: socket address to connect to and TLS server name
     DoH { addr: SocketAddr, dns_name: String },
+    /// DNS over HTTPS by hostname only; the address is looked up through
+    /// `bootstrap_dns` when the server starts
+    DoHHost { dns_name: String, port: u16 },
 }
 
 /// The only endpoint path supported by hickory 0.24 (hardcoded upstream).
@@ -104,10 +447,12 @@ impl FromStr for Upstream {
             }
             None => match host.parse::<IpAddr>() {
                 Ok(ip) => SocketAddr::new(ip, host_port.unwrap_or(DOH_DEFAULT_PORT)),
-                Err(_) => anyhow::bail!(
-                    "Invalid DoH upstream '{s}': hostname requires a bootstrap address, \
-                     e.g. 'https://{host}{DOH_QUERY_PATH}@9.9.9.9:443'"
-                ),
+                Err(_) => {
+                    return Ok(Upstream::DoHHost {
+                        dns_name: host,
+                        port: host_port.unwrap_or(DOH_DEFAULT_PORT),
+                    })
+                }
             },
         };
 
@@ -184,19 +529,296 @@ impl fmt::Display for Upstream {
                     write!(f, "https://{dns_name}{DOH_QUERY_PATH}@{addr}")
                 }
             }
+            Upstream::DoHHost { dns_name, port } if *port == DOH_DEFAULT_PORT => {
+                write!(f, "https://{dns_name}{DOH_QUERY_PATH}")
+            }
+            Upstream::DoHHost { dns_name, port } => {
+                write!(f, "https://{dns_name}:{port}{DOH_QUERY_PATH}")
+            }
         }
     }
 }
 
+/// An IP network in CIDR notation (`10.0.0.0/8`, `2001:db8::/32`); a bare
+/// address is a single-host network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Whether `ip` falls inside this network (never across address families)
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid IP range '{s}': expected e.g. 10.0.0.0/8"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .with_context(|| {
+                    format!("Invalid IP range '{s}': prefix length must be 0-{max_len}")
+                })?,
+            None => max_len,
+        };
+        Ok(IpRange { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// A `remote_lists` / `local_lists` entry. Written either as a plain URL or
+/// path string, or as a table that can switch the source off without
+/// deleting it or tag it with a category: `{ url = "https://...",
+/// enabled = false }` or `{ path = "/etc/...", category = "social" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "ListSourceRepr", into = "ListSourceRepr")]
+pub struct ListSource {
+    /// URL (remote lists) or file path (local lists)
+    pub location: String,
+    pub enabled: bool,
+    /// Category the source's entries are tagged with (`ads`, `social`...),
+    /// which `category disable` can switch off at runtime
+    pub category: Option<String>,
+}
+
+/// On-disk forms of `ListSource`
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ListSourceRepr {
+    Plain(String),
+    Url(UrlSource),
+    Path(PathSource),
+}
+
+/// `{ url = "..." }` form
+#[derive(Serialize)]
+struct UrlSource {
+    url: String,
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+}
+
+/// `{ path = "..." }` form
+#[derive(Serialize)]
+struct PathSource {
+    path: String,
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+}
+
+/// Either table form as read. Parsed through this strict struct rather
+/// than the untagged enum, so a misspelled key is reported by name instead
+/// of as "did not match any variant".
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceTable {
+    url: Option<String>,
+    path: Option<String>,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    category: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for ListSourceRepr {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ReprVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ReprVisitor {
+            type Value = ListSourceRepr;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a URL or path, or a table with `url` or `path`")
+            }
+
+            fn visit_str<E: serde::de::Error>(
+                self,
+                value: &str,
+            ) -> std::result::Result<Self::Value, E> {
+                Ok(ListSourceRepr::Plain(value.to_string()))
+            }
+
+            fn visit_map<A>(self, map: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                use serde::de::Error;
+
+                let SourceTable {
+                    url,
+                    path,
+                    enabled,
+                    category,
+                } = SourceTable::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
+                match (url, path) {
+                    (Some(url), None) => Ok(ListSourceRepr::Url(UrlSource {
+                        url,
+                        enabled,
+                        category,
+                    })),
+                    (None, Some(path)) => Ok(ListSourceRepr::Path(PathSource {
+                        path,
+                        enabled,
+                        category,
+                    })),
+                    (Some(_), Some(_)) => Err(A::Error::custom(
+                        "a list source has either `url` or `path`, not both",
+                    )),
+                    (None, None) => Err(A::Error::custom("a list source needs `url` or `path`")),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(ReprVisitor)
+    }
+}
+
+impl From<ListSourceRepr> for ListSource {
+    fn from(repr: ListSourceRepr) -> Self {
+        match repr {
+            ListSourceRepr::Plain(location) => ListSource::from(location),
+            ListSourceRepr::Url(UrlSource {
+                url,
+                enabled,
+                category,
+            }) => ListSource {
+                location: url,
+                enabled,
+                category,
+            },
+            ListSourceRepr::Path(PathSource {
+                path,
+                enabled,
+                category,
+            }) => ListSource {
+                location: path,
+                enabled,
+                category,
+            },
+        }
+    }
+}
+
+impl From<ListSource> for ListSourceRepr {
+    fn from(source: ListSource) -> Self {
+        // Enabled, untagged sources keep the plain string form
+        if source.enabled && source.category.is_none() {
+            ListSourceRepr::Plain(source.location)
+        } else if source.location.starts_with("http://") || source.location.starts_with("https://")
+        {
+            ListSourceRepr::Url(UrlSource {
+                url: source.location,
+                enabled: source.enabled,
+                category: source.category,
+            })
+        } else {
+            ListSourceRepr::Path(PathSource {
+                path: source.location,
+                enabled: source.enabled,
+                category: source.category,
+            })
+        }
+    }
+}
+
+impl From<String> for ListSource {
+    fn from(location: String) -> Self {
+        ListSource {
+            location,
+            enabled: true,
+            category: None,
+        }
+    }
+}
+
+impl From<&str> for ListSource {
+    fn from(location: &str) -> Self {
+        ListSource::from(location.to_string())
+    }
+}
+
+/// Locations of the enabled sources in a list
+fn enabled_locations(sources: &[ListSource]) -> Vec<String> {
+    sources
+        .iter()
+        .filter(|source| source.enabled)
+        .map(|source| source.location.clone())
+        .collect()
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct BlocklistConfig {
     /// Remote URLs to download blocklists from
     #[serde(default)]
-    pub remote_lists: Vec<String>,
+    pub remote_lists: Vec<ListSource>,
 
     /// Local blocklist file paths
     #[serde(default)]
-    pub local_lists: Vec<String>,
+    pub local_lists: Vec<ListSource>,
+
+    /// Local blocklist files in suffix mode: each entry blocks itself and
+    /// all of its subdomains, without needing `*.` rules
+    #[serde(default)]
+    pub suffix_lists: Vec<String>,
+
+    /// Hosts-format files (e.g. `/etc/hosts`) whose sinkholed entries
+    /// (mapped to 0.0.0.0 or 127.0.0.1) are blocked; real mappings are ignored
+    #[serde(default)]
+    pub hosts_files: Vec<String>,
 
     /// Path to custom blocklist file
     #[serde(default = "default_custom_list")]
@@ -205,9 +827,99 @@ pub struct BlocklistConfig {
     /// Enable wildcard domain matching
     #[serde(default = "default_true")]
     pub enable_wildcards: bool,
+
+    /// Deepest parent (in labels) wildcard, suffix and allow rules are
+    /// looked up for; regexes see only that many trailing labels. Bounds
+    /// the matching work of absurdly deep query names.
+    #[serde(default = "default_max_match_labels")]
+    pub max_match_labels: usize,
+
+    /// Keep a compiled binary index of the merged blocklist next to the
+    /// custom list and load it at startup instead of re-parsing the sources
+    #[serde(default)]
+    pub use_index: bool,
+
+    /// Load the cached remote lists (`remote-blocklist-cache*.txt`); off
+    /// leaves only the local sources, e.g. to test the custom list alone
+    #[serde(default = "default_true")]
+    pub use_remote_cache: bool,
+
+    /// Write the remote cache gzip-compressed (`.txt.gz`); compressed
+    /// caches are read either way
+    #[serde(default)]
+    pub compress_cache: bool,
+
+    /// Sanity check of the lists loaded by a reload
+    #[serde(default)]
+    pub reload_check: ReloadCheckConfig,
+}
+
+/// Domains checked after every reload (SIGHUP or control socket) before the
+/// new lists go live
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReloadCheckConfig {
+    /// Domains the reloaded lists must block
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must_block: Vec<String>,
+
+    /// Domains the reloaded lists must not block
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must_allow: Vec<String>,
+
+    /// Keep serving the previous lists when the check fails (otherwise the
+    /// failure is only logged)
+    #[serde(default)]
+    pub keep_previous: bool,
+}
+
+impl BlocklistConfig {
+    /// URLs of the remote lists that are not disabled
+    pub fn enabled_remote_lists(&self) -> Vec<String> {
+        enabled_locations(&self.remote_lists)
+    }
+
+    /// Make relative paths in `remote_lists` (entries without a scheme)
+    /// absolute against `dir`, the config file's directory, so they don't
+    /// depend on where the server was started from
+    fn resolve_remote_paths(&mut self, dir: &Path) {
+        for source in &mut self.remote_lists {
+            if !source.location.contains("://") && Path::new(&source.location).is_relative() {
+                source.location = dir.join(&source.location).display().to_string();
+            }
+        }
+    }
+
+    /// Paths of the local lists that are not disabled
+    pub fn enabled_local_lists(&self) -> Vec<String> {
+        enabled_locations(&self.local_lists)
+    }
+
+    /// URLs of the enabled remote lists grouped by category, untagged
+    /// (`None`) first. Each group is downloaded into its own cache file.
+    pub fn enabled_remote_groups(&self) -> BTreeMap<Option<String>, Vec<String>> {
+        let mut groups: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+        for source in self.remote_lists.iter().filter(|source| source.enabled) {
+            groups
+                .entry(source.category.clone())
+                .or_default()
+                .push(source.location.clone());
+        }
+        groups
+    }
+
+    /// Every category named by a remote or local list
+    pub fn categories(&self) -> BTreeSet<String> {
+        self.remote_lists
+            .iter()
+            .chain(&self.local_lists)
+            .filter_map(|source| source.category.clone())
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct LoggingConfig {
     /// Enable logging of blocked queries
     #[serde(default = "default_true")]
@@ -217,12 +929,35 @@ pub struct LoggingConfig {
     #[serde(default = "default_log_path")]
     pub log_path: String,
 
+    /// Separate file for the `[BLOCKED]`/`[ALLOWED]` query lines; they go
+    /// to `log_path` when unset
+    #[serde(default)]
+    pub query_log_path: Option<String>,
+
+    /// When the log files are rotated
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+
+    /// Size a log file grows to before it is rotated (`log_rotation = "size"`)
+    #[serde(default = "default_log_max_size_mb")]
+    pub log_max_size_mb: u64,
+
+    /// Files kept per log, the current one included
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: usize,
+
     /// Log level (trace, debug, info, warn, error)
     #[serde(default = "default_log_level")]
     pub log_level: String,
+
+    /// Share of allowed queries that get an `[ALLOWED]` line (0.0-1.0),
+    /// tagged `sample_rate=` for `analyze`; blocked queries are always logged
+    #[serde(default = "default_query_log_sample_rate")]
+    pub query_log_sample_rate: f64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct UpdaterConfig {
     /// Enable automatic updates
     #[serde(default = "default_true")]
@@ -239,6 +974,11 @@ pub struct UpdaterConfig {
     /// Refresh remote blocklists once at daemon startup (in the background)
     #[serde(default = "default_true")]
     pub update_on_start: bool,
+
+    /// Keep the old cache when a download has fewer domains than this
+    /// fraction of it (a truncated or empty upstream answer); 0 disables
+    #[serde(default = "default_min_update_ratio")]
+    pub min_update_ratio: f64,
 }
 
 // Default value functions
@@ -250,6 +990,10 @@ fn default_listen_port() -> u16 {
     53
 }
 
+fn default_block_page_port() -> u16 {
+    80
+}
+
 fn default_upstream_dns() -> Vec<Upstream> {
     vec!["1.1.1.1:53".parse().expect("valid default upstream")]
 }
@@ -258,10 +1002,26 @@ fn default_blocked_response() -> BlockedResponse {
     BlockedResponse::Refused
 }
 
+fn default_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_max_concurrent_queries() -> usize {
+    1024
+}
+
 fn default_custom_list() -> String {
     get_default_custom_list_path()
 }
 
+fn default_control_domain() -> String {
+    "example.com".to_string()
+}
+
+fn default_max_match_labels() -> usize {
+    crate::blocklist::MAX_LABELS
+}
+
 fn default_true() -> bool {
     true
 }
@@ -270,6 +1030,14 @@ fn default_log_path() -> String {
     get_default_log_path()
 }
 
+fn default_pid_file() -> String {
+    get_default_pid_file_path()
+}
+
+fn default_control_socket() -> String {
+    get_default_control_socket_path()
+}
+
 // Platform-specific default paths
 
 #[cfg(target_os = "linux")]
@@ -318,10 +1086,68 @@ fn get_default_log_path() -> String {
     "blackhole.log".to_string()
 }
 
+#[cfg(target_os = "linux")]
+fn get_default_pid_file_path() -> String {
+    "/run/skypier/blackhole.pid".to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn get_default_pid_file_path() -> String {
+    "/usr/local/var/run/skypier/blackhole.pid".to_string()
+}
+
+#[cfg(target_os = "windows")]
+fn get_default_pid_file_path() -> String {
+    format!(
+        "{}\\Skypier\\blackhole.pid",
+        std::env::var("PROGRAMDATA").unwrap_or_else(|_| "C:\\ProgramData".to_string())
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn get_default_pid_file_path() -> String {
+    "blackhole.pid".to_string()
+}
+
+#[cfg(target_os = "linux")]
+fn get_default_control_socket_path() -> String {
+    "/run/skypier/control.sock".to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn get_default_control_socket_path() -> String {
+    "/usr/local/var/run/skypier/control.sock".to_string()
+}
+
+#[cfg(target_os = "windows")]
+fn get_default_control_socket_path() -> String {
+    format!(
+        "{}\\Skypier\\control.sock",
+        std::env::var("PROGRAMDATA").unwrap_or_else(|_| "C:\\ProgramData".to_string())
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn get_default_control_socket_path() -> String {
+    "control.sock".to_string()
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_query_log_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_log_max_size_mb() -> u64 {
+    100
+}
+
+fn default_log_max_files() -> usize {
+    7
+}
+
 fn default_update_schedule() -> String {
     "0 0 0 * * *".to_string() // Daily at midnight (sec min hour dom month dow)
 }
@@ -330,6 +1156,10 @@ fn default_timezone() -> String {
     "EST".to_string()
 }
 
+fn default_min_update_ratio() -> f64 {
+    0.5
+}
+
 /// Get the default configuration file path for the current platform
 #[cfg(target_os = "linux")]
 pub fn get_default_config_path() -> String {
@@ -354,17 +1184,265 @@ pub fn get_default_config_path() -> String {
     "blackhole.toml".to_string()
 }
 
+/// Prefix of the environment variables that override config values
+const ENV_PREFIX: &str = "SKYPIER_";
+
+/// Parse a comma-separated env var into its non-empty, trimmed items
+fn split_env_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 impl Config {
-    /// Load configuration from file
+    /// Load configuration from file, then apply `SKYPIER_*` environment
+    /// overrides (precedence: env > file > defaults)
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
         let content = fs::read_to_string(path)
             .with_context(|| format!("Failed to read config file: {}", path.display()))?;
-        let config: Config = toml::from_str(&content)
+        let mut config: Config = toml::from_str(&content)
             .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.blocklist.resolve_remote_paths(dir);
+        }
+        config.apply_env_overrides()?;
+        config.validate()?;
         Ok(config)
     }
 
+    /// Load configuration from `path`, or run on `Config::default()` (with a
+    /// warning) when `path` is the platform default and doesn't exist yet, so
+    /// a fresh install works without a config file. Any other missing path,
+    /// i.e. one given with `--config`, is an error.
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let is_default = path == Path::new(&get_default_config_path());
+        Self::load_allowing_missing(path, is_default)
+    }
+
+    fn load_allowing_missing(path: &Path, allow_missing: bool) -> Result<Self> {
+        if allow_missing && !path.exists() {
+            tracing::warn!(
+                path = %path.display(),
+                "No config file found, running with built-in defaults"
+            );
+            let mut config = Config::default();
+            config.apply_env_overrides()?;
+            config.validate()?;
+            return Ok(config);
+        }
+        Self::load(path)
+    }
+
+    /// Reject settings that cannot work together. The files kept next to
+    /// the custom list (remote caches, index, ...) have fixed names, so a
+    /// `custom_list` using one of those names would be read or overwritten
+    /// as that file.
+    pub fn validate(&self) -> Result<()> {
+        let custom_list = Path::new(&self.blocklist.custom_list);
+        if let Some(reserved) = crate::loader::reserved_paths(self)
+            .into_iter()
+            .find(|path| path.as_path() == custom_list)
+        {
+            anyhow::bail!(
+                "custom_list {} collides with a file skypier-blackhole manages itself ({}); \
+                 rename the custom list",
+                custom_list.display(),
+                reserved.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+        let rate = self.logging.query_log_sample_rate;
+        if !(0.0..=1.0).contains(&rate) {
+            anyhow::bail!("query_log_sample_rate must be between 0.0 and 1.0, got {rate}");
+        }
+        if !self.server.allow_public_sink {
+            let public = std::iter::once(&self.server.blocked_response)
+                .chain(self.server.blocked_response_by_type.values())
+                .find_map(|response| match response {
+                    BlockedResponse::Ip(ip) if !is_local_sink(*ip) => Some(*ip),
+                    _ => None,
+                })
+                .or(self.server.block_page_ip.filter(|ip| !is_local_sink(*ip)));
+            if let Some(ip) = public {
+                anyhow::bail!(
+                    "Sink IP {ip} is a public address; blocked domains would resolve to \
+                     someone else's host. Use a local address, or set allow_public_sink = true"
+                );
+            }
+        }
+        let labels = self.blocklist.max_match_labels;
+        if !(1..=crate::blocklist::MAX_LABELS).contains(&labels) {
+            anyhow::bail!(
+                "max_match_labels must be between 1 and {}, got {labels}",
+                crate::blocklist::MAX_LABELS
+            );
+        }
+        if self.server.webhook_url.is_some() && self.server.webhook_domains.is_empty() {
+            anyhow::bail!(
+                "webhook_url needs webhook_domains: the domains whose blocks are reported"
+            );
+        }
+        if self.server.block_page_port == 0 {
+            anyhow::bail!("block_page_port must be a port number, not 0");
+        }
+        if let (Some(min), Some(max)) = (self.server.min_ttl, self.server.max_ttl) {
+            if min > max {
+                anyhow::bail!("min_ttl ({min}s) must not be greater than max_ttl ({max}s)");
+            }
+        }
+        if self.server.cache_max_entries == 0 {
+            anyhow::bail!(
+                "cache_max_entries must be at least 1; leave serve_stale unset to keep no answers"
+            );
+        }
+        if self.logging.log_max_files == 0 || self.logging.log_max_size_mb == 0 {
+            anyhow::bail!("log_max_files and log_max_size_mb must be at least 1");
+        }
+        if let Some(route) = self
+            .server
+            .client_upstreams
+            .iter()
+            .find(|route| route.clients.is_empty() || route.upstream_dns.is_empty())
+        {
+            anyhow::bail!(
+                "client_upstreams entries need both clients and upstream_dns, got clients {:?}",
+                route
+                    .clients
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+            );
+        }
+        if self.server.bootstrap_dns.is_none() {
+            if let Some(Upstream::DoHHost { dns_name, .. }) = self
+                .server
+                .all_upstreams()
+                .find(|upstream| matches!(upstream, Upstream::DoHHost { .. }))
+            {
+                anyhow::bail!(
+                    "DoH upstream {dns_name} is a hostname: set bootstrap_dns to a plain resolver \
+                     or add a bootstrap address, e.g. 'https://{dns_name}{DOH_QUERY_PATH}@9.9.9.9:443'"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Override the listen address and port (e.g. from `start --listen`)
+    pub fn set_listen(&mut self, addr: SocketAddr) {
+        self.server.listen_addr = addr.ip().to_string();
+        self.server.listen_port = addr.port();
+    }
+
+    /// Answer blocked queries with this IP instead of the configured
+    /// `blocked_response` (e.g. from `start --sink`)
+    pub fn set_sink(&mut self, ip: IpAddr) {
+        self.server.blocked_response = BlockedResponse::Ip(ip);
+    }
+
+    /// Override config values from `SKYPIER_*` environment variables, e.g.
+    /// `SKYPIER_LISTEN_PORT=5353` or `SKYPIER_UPSTREAM_DNS=9.9.9.9:53,1.1.1.1:53`.
+    /// List values are comma-separated. Malformed values are an error.
+    pub fn apply_env_overrides(&mut self) -> Result<()> {
+        self.apply_overrides_from(|key| std::env::var(key).ok())
+    }
+
+    /// `apply_env_overrides` with an injectable variable lookup
+    fn apply_overrides_from(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let get = |name: &str| {
+            let key = format!("{ENV_PREFIX}{name}");
+            var(&key).map(|value| (key, value))
+        };
+        let parse_bool = |key: &str, value: &str| -> Result<bool> {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" => Ok(false),
+                _ => anyhow::bail!("Invalid {key}='{value}': expected true or false"),
+            }
+        };
+
+        // [server]
+        if let Some((_, value)) = get("LISTEN_ADDR") {
+            self.server.listen_addr = value;
+        }
+        if let Some((key, value)) = get("LISTEN_PORT") {
+            self.server.listen_port = value
+                .parse()
+                .with_context(|| format!("Invalid {key}='{value}'"))?;
+        }
+        if let Some((key, value)) = get("UPSTREAM_DNS") {
+            self.server.upstream_dns = split_env_list(&value)
+                .iter()
+                .map(|upstream| upstream.parse())
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid {key}='{value}'"))?;
+        }
+        if let Some((key, value)) = get("BLOCKED_RESPONSE") {
+            self.server.blocked_response = match value.to_lowercase().as_str() {
+                "refused" => BlockedResponse::Refused,
+                "nxdomain" => BlockedResponse::NxDomain,
+                "nodata" => BlockedResponse::NoData,
+                ip => BlockedResponse::Ip(ip.parse().with_context(|| {
+                    format!("Invalid {key}='{value}': expected refused, nxdomain, nodata or an IP")
+                })?),
+            };
+        }
+
+        // [blocklist]
+        if let Some((_, value)) = get("REMOTE_LISTS") {
+            self.blocklist.remote_lists = split_env_list(&value)
+                .into_iter()
+                .map(ListSource::from)
+                .collect();
+        }
+        if let Some((_, value)) = get("LOCAL_LISTS") {
+            self.blocklist.local_lists = split_env_list(&value)
+                .into_iter()
+                .map(ListSource::from)
+                .collect();
+        }
+        if let Some((_, value)) = get("CUSTOM_LIST") {
+            self.blocklist.custom_list = value;
+        }
+        if let Some((key, value)) = get("ENABLE_WILDCARDS") {
+            self.blocklist.enable_wildcards = parse_bool(&key, &value)?;
+        }
+        if let Some((key, value)) = get("USE_REMOTE_CACHE") {
+            self.blocklist.use_remote_cache = parse_bool(&key, &value)?;
+        }
+
+        // [logging]
+        if let Some((key, value)) = get("LOG_BLOCKED") {
+            self.logging.log_blocked = parse_bool(&key, &value)?;
+        }
+        if let Some((_, value)) = get("LOG_PATH") {
+            self.logging.log_path = value;
+        }
+        if let Some((_, value)) = get("LOG_LEVEL") {
+            self.logging.log_level = value;
+        }
+
+        // [updater]
+        if let Some((key, value)) = get("UPDATER_ENABLED") {
+            self.updater.enabled = parse_bool(&key, &value)?;
+        }
+        if let Some((_, value)) = get("UPDATE_SCHEDULE") {
+            self.updater.schedule = value;
+        }
+        if let Some((_, value)) = get("TIMEZONE") {
+            self.updater.timezone = value;
+        }
+        if let Some((key, value)) = get("UPDATE_ON_START") {
+            self.updater.update_on_start = parse_bool(&key, &value)?;
+        }
+
+        Ok(())
+    }
+
     /// Save configuration to file
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref();
@@ -380,7 +1458,7 @@ impl Config {
     /// If the file is missing and stdin is an interactive terminal, prompts
     /// the user to write out `Config::default()` at `path` (creating parent
     /// directories as needed) before loading. Non-interactive sessions and
-    /// declined prompts fall through to the plain `load` error.
+    /// declined prompts fall through to `load_or_default`.
     pub fn load_or_prompt_default<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
 
@@ -406,7 +1484,7 @@ impl Config {
             }
         }
 
-        Self::load(path)
+        Self::load_or_default(path)
     }
 }
 
@@ -419,23 +1497,68 @@ impl Default for Config {
                 listen_port: default_listen_port(),
                 upstream_dns: default_upstream_dns(),
                 blocked_response: default_blocked_response(),
+                min_ttl: None,
+                max_ttl: None,
+                blocked_response_by_type: BTreeMap::new(),
+                ecs_policy: EcsPolicy::default(),
+                on_question_mismatch: QuestionMismatch::default(),
+                max_concurrent_queries: default_max_concurrent_queries(),
+                blocked_explain: false,
+                blocked_authoritative: false,
+                block_any_queries: false,
+                doh_listen: None,
+                doh_trusted_proxies: vec![],
+                allow_public_sink: false,
+                block_page_ip: None,
+                block_page_port: default_block_page_port(),
+                honor_rd: false,
+                safe_search: false,
+                self_test: SelfTestConfig::default(),
+                blocked_ip_ranges: vec![],
+                doh_canary: true,
+                compress_responses: true,
+                strip_additional: false,
+                max_udp_response_size: None,
+                serve_stale: None,
+                cache_max_entries: default_cache_max_entries(),
+                webhook_url: None,
+                webhook_domains: vec![],
+                pid_file: default_pid_file(),
+                bootstrap_dns: None,
+                control_socket: default_control_socket(),
+                upstream_cookies: false,
+                randomize_case: false,
+                client_upstreams: vec![],
             },
             blocklist: BlocklistConfig {
                 remote_lists: vec![],
                 local_lists: vec![],
+                suffix_lists: vec![],
+                hosts_files: vec![],
                 custom_list: default_custom_list(),
                 enable_wildcards: true,
+                max_match_labels: default_max_match_labels(),
+                use_index: false,
+                use_remote_cache: true,
+                compress_cache: false,
+                reload_check: ReloadCheckConfig::default(),
             },
             logging: LoggingConfig {
                 log_blocked: true,
                 log_path: default_log_path(),
+                query_log_path: None,
+                log_rotation: LogRotation::default(),
+                log_max_size_mb: default_log_max_size_mb(),
+                log_max_files: default_log_max_files(),
                 log_level: default_log_level(),
+                query_log_sample_rate: default_query_log_sample_rate(),
             },
             updater: UpdaterConfig {
                 enabled: true,
                 schedule: default_update_schedule(),
                 timezone: default_timezone(),
                 update_on_start: true,
+                min_update_ratio: default_min_update_ratio(),
             },
         }
     }
@@ -445,6 +1568,20 @@ impl Default for Config {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unknown_config_key_is_an_error() {
+        let example = include_str!("../config/blackhole.toml.example");
+        let config: Config = toml::from_str(example).expect("example config parses");
+        assert_eq!(config.server.listen_port, 53);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.toml");
+        fs::write(&path, example.replace("listen_port", "listen_prot")).unwrap();
+        let err = format!("{:#}", Config::load(&path).unwrap_err());
+        assert!(err.contains("unknown field `listen_prot`"), "{err}");
+        assert!(err.contains("listen_port"), "{err}");
+    }
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
@@ -457,6 +1594,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_env_overrides_file_values() {
+        // As if read from a file
+        let mut config = Config::default();
+        config.server.listen_addr = "127.0.0.2".to_string();
+        config.logging.log_level = "warn".to_string();
+        let vars: std::collections::HashMap<&str, &str> = [
+            ("SKYPIER_LISTEN_PORT", "5353"),
+            (
+                "SKYPIER_UPSTREAM_DNS",
+                "9.9.9.9:53, https://1.1.1.1/dns-query",
+            ),
+            ("SKYPIER_LOG_LEVEL", "debug"),
+        ]
+        .into_iter()
+        .collect();
+        config
+            .apply_overrides_from(|key| vars.get(key).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(config.server.listen_port, 5353);
+        assert_eq!(
+            config.server.upstream_dns,
+            vec![
+                "9.9.9.9:53".parse().unwrap(),
+                "https://1.1.1.1/dns-query".parse().unwrap(),
+            ]
+        );
+        assert_eq!(config.logging.log_level, "debug");
+        // Untouched values keep the file's setting
+        assert_eq!(config.server.listen_addr, "127.0.0.2");
+    }
+
+    #[test]
+    fn test_missing_default_config_falls_back_to_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.toml");
+
+        let config = Config::load_allowing_missing(&path, true).unwrap();
+        assert_eq!(config.server.listen_addr, default_listen_addr());
+        assert_eq!(
+            config.blocklist.custom_list,
+            Config::default().blocklist.custom_list
+        );
+        assert!(!path.exists(), "the fallback must not write a file");
+
+        // A path given explicitly must exist
+        let err = Config::load_allowing_missing(&path, false).unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to read config file"));
+    }
+
+    #[test]
+    fn test_env_overrides_lookup() {
+        let vars: std::collections::HashMap<&str, &str> = [
+            ("SKYPIER_LISTEN_ADDR", "0.0.0.0"),
+            ("SKYPIER_BLOCKED_RESPONSE", "0.0.0.0"),
+            ("SKYPIER_LOCAL_LISTS", "/a.txt,,/b.txt"),
+            ("SKYPIER_UPDATER_ENABLED", "off"),
+        ]
+        .into_iter()
+        .collect();
+        let mut config = Config::default();
+        config
+            .apply_overrides_from(|key| vars.get(key).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(config.server.listen_addr, "0.0.0.0");
+        assert!(matches!(
+            config.server.blocked_response,
+            BlockedResponse::Ip(ip) if ip == IpAddr::from([0, 0, 0, 0])
+        ));
+        assert_eq!(
+            config.blocklist.enabled_local_lists(),
+            vec!["/a.txt", "/b.txt"]
+        );
+        assert!(!config.updater.enabled);
+    }
+
+    #[test]
+    fn test_env_override_rejects_malformed_value() {
+        let mut config = Config::default();
+        let err = config
+            .apply_overrides_from(|key| (key == "SKYPIER_LISTEN_PORT").then(|| "high".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("SKYPIER_LISTEN_PORT"));
+    }
+
     #[test]
     fn test_upstream_parse_udp() {
         let upstream: Upstream = "9.9.9.9:53".parse().unwrap();
@@ -533,12 +1757,100 @@ mod tests {
         assert!(err.to_string().contains("/dns-query"));
     }
 
+    #[test]
+    fn test_block_page_port() {
+        let server: ServerConfig = toml::from_str("block_page_ip = \"192.168.1.10\"").unwrap();
+        assert_eq!(server.block_page_port, 80);
+        let server: ServerConfig =
+            toml::from_str("block_page_ip = \"192.168.1.10\"\nblock_page_port = 443").unwrap();
+        assert_eq!(server.block_page_port, 443);
+
+        let mut config = Config::default();
+        config.server.block_page_port = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("block_page_port"), "{err}");
+    }
+
+    #[test]
+    fn test_min_ttl_above_max_ttl_is_rejected() {
+        let mut config = Config::default();
+        config.server.min_ttl = Some(600);
+        config.server.max_ttl = Some(60);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("min_ttl (600s)"), "{err}");
+
+        config.server.max_ttl = Some(600);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_zero_cache_max_entries_is_rejected() {
+        let mut config = Config::default();
+        config.server.cache_max_entries = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("cache_max_entries"), "{err}");
+    }
+
+    #[test]
+    fn test_webhook_url_needs_domains() {
+        let mut config = Config::default();
+        config.server.webhook_url = Some("https://alerts.example.com/hook".to_string());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("webhook_domains"), "{err}");
+
+        config.server.webhook_domains = vec!["malware.example".to_string()];
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_public_sink_needs_allow_public_sink() {
+        let mut config = Config::default();
+        for local in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "192.168.1.10",
+            "100.64.0.1",
+            "::",
+            "fd00::10",
+        ] {
+            config.set_sink(local.parse().unwrap());
+            config.validate().unwrap();
+        }
+
+        config.set_sink("203.0.113.7".parse().unwrap());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("allow_public_sink"), "{err}");
+        // Per-type sinks are checked too
+        config.server.blocked_response = BlockedResponse::Refused;
+        config.server.blocked_response_by_type.insert(
+            "AAAA".into(),
+            BlockedResponse::Ip("2001:db8::1".parse().unwrap()),
+        );
+        assert!(config.validate().is_err());
+
+        config.server.allow_public_sink = true;
+        config.validate().unwrap();
+    }
+
     #[test]
     fn test_upstream_rejects_hostname_without_bootstrap() {
-        let err = "https://dns.quad9.net/dns-query"
-            .parse::<Upstream>()
-            .unwrap_err();
+        let upstream: Upstream = "https://dns.quad9.net/dns-query".parse().unwrap();
+        assert_eq!(
+            upstream,
+            Upstream::DoHHost {
+                dns_name: "dns.quad9.net".to_string(),
+                port: 443,
+            }
+        );
+        assert_eq!(upstream.to_string(), "https://dns.quad9.net/dns-query");
+
+        // Only usable with a bootstrap resolver configured
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![upstream];
+        let err = config.validate().unwrap_err();
         assert!(err.to_string().contains("bootstrap"));
+        config.server.bootstrap_dns = Some("9.9.9.9:53".parse().unwrap());
+        config.validate().unwrap();
     }
 
     #[test]
@@ -566,4 +1878,174 @@ mod tests {
         assert!(serialized.contains("https://dns.quad9.net/dns-query@9.9.9.9:443"));
         assert!(serialized.contains("https://1.1.1.1/dns-query"));
     }
+
+    #[test]
+    fn test_listen_socket_addr_ipv6() {
+        let mut server = Config::default().server;
+        server.listen_addr = "::1".to_string();
+        server.listen_port = 53;
+        assert_eq!(
+            server.listen_socket_addr().unwrap(),
+            "[::1]:53".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(server.listen_display(), "[::1]:53");
+
+        server.listen_addr = "[::1]".to_string();
+        assert_eq!(server.listen_display(), "[::1]:53");
+
+        server.listen_addr = "not-an-ip".to_string();
+        assert!(server.listen_socket_addr().is_err());
+    }
+
+    #[test]
+    fn test_ip_range_contains() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        assert!(!range.contains("::ffff:10.1.2.3".parse().unwrap()));
+
+        let host: IpRange = "192.0.2.7".parse().unwrap();
+        assert_eq!(host.to_string(), "192.0.2.7/32");
+        assert!(host.contains("192.0.2.7".parse().unwrap()));
+        assert!(!host.contains("192.0.2.8".parse().unwrap()));
+
+        let v6: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains("2001:db9::1".parse().unwrap()));
+
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("203.0.113.1".parse().unwrap()));
+
+        for bad in ["10.0.0.0/33", "::/129", "nope/8", "10.0.0.0/x"] {
+            assert!(
+                bad.parse::<IpRange>().is_err(),
+                "'{bad}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_sources_accept_plain_and_toggled_forms() {
+        let toml_str = r#"
+            remote_lists = [
+                "https://example.com/ads.txt",
+                { url = "https://example.com/social.txt", enabled = false },
+                { url = "https://example.com/malware.txt" },
+            ]
+            local_lists = ["/a.txt", { path = "/b.txt", enabled = false }]
+        "#;
+        let blocklist: BlocklistConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            blocklist.enabled_remote_lists(),
+            vec![
+                "https://example.com/ads.txt",
+                "https://example.com/malware.txt"
+            ]
+        );
+        assert_eq!(blocklist.enabled_local_lists(), vec!["/a.txt"]);
+
+        let serialized = toml::to_string(&blocklist).unwrap();
+        let reparsed: BlocklistConfig = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.remote_lists, blocklist.remote_lists);
+        assert_eq!(reparsed.local_lists, blocklist.local_lists);
+    }
+
+    #[test]
+    fn test_relative_remote_list_is_relative_to_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.toml");
+        let mut config = Config::default();
+        config.blocklist.remote_lists = vec![
+            "lists/extra.txt".into(),
+            "/srv/lists/other.txt".into(),
+            "https://example.com/ads.txt".into(),
+        ];
+        config.save(&path).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(
+            config.blocklist.enabled_remote_lists(),
+            vec![
+                dir.path().join("lists/extra.txt").display().to_string(),
+                "/srv/lists/other.txt".to_string(),
+                "https://example.com/ads.txt".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_source_typo_is_an_error() {
+        for (toml_str, typo) in [
+            (
+                r#"remote_lists = [{ url = "https://example.com/ads.txt", enabeld = false }]"#,
+                "enabeld",
+            ),
+            (
+                r#"local_lists = [{ path = "/b.txt", catgory = "social" }]"#,
+                "catgory",
+            ),
+        ] {
+            let err = toml::from_str::<BlocklistConfig>(toml_str)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains(&format!("unknown field `{typo}`")), "{err}");
+        }
+        for toml_str in [
+            r#"local_lists = [{ enabled = false }]"#,
+            r#"local_lists = [{ url = "https://example.com/a.txt", path = "/a.txt" }]"#,
+        ] {
+            assert!(
+                toml::from_str::<BlocklistConfig>(toml_str).is_err(),
+                "{toml_str} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_list_colliding_with_cache_is_rejected() {
+        let mut config = Config::default();
+        config.blocklist.custom_list = "/etc/skypier/remote-blocklist-cache.txt".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("collides"), "{err}");
+
+        config.blocklist.custom_list = "/etc/skypier/custom-blocklist.txt".to_string();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_list_sources_with_categories() {
+        let toml_str = r#"
+            remote_lists = [
+                "https://example.com/ads.txt",
+                { url = "https://example.com/social.txt", category = "social" },
+                { url = "https://example.com/more-social.txt", category = "social" },
+            ]
+            local_lists = [{ path = "/malware.txt", category = "malware" }]
+        "#;
+        let blocklist: BlocklistConfig = toml::from_str(toml_str).unwrap();
+
+        assert_eq!(
+            blocklist.categories().into_iter().collect::<Vec<_>>(),
+            vec!["malware", "social"]
+        );
+        let groups: Vec<_> = blocklist.enabled_remote_groups().into_iter().collect();
+        assert_eq!(
+            groups,
+            vec![
+                (None, vec!["https://example.com/ads.txt".to_string()]),
+                (
+                    Some("social".to_string()),
+                    vec![
+                        "https://example.com/social.txt".to_string(),
+                        "https://example.com/more-social.txt".to_string()
+                    ]
+                ),
+            ]
+        );
+
+        let serialized = toml::to_string(&blocklist).unwrap();
+        let reparsed: BlocklistConfig = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.remote_lists, blocklist.remote_lists);
+        assert_eq!(reparsed.local_lists, blocklist.local_lists);
+    }
 }
@@ -0,0 +1,476 @@
+use crate::webhook::BlockEvent;
+use crate::Result;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+
+/// How long a client gets to send its request line before it is dropped,
+/// so a stuck client can't hold the socket
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON-RPC error codes (JSON-RPC 2.0 section 5.1)
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The method itself failed (e.g. the reload did)
+const SERVER_ERROR: i64 = -32000;
+
+/// A JSON-RPC 2.0 request, sent as a single line
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+/// The reply to an `RpcRequest`: `result` on success, `error` otherwise
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcResponse {
+    fn result(id: Value, result: Value) -> Self {
+        RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i64, message: impl Into<String>) -> Self {
+        RpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+/// The JSON-RPC methods, with their params
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Method {
+    /// Reread the lists, like SIGHUP
+    Reload,
+    /// Query counters and the blocklist size
+    Stats,
+    /// Drop the answers kept for `serve_stale`
+    FlushCache,
+    /// Append `{"domain": ...}` to the custom list and reload
+    Add(String),
+    /// Remove `{"domain": ...}` from the custom list and reload
+    Remove(String),
+    /// The custom list's entries
+    List,
+}
+
+impl Method {
+    /// The method named `method`; an error code and message if there is
+    /// no such method or its params don't fit
+    fn parse(method: &str, params: &Value) -> std::result::Result<Method, (i64, String)> {
+        let domain = || {
+            params
+                .get("domain")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    (
+                        INVALID_PARAMS,
+                        format!("{method} needs a \"domain\" string param"),
+                    )
+                })
+        };
+        Ok(match method {
+            "reload" => Method::Reload,
+            "stats" => Method::Stats,
+            "flush_cache" => Method::FlushCache,
+            "add" => Method::Add(domain()?),
+            "remove" => Method::Remove(domain()?),
+            "list" => Method::List,
+            other => return Err((METHOD_NOT_FOUND, format!("unknown method {other:?}"))),
+        })
+    }
+}
+
+/// Answer one JSON-RPC request line, running the method through `call`.
+/// Always returns a response, malformed requests included.
+async fn dispatch<C, CFut>(line: &str, call: &C) -> RpcResponse
+where
+    C: Fn(Method) -> CFut,
+    CFut: Future<Output = Result<Value>>,
+{
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return RpcResponse::error(Value::Null, PARSE_ERROR, e.to_string()),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request = match serde_json::from_value::<RpcRequest>(value) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => return RpcResponse::error(id, INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+        Err(e) => return RpcResponse::error(id, INVALID_REQUEST, e.to_string()),
+    };
+    let method = match Method::parse(&request.method, &request.params) {
+        Ok(method) => method,
+        Err((code, message)) => return RpcResponse::error(request.id, code, message),
+    };
+    match call(method).await {
+        Ok(result) => RpcResponse::result(request.id, result),
+        Err(e) => RpcResponse::error(request.id, SERVER_ERROR, format!("{:#}", e)),
+    }
+}
+
+/// The server's control socket: one request line in (e.g. `reload`), one
+/// response line out (`ok ...` or `error: ...`). The exception is `watch`,
+/// answered with `ok` and then one line per block event until the client
+/// hangs up. A line starting with `{` is a JSON-RPC 2.0 request instead
+/// (see `Method`) and gets a JSON-RPC response line, for UIs that would
+/// rather not parse text. Bound by `start` and removed again when dropped
+/// on shutdown.
+pub(crate) struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    /// Source of the block events streamed to `watch` clients
+    events: Option<broadcast::Sender<BlockEvent>>,
+}
+
+impl ControlSocket {
+    /// Listen at `path`, readable and writable by the owner only. A socket
+    /// file left by a crashed server is replaced.
+    pub fn bind(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(parent)?;
+        match std::fs::remove_file(path) {
+            Ok(()) => tracing::debug!(path = %path.display(), "Removed stale control socket"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        // Bind inside a directory only we can enter, restrict the socket,
+        // then move it into place: it is never reachable with the default
+        // permissions, not even between bind and chmod
+        let name = path
+            .file_name()
+            .context("Control socket path has no file name")?;
+        let staging = parent.join(format!(
+            ".{}.{}",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join(name);
+        let bound = UnixListener::bind(&staged)
+            .map_err(anyhow::Error::from)
+            .and_then(|listener| {
+                std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+                std::fs::rename(&staged, path)?;
+                Ok(listener)
+            });
+        let _ = std::fs::remove_dir_all(&staging);
+        let listener =
+            bound.with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+            listener,
+            events: None,
+        })
+    }
+
+    /// Stream the events sent on `events` to `watch` clients
+    pub fn with_events(mut self, events: broadcast::Sender<BlockEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Answer requests until the task is dropped: text commands through
+    /// `handle`, JSON-RPC methods through `call`. Clients are served one at
+    /// a time, so two reloads never overlap.
+    pub async fn serve<F, Fut, C, CFut>(self, handle: F, call: C)
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<String>>,
+        C: Fn(Method) -> CFut,
+        CFut: Future<Output = Result<Value>>,
+    {
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Control socket accept failed");
+                    continue;
+                }
+            };
+            if let Err(e) = answer(stream, &handle, &call, self.events.as_ref()).await {
+                tracing::debug!(error = %e, "Control request failed");
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to remove control socket");
+        }
+    }
+}
+
+async fn answer<F, Fut, C, CFut>(
+    stream: UnixStream,
+    handle: &F,
+    call: &C,
+    events: Option<&broadcast::Sender<BlockEvent>>,
+) -> Result<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String>>,
+    C: Fn(Method) -> CFut,
+    CFut: Future<Output = Result<Value>>,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, BufReader::new(reader).read_line(&mut line))
+        .await
+        .context("Timed out waiting for the request")??;
+
+    let request = line.trim().to_string();
+    tracing::debug!(request = %request, "Control request");
+    if request.starts_with('{') {
+        let mut response = serde_json::to_string(&dispatch(&request, call).await)?;
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+        return Ok(());
+    }
+    if request == "watch" {
+        let Some(events) = events else {
+            writer
+                .write_all(b"error: this server has no block events to watch\n")
+                .await?;
+            return Ok(());
+        };
+        // Subscribed before the `ok`, so no event after it is missed
+        let events = events.subscribe();
+        writer.write_all(b"ok\n").await?;
+        // Streamed in the background so other requests are still served
+        tokio::spawn(stream_events(writer, events));
+        return Ok(());
+    }
+    let response = match handle(request).await {
+        Ok(reply) if reply.is_empty() => "ok\n".to_string(),
+        Ok(reply) => format!("ok {}\n", reply),
+        Err(e) => format!("error: {:#}\n", e),
+    };
+    writer.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Write one line per block event to a `watch` client until it goes away
+async fn stream_events(mut writer: OwnedWriteHalf, mut events: broadcast::Receiver<BlockEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::debug!(missed, "Watch client fell behind, skipped events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if writer
+            .write_all(format!("{}\n", event).as_bytes())
+            .await
+            .is_err()
+        {
+            tracing::debug!("Watch client disconnected");
+            return;
+        }
+    }
+}
+
+/// Send `request` to the server's control socket and return the text after
+/// `ok`. None if no server is listening there; an `error:` reply is an error.
+pub(crate) async fn request(path: &Path, request: &str) -> Result<Option<String>> {
+    let Some((mut reader, _writer)) = send(path, request).await? else {
+        return Ok(None);
+    };
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    parse_reply(request, &line).map(Some)
+}
+
+/// Call a JSON-RPC `method` on the server's control socket and return its
+/// result. None if no server is listening; an error response is an error.
+/// Only the tests speak JSON-RPC from this side; the CLI uses the text
+/// commands.
+#[cfg(test)]
+pub(crate) async fn call(path: &Path, method: &str, params: Value) -> Result<Option<Value>> {
+    let request = RpcRequest {
+        jsonrpc: "2.0".to_string(),
+        id: Value::from(1),
+        method: method.to_string(),
+        params,
+    };
+    let Some((mut reader, _writer)) = send(path, &serde_json::to_string(&request)?).await? else {
+        return Ok(None);
+    };
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let response: RpcResponse = serde_json::from_str(&line)
+        .with_context(|| format!("Unexpected control socket reply: {:?}", line.trim()))?;
+    match (response.result, response.error) {
+        (_, Some(error)) => {
+            anyhow::bail!(
+                "Server refused {method:?}: {} ({})",
+                error.message,
+                error.code
+            )
+        }
+        (Some(result), None) => Ok(Some(result)),
+        (None, None) => anyhow::bail!("Control socket reply without a result: {:?}", line.trim()),
+    }
+}
+
+/// Subscribe to the server's block events: every line read from the
+/// returned stream is one event (see `BlockEvent`). None if no server is
+/// listening.
+pub(crate) async fn watch(path: &Path) -> Result<Option<Lines<BufReader<OwnedReadHalf>>>> {
+    let Some((mut reader, writer)) = send(path, "watch").await? else {
+        return Ok(None);
+    };
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    parse_reply("watch", &line)?;
+    // The server only writes from here on
+    drop(writer);
+    Ok(Some(reader.lines()))
+}
+
+/// Connect to the control socket and send one request line. None if no
+/// server is listening there.
+async fn send(
+    path: &Path,
+    request: &str,
+) -> Result<Option<(BufReader<OwnedReadHalf>, OwnedWriteHalf)>> {
+    let stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to connect to control socket {}", path.display()))
+        }
+    };
+
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+    Ok(Some((BufReader::new(reader), writer)))
+}
+
+/// The text after `ok` in a reply line; an `error:` reply is an error
+fn parse_reply(request: &str, line: &str) -> Result<String> {
+    let line = line.trim();
+    if let Some(message) = line.strip_prefix("error:") {
+        anyhow::bail!("Server refused {request:?}: {}", message.trim());
+    }
+    match line.strip_prefix("ok") {
+        Some(reply) => Ok(reply.trim().to_string()),
+        None => anyhow::bail!("Unexpected control socket reply: {line:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_reload_round_trip_at_custom_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("ctl.sock");
+        assert_eq!(request(&path, "reload").await.unwrap(), None);
+
+        let socket = ControlSocket::bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the socket is left behind, not the directory it was bound in
+        let entries: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["ctl.sock"]);
+
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reloads);
+        let task = tokio::spawn(socket.serve(
+            move |request| {
+                let counter = Arc::clone(&counter);
+                async move {
+                    match request.as_str() {
+                        "reload" => Ok(format!("{}", counter.fetch_add(1, Ordering::SeqCst) + 1)),
+                        _ => anyhow::bail!("unknown command"),
+                    }
+                }
+            },
+            |_| async { anyhow::bail!("no methods") },
+        ));
+
+        assert_eq!(
+            request(&path, "reload").await.unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(
+            request(&path, "reload").await.unwrap(),
+            Some("2".to_string())
+        );
+        assert!(request(&path, "bogus").await.is_err());
+        assert_eq!(reloads.load(Ordering::SeqCst), 2);
+
+        // Dropping the serving task removes the socket
+        task.abort();
+        let _ = task.await;
+        assert!(!path.exists());
+        assert_eq!(request(&path, "reload").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Result;
+use hickory_proto::op::{Edns, Message};
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// EDNS option code of a DNS cookie
+const COOKIE_OPTION: u16 = 10;
+const CLIENT_COOKIE_LEN: usize = 8;
+/// Allowed server cookie lengths (RFC 7873 section 4)
+const SERVER_COOKIE_LEN: std::ops::RangeInclusive<usize> = 8..=32;
+
+/// DNS Cookies (RFC 7873) on queries to upstreams (`upstream_cookies`):
+/// each upstream gets its own random client cookie, sent along with the
+/// server cookie it last returned. Answers carrying a cookie must echo the
+/// client cookie, so an off-path spoofer who never saw the query can't
+/// forge one. Upstreams that don't do cookies simply never send one back;
+/// once one has, its answers without a cookie are refused, or a spoofer
+/// could just leave the cookie out.
+pub(crate) struct UpstreamCookies {
+    cookies: Mutex<HashMap<String, Cookie>>,
+}
+
+struct Cookie {
+    client: [u8; CLIENT_COOKIE_LEN],
+    /// The upstream's last server cookie, if it sent one
+    server: Option<Vec<u8>>,
+}
+
+impl UpstreamCookies {
+    pub fn new() -> Self {
+        UpstreamCookies {
+            cookies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add the cookie option for `upstream` to `request`
+    pub fn attach(&self, upstream: &str, request: &mut Message) {
+        let mut cookies = self.cookies.lock().unwrap();
+        let cookie = cookies
+            .entry(upstream.to_string())
+            .or_insert_with(|| Cookie {
+                client: rand::random(),
+                server: None,
+            });
+        let mut data = cookie.client.to_vec();
+        data.extend(cookie.server.iter().flatten());
+        request
+            .extensions_mut()
+            .get_or_insert_with(Edns::new)
+            .options_mut()
+            .insert(EdnsOption::Unknown(COOKIE_OPTION, data));
+    }
+
+    /// Check the cookie in `upstream`'s answer and remember its server
+    /// cookie. An answer without a cookie is accepted only while the
+    /// upstream has never sent one; one whose client cookie isn't ours, or
+    /// that is malformed, is an error.
+    pub fn validate(&self, upstream: &str, response: &Message) -> Result<()> {
+        let mut cookies = self.cookies.lock().unwrap();
+        let data = match response
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Cookie))
+        {
+            None => {
+                let known = cookies.get(upstream).is_some_and(|c| c.server.is_some());
+                if known {
+                    anyhow::bail!(
+                        "Answer from {upstream} has no DNS cookie though it sent one before, \
+                         dropping it"
+                    );
+                }
+                return Ok(());
+            }
+            Some(EdnsOption::Unknown(_, data)) => data,
+            Some(other) => anyhow::bail!("Malformed DNS cookie from {upstream}: {other:?}"),
+        };
+
+        let cookie = cookies
+            .get_mut(upstream)
+            .ok_or_else(|| anyhow::anyhow!("Unsolicited DNS cookie from {upstream}"))?;
+        let (client, server) = data.split_at(data.len().min(CLIENT_COOKIE_LEN));
+        if client != cookie.client {
+            anyhow::bail!("DNS cookie from {upstream} doesn't match ours, dropping the answer");
+        }
+        if !server.is_empty() {
+            if !SERVER_COOKIE_LEN.contains(&server.len()) {
+                anyhow::bail!(
+                    "Malformed DNS server cookie from {upstream} ({} bytes)",
+                    server.len()
+                );
+            }
+            cookie.server = Some(server.to_vec());
+        }
+        Ok(())
+    }
+}
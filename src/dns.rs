@@ -1,19 +1,122 @@
-use crate::config::Upstream;
-use crate::{BlocklistManager, Config, Result, RuntimeMetrics};
-use hickory_client::client::{AsyncClient, ClientHandle};
+use crate::config::{EcsPolicy, IpRange, QuestionMismatch, Upstream};
+use crate::cookie::UpstreamCookies;
+use crate::logger::QUERY_LOG_TARGET;
+use crate::policy::{BlockPolicy, BlocklistPolicy, Decision};
+use crate::safe_search::safe_search_target;
+use crate::stale::StaleCache;
+use crate::webhook::{BlockEvent, Webhook};
+use crate::{BlocklistManager, Config, MatchKind, Result, RuntimeMetrics};
+use anyhow::Context;
+use hickory_client::client::AsyncClient;
 use hickory_client::udp::UdpClientStream;
 use hickory_proto::h2::HttpsClientStreamBuilder;
 use hickory_proto::iocompat::AsyncIoTokioAsStd;
-use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::op::{
+    Edns, Header, Message, MessageType, NoopMessageFinalizer, OpCode, Query, ResponseCode,
+};
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use hickory_proto::rr::rdata::{CNAME, HINFO, SOA, TXT};
 use hickory_proto::rr::{Name, RData, Record, RecordType};
-use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable, BinEncoder};
+use hickory_proto::tcp::TcpClientStream;
+use hickory_proto::xfer::{DnsHandle, DnsMultiplexer, DnsRequest, DnsRequestOptions, FirstAnswer};
 use rand::Rng;
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::{Arc, OnceLock};
-use tokio::net::{TcpStream as TokioTcpStream, UdpSocket};
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream as TokioTcpStream, UdpSocket};
+use tokio::sync::{broadcast, Mutex, Semaphore};
+use tracing::Instrument;
+
+/// EDNS UDP payload size advertised to upstreams (the DNS Flag Day 2020 value)
+const UPSTREAM_EDNS_PAYLOAD: u16 = 1232;
+/// UDP response size every client accepts (RFC 1035), the limit without EDNS
+const MIN_UDP_PAYLOAD: u16 = 512;
+
+/// First delay after a failed `recv_from`; doubles per consecutive failure
+const RECV_BACKOFF_BASE: Duration = Duration::from_millis(10);
+/// Longest delay between receive attempts (and between rebind attempts)
+const RECV_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Consecutive receive failures after which the socket is rebound
+const RECV_ERRORS_BEFORE_REBIND: u32 = 10;
+/// How often expired temporary blocks are swept from the blocklist
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// How long clients may cache a NODATA blocked answer (SOA TTL and MINIMUM)
+const BLOCKED_NEGATIVE_TTL: u32 = 60;
+/// Canary domain browsers query to decide whether to turn on DNS-over-HTTPS
+/// (answering NXDOMAIN tells them to keep using this resolver)
+const DOH_CANARY_DOMAIN: &str = "use-application-dns.net";
+/// TTL of the CNAME pointing a search engine at its safe-search endpoint
+const SAFE_SEARCH_TTL: u32 = 300;
+
+/// Consecutive `recv_from` failures in the server loop
+#[derive(Debug, Default)]
+struct RecvBackoff {
+    consecutive: u32,
+}
+
+impl RecvBackoff {
+    /// Record a failure and return how long to wait before the next attempt
+    fn failed(&mut self) -> Duration {
+        self.consecutive = self.consecutive.saturating_add(1);
+        let exponent = (self.consecutive - 1).min(16);
+        (RECV_BACKOFF_BASE * 2u32.pow(exponent)).min(RECV_BACKOFF_MAX)
+    }
+
+    fn succeeded(&mut self) {
+        self.consecutive = 0;
+    }
+
+    fn should_rebind(&self) -> bool {
+        self.consecutive >= RECV_ERRORS_BEFORE_REBIND
+    }
+}
+
+/// How long `ping_upstreams` waits for each upstream's answer
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Longest `pause` accepted; past a week, blocking is simply off
+pub(crate) const MAX_PAUSE: Duration = Duration::from_secs(7 * 86400);
+
+/// How long `check_block_page` waits for the connection
+const BLOCK_PAGE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Outcome of `DnsServer::ping_upstreams` for one upstream
+pub(crate) struct UpstreamPing {
+    pub upstream: String,
+    /// Round-trip time and response code, or why no answer came back
+    pub result: std::result::Result<(Duration, ResponseCode), String>,
+}
+
+/// An upstream answered another question than it was asked (see
+/// `QuestionMismatch`)
+#[derive(Debug)]
+struct MismatchedQuestion {
+    upstream: String,
+    asked: Option<String>,
+    answered: Option<String>,
+}
+
+impl std::fmt::Display for MismatchedQuestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |question: &Option<String>| question.clone().unwrap_or("nothing".to_string());
+        write!(
+            f,
+            "{} answered {} to a query for {}",
+            self.upstream,
+            show(&self.answered),
+            show(&self.asked)
+        )
+    }
+}
+
+impl std::error::Error for MismatchedQuestion {}
+
+/// Block events buffered per `watch` client; a client further behind
+/// misses the oldest ones
+const BLOCK_EVENT_BUFFER: usize = 256;
 
 /// DNS server that blocks domains from blocklist and forwards allowed queries
 pub struct DnsServer {
@@ -25,37 +128,171 @@ pub struct DnsServer {
     upstream_clients: Arc<Mutex<HashMap<Upstream, AsyncClient>>>,
     /// In-RAM query metrics, updated for every query
     metrics: Arc<RuntimeMetrics>,
+    /// Bounds the number of concurrently running `handle_query` tasks
+    query_permits: Arc<Semaphore>,
+    /// Decides which queries are blocked
+    policy: Arc<dyn BlockPolicy>,
+    /// Receives block events, if `webhook_url` is set
+    webhook: Option<Webhook>,
+    /// Addresses of DoH upstreams given by hostname, looked up through
+    /// `bootstrap_dns` at startup (or on first use if that failed)
+    doh_addrs: Arc<Mutex<HashMap<String, IpAddr>>>,
+    /// Last good answers, served when the upstream fails (`serve_stale`)
+    stale: Option<Arc<StaleCache>>,
+    /// DNS cookies exchanged with the upstreams (`upstream_cookies`)
+    cookies: Option<Arc<UpstreamCookies>>,
+    /// Every blocked query, for `watch` clients of the control socket
+    block_events: broadcast::Sender<BlockEvent>,
+    /// While set and in the future, nothing is blocked (`pause`)
+    paused_until: Arc<std::sync::Mutex<Option<Instant>>>,
 }
 
 impl DnsServer {
     /// Create a new DNS server instance
     pub fn new(config: Config, blocklist: Arc<BlocklistManager>) -> Result<Self> {
+        let max_queries = config.server.max_concurrent_queries.max(1);
+        let webhook = match &config.server.webhook_url {
+            Some(url) => Some(Webhook::new(url, &config.server.webhook_domains)?),
+            None => None,
+        };
+        let stale = config.server.serve_stale.map(|secs| {
+            Arc::new(StaleCache::new(
+                Duration::from_secs(secs.into()),
+                config.server.cache_max_entries,
+            ))
+        });
+        let cookies = config
+            .server
+            .upstream_cookies
+            .then(|| Arc::new(UpstreamCookies::new()));
         Ok(DnsServer {
+            webhook,
+            stale,
+            cookies,
+            block_events: broadcast::channel(BLOCK_EVENT_BUFFER).0,
+            paused_until: Arc::new(std::sync::Mutex::new(None)),
             config: Arc::new(config),
+            policy: Arc::new(BlocklistPolicy::new(Arc::clone(&blocklist))),
             blocklist,
             upstream_clients: Arc::new(Mutex::new(HashMap::new())),
+            doh_addrs: Arc::new(Mutex::new(HashMap::new())),
             metrics: Arc::new(RuntimeMetrics::new()),
+            query_permits: Arc::new(Semaphore::new(max_queries)),
         })
     }
 
+    /// Replace the default blocklist-backed policy with custom block logic
+    pub fn with_policy(mut self, policy: Arc<dyn BlockPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sender of the block events, to subscribe `watch` clients to
+    pub(crate) fn block_events(&self) -> broadcast::Sender<BlockEvent> {
+        self.block_events.clone()
+    }
+
     /// Handle to the in-RAM query metrics (consumed by the TUI dashboard)
     pub fn metrics(&self) -> Arc<RuntimeMetrics> {
         Arc::clone(&self.metrics)
     }
 
+    /// Drop the answers kept for `serve_stale`, returning how many there
+    /// were (none if it is off)
+    pub fn flush_cache(&self) -> usize {
+        let Some(stale) = &self.stale else {
+            return 0;
+        };
+        let flushed = stale.clear();
+        self.metrics.set_cached_answers(0);
+        flushed
+    }
+
+    /// Stop blocking for `duration`: every query is forwarded until then,
+    /// or until `resume`. A new pause replaces the current one.
+    pub fn pause(&self, duration: Duration) -> Result<()> {
+        if duration > MAX_PAUSE {
+            anyhow::bail!(
+                "Pause of {}s is longer than the {}s maximum",
+                duration.as_secs(),
+                MAX_PAUSE.as_secs()
+            );
+        }
+        let until = Instant::now()
+            .checked_add(duration)
+            .context("Pause ends too far in the future")?;
+        *self.paused_until.lock().unwrap() = Some(until);
+        tracing::info!(secs = duration.as_secs(), "Blocking paused");
+        Ok(())
+    }
+
+    /// End a pause early; false if blocking wasn't paused
+    pub fn resume(&self) -> bool {
+        let was_paused = self.paused_for().is_some();
+        *self.paused_until.lock().unwrap() = None;
+        if was_paused {
+            tracing::info!("Blocking resumed");
+        }
+        was_paused
+    }
+
+    /// How much of the current pause is left, if blocking is paused
+    pub fn paused_for(&self) -> Option<Duration> {
+        let mut paused_until = self.paused_until.lock().unwrap();
+        let left = paused_until.map(|until| until.saturating_duration_since(Instant::now()));
+        if left.is_some_and(|left| left.is_zero()) {
+            // Expired: blocking resumes by itself
+            *paused_until = None;
+            tracing::info!("Pause over, blocking resumed");
+            return None;
+        }
+        left
+    }
+
+    /// Whether a DoH connection from `ip` may name the client with
+    /// `X-Forwarded-For` (`doh_trusted_proxies`)
+    pub(crate) fn trusts_proxy(&self, ip: IpAddr) -> bool {
+        self.config
+            .server
+            .doh_trusted_proxies
+            .iter()
+            .any(|proxy| proxy.contains(ip))
+    }
+
+    /// Wait for one of the `max_concurrent_queries` slots, held until the
+    /// permit is dropped
+    pub(crate) async fn query_permit(&self) -> Result<tokio::sync::OwnedSemaphorePermit> {
+        Arc::clone(&self.query_permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| anyhow::anyhow!("Query semaphore closed"))
+    }
+
+    /// Number of queries currently being handled
+    pub fn in_flight_queries(&self) -> usize {
+        self.config.server.max_concurrent_queries.max(1) - self.query_permits.available_permits()
+    }
+
     /// Start the DNS server
     pub async fn start(&self) -> Result<()> {
-        let listen_addr = format!(
-            "{}:{}",
-            self.config.server.listen_addr, self.config.server.listen_port
-        );
+        let listen_addr = self.config.server.listen_socket_addr()?;
 
         tracing::info!(addr = %listen_addr, "Starting DNS server");
 
         // Bind UDP socket
-        let socket = UdpSocket::bind(&listen_addr).await?;
+        let socket = UdpSocket::bind(listen_addr)
+            .await
+            .map_err(|e| bind_error(listen_addr, e))?;
         tracing::info!(proto = "UDP", addr = %listen_addr, "DNS server listening");
 
+        if let Some(doh_addr) = self.config.server.doh_listen {
+            let listener = TcpListener::bind(doh_addr)
+                .await
+                .map_err(|e| bind_error(doh_addr, e))?;
+            tracing::info!(proto = "DoH", addr = %doh_addr, "DNS server listening");
+            tokio::spawn(crate::doh_server::serve(self.clone(), listener));
+        }
+
         // Validate upstream DNS configuration
         let upstreams = &self.config.server.upstream_dns;
         if upstreams.is_empty() {
@@ -66,6 +303,33 @@ impl DnsServer {
             count = upstreams.len(),
             "Upstream DNS servers configured; a random one is chosen per query"
         );
+        if let Err(e) = self.bootstrap_doh_hosts().await {
+            tracing::warn!(error = %e, "DoH bootstrap failed, retrying on first use");
+        }
+
+        if self.config.server.self_test.enabled {
+            match self.self_test().await {
+                Ok(()) => tracing::info!("Startup self-test passed"),
+                Err(e) if self.config.server.self_test.fail_closed => {
+                    return Err(e.context("Startup self-test failed"));
+                }
+                Err(e) => tracing::warn!(error = %e, "Startup self-test failed, serving anyway"),
+            }
+        }
+
+        // Periodically drop expired temporary blocks (they already stop
+        // matching on expiry; this just frees them)
+        let blocklist = Arc::clone(&self.blocklist);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let removed = blocklist.sweep_expired().await;
+                if removed > 0 {
+                    tracing::info!(count = removed, "Temporary blocks expired");
+                }
+            }
+        });
 
         // Main server loop
         self.run_server(socket).await?;
@@ -76,14 +340,34 @@ impl DnsServer {
     /// Main server loop - handle incoming DNS queries
     async fn run_server(&self, socket: UdpSocket) -> Result<()> {
         let mut buf = vec![0u8; 512]; // Standard DNS packet size
-        let socket = Arc::new(socket);
+        let bind_addr = socket.local_addr()?;
+        let mut socket = Arc::new(socket);
+        let mut backoff = RecvBackoff::default();
 
         loop {
             // Receive DNS query
             let (len, src) = match socket.recv_from(&mut buf).await {
-                Ok(result) => result,
+                Ok(result) => {
+                    backoff.succeeded();
+                    result
+                }
                 Err(e) => {
-                    tracing::error!(error = %e, "Failed to receive from socket");
+                    // Back off so a socket stuck in an error state doesn't
+                    // spin the loop, and start over with a fresh socket if
+                    // the errors persist
+                    let delay = backoff.failed();
+                    tracing::error!(
+                        error = %e,
+                        consecutive = backoff.consecutive,
+                        "Failed to receive from socket"
+                    );
+                    if backoff.should_rebind() {
+                        drop(socket);
+                        socket = Self::rebind(bind_addr).await;
+                        backoff.succeeded();
+                    } else {
+                        tokio::time::sleep(delay).await;
+                    }
                     continue;
                 }
             };
@@ -95,134 +379,593 @@ impl DnsServer {
                 Ok(msg) => msg,
                 Err(e) => {
                     tracing::warn!(src = %src, error = %e, "Failed to parse DNS message");
+                    // Answer FORMERR when the header is readable, so the
+                    // client doesn't wait for a timeout
+                    let formerr = formerr_response(&buf[..len])
+                        .and_then(|response| encode_message(&response, false).ok());
+                    if let Some(bytes) = formerr {
+                        if let Err(e) = socket.send_to(&bytes, src).await {
+                            tracing::debug!(src = %src, error = %e, "Failed to send FORMERR");
+                        }
+                    }
                     continue;
                 }
             };
 
+            // Wait for a free slot before spawning, so a flood backs up into
+            // the socket buffer (where the kernel drops the excess) instead
+            // of piling up tasks in memory
+            let permit = self.query_permit().await?;
+
             // Handle query in background task
             let server = self.clone();
             let socket_clone = Arc::clone(&socket);
             tokio::spawn(async move {
-                if let Err(e) = server.handle_query(query, src, socket_clone).await {
-                    tracing::error!(error = %e, "Error handling query");
-                }
+                // Errors are logged inside the query's span
+                let _ = server.handle_query(query, src, socket_clone).await;
+                drop(permit);
             });
         }
     }
 
-    /// Handle a single DNS query
+    /// Check the pipeline end to end: the control domain must be allowed and
+    /// resolve through the upstream, and the sentinel domain (if configured)
+    /// must be blocked. Does not touch the query metrics.
+    pub async fn self_test(&self) -> Result<()> {
+        let settings = &self.config.server.self_test;
+        let control = &settings.control_domain;
+
+        if let Some(rule) = self.blocklist.matching_rule(control).await {
+            anyhow::bail!("control domain {} is blocked ({})", control, rule);
+        }
+        let mut query = Message::new();
+        query.set_id(rand::random()).set_recursion_desired(true);
+        query.add_query(Query::query(Name::from_str(control)?, RecordType::A));
+        let response = self
+            .forward_to_upstream(&query, None)
+            .await
+            .with_context(|| format!("control domain {} did not resolve", control))?;
+        if response.response_code() != ResponseCode::NoError || response.answers().is_empty() {
+            anyhow::bail!(
+                "control domain {} resolved to {} with {} answers",
+                control,
+                response.response_code(),
+                response.answers().len()
+            );
+        }
+        tracing::info!(domain = %control, "Self-test: control domain resolved");
+
+        if let Some(sentinel) = &settings.sentinel_domain {
+            let rule = self
+                .blocklist
+                .matching_rule(sentinel)
+                .await
+                .ok_or_else(|| anyhow::anyhow!("sentinel domain {} is not blocked", sentinel))?;
+            tracing::info!(domain = %sentinel, rule = %rule, "Self-test: sentinel domain blocked");
+        }
+
+        Ok(())
+    }
+
+    /// Bind a fresh socket on `addr`, retrying until it succeeds. Binding
+    /// fails while in-flight queries still hold the old socket, so this can
+    /// take a few attempts.
+    async fn rebind(addr: SocketAddr) -> Arc<UdpSocket> {
+        tracing::warn!(addr = %addr, "Rebinding UDP socket after repeated receive errors");
+        loop {
+            match UdpSocket::bind(addr).await {
+                Ok(socket) => {
+                    tracing::info!(addr = %addr, "UDP socket rebound, resuming");
+                    return Arc::new(socket);
+                }
+                Err(e) => {
+                    tracing::warn!(addr = %addr, error = %e, "Rebind failed, retrying");
+                    tokio::time::sleep(RECV_BACKOFF_MAX).await;
+                }
+            }
+        }
+    }
+
+    /// Answer one query inside a `query` span carrying a short random
+    /// request id, the client IP and the query name, so the log lines of
+    /// concurrent queries can be told apart
     async fn handle_query(
         &self,
         query: Message,
         src: SocketAddr,
         socket: Arc<UdpSocket>,
     ) -> Result<()> {
+        let span = Self::query_span(&query, src);
+        async move {
+            let result = self.answer_query(query, src, socket).await;
+            if let Err(e) = &result {
+                tracing::error!(error = %e, "Error handling query");
+            }
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Answer a query that came in over another transport than UDP (the
+    /// DoH listener), in the same kind of span as `handle_query`
+    pub(crate) async fn answer(&self, query: Message, src: SocketAddr) -> Message {
+        let span = Self::query_span(&query, src);
+        self.respond(&query, src).instrument(span).await
+    }
+
+    fn query_span(query: &Message, src: SocketAddr) -> tracing::Span {
+        let domain = query
+            .queries()
+            .first()
+            .map(|q| q.name().to_utf8())
+            .unwrap_or_default();
+        tracing::info_span!(
+            "query",
+            id = %format!("{:08x}", rand::random::<u32>()),
+            client = %src.ip(),
+            domain = %domain,
+        )
+    }
+
+    async fn answer_query(
+        &self,
+        query: Message,
+        src: SocketAddr,
+        socket: Arc<UdpSocket>,
+    ) -> Result<()> {
+        let response = self.respond(&query, src).await;
+
+        // Send response, truncated if it is over the client's UDP budget
+        let limit = self.udp_response_limit(&query);
+        let response_bytes =
+            encode_udp_response(response, self.config.server.compress_responses, limit)?;
+        socket.send_to(&response_bytes, src).await?;
+
+        Ok(())
+    }
+
+    /// Run a query through the pipeline (canary, ANY handling, blocklist,
+    /// upstream) and build the answer, whatever transport it came over
+    async fn respond(&self, query: &Message, src: SocketAddr) -> Message {
         // Extract query information
-        let query_name = match query.queries().first() {
-            Some(q) => q.name().to_utf8(),
+        let (query_name, query_type) = match query.queries().first() {
+            Some(q) => (q.name().to_utf8(), q.query_type()),
             None => {
-                tracing::warn!(src = %src, "Query has no questions");
-                return Ok(());
+                // Nothing to resolve, but answer so the client isn't left
+                // waiting for a timeout
+                tracing::warn!(src = %src, "Query has no questions, answering FORMERR");
+                return Self::create_rcode_response(query, ResponseCode::FormErr);
             }
         };
 
         tracing::debug!(src = %src, domain = %query_name, "Query received");
+        self.metrics.record_client(src.ip());
+
+        if self.config.server.doh_canary && is_doh_canary(&query_name) {
+            tracing::debug!(domain = %query_name, client = %src.ip(), "DoH canary, answering NXDOMAIN");
+            self.metrics.record_blocked(&query_name);
+            self.metrics.record_client_blocked(src.ip());
+            return Self::create_rcode_response(query, ResponseCode::NXDomain);
+        }
 
-        // Check if domain is blocked
-        let is_blocked = self.blocklist.is_blocked(&query_name).await;
+        if self.config.server.block_any_queries && query_type == RecordType::ANY {
+            tracing::debug!(domain = %query_name, client = %src.ip(), "ANY query, answering with a minimal HINFO");
+            return Self::create_any_response(query);
+        }
+
+        // Check if domain is blocked, unless blocking is paused
+        let decision = if self.paused_for().is_some() {
+            Decision::Allow
+        } else {
+            self.policy.decide(&query_name, query_type, src.ip()).await
+        };
 
-        let response = if is_blocked {
-            // The `blocked` marker field is what the TUI keys its highlighting
-            // on; keep it if the message text changes.
-            tracing::info!(domain = %query_name, source_ip = %src.ip(), blocked = true, "blocked");
+        if let Decision::Block(rule) = decision {
+            // `analyze` parses these `[BLOCKED]`/`[ALLOWED]` lines and the TUI
+            // keys its highlighting on the `blocked` marker field: keep the
+            // message and the key=value field names stable.
+            tracing::info!(
+                target: QUERY_LOG_TARGET,
+                domain = %query_name,
+                client = %src.ip(),
+                qtype = %query_type,
+                blocked = true,
+                "[BLOCKED]"
+            );
             self.metrics.record_blocked(&query_name);
+            self.metrics.record_client_blocked(src.ip());
+            if let Some(webhook) = self.webhook.as_ref().filter(|w| w.wants(&query_name)) {
+                if !webhook.send(BlockEvent::new(&query_name, src.ip(), &rule)) {
+                    self.metrics.record_webhook_dropped();
+                }
+            }
+            if self.block_events.receiver_count() > 0 {
+                let _ = self
+                    .block_events
+                    .send(BlockEvent::new(&query_name, src.ip(), &rule));
+            }
 
             // Create blocked response
-            self.create_blocked_response(&query)
+            self.create_blocked_response(query, &rule)
         } else {
+            // Only recursion is on offer here; a client that asked for none
+            // gets what is already cached, else REFUSED rather than a
+            // recursive answer (and isn't counted as allowed)
+            let cached = if self.config.server.honor_rd && !query.recursion_desired() {
+                let route = self.config.server.route_for(Some(src.ip()));
+                match self.stale.as_ref().and_then(|s| s.fresh(route, query)) {
+                    Some(response) => Some(response),
+                    None => {
+                        tracing::debug!(domain = %query_name, client = %src.ip(), "Recursion not desired, answering REFUSED");
+                        return Self::create_rcode_response(query, ResponseCode::Refused);
+                    }
+                }
+            } else {
+                None
+            };
+
             // Domain is allowed - forward to upstream
-            tracing::debug!(domain = %query_name, source_ip = %src.ip(), "allowed");
+            let rate = self.config.logging.query_log_sample_rate;
+            if rate >= 1.0 {
+                tracing::info!(
+                    target: QUERY_LOG_TARGET,
+                    domain = %query_name,
+                    client = %src.ip(),
+                    qtype = %query_type,
+                    "[ALLOWED]"
+                );
+            } else if sampled(rate, &mut rand::thread_rng()) {
+                // The line stands for 1/rate queries; `analyze` scales it
+                tracing::info!(
+                    target: QUERY_LOG_TARGET,
+                    domain = %query_name,
+                    client = %src.ip(),
+                    qtype = %query_type,
+                    sample_rate = rate,
+                    "[ALLOWED]"
+                );
+            }
             self.metrics.record_allowed();
 
-            // Forward to upstream DNS
-            self.forward_to_upstream(query).await?
+            if let Some(response) = cached {
+                tracing::debug!(domain = %query_name, client = %src.ip(), "Recursion not desired, answering from cache");
+                return response;
+            }
+
+            if self.config.server.safe_search {
+                if let Some(target) = safe_search_target(&query_name) {
+                    tracing::debug!(domain = %query_name, target = %target, "Enforcing safe search");
+                    return self.resolve_safe_search(query, target, src.ip()).await;
+                }
+            }
+
+            self.resolve_upstream(query, &query_name, src.ip()).await
+        }
+    }
+
+    /// Forward an allowed query upstream. If that fails, answer from the
+    /// stale cache when `serve_stale` allows, else SERVFAIL so the client
+    /// fails fast instead of waiting for its own timeout.
+    async fn resolve_upstream(&self, query: &Message, query_name: &str, client: IpAddr) -> Message {
+        // Stale answers stay with the upstreams that gave them, so clients
+        // routed elsewhere (e.g. to a filtering resolver) never get them
+        let route = self.config.server.route_for(Some(client));
+        match self.forward_to_upstream(query, Some(client)).await {
+            Ok(response) => {
+                if let Some(stale) = &self.stale {
+                    stale.store(route, query, &response);
+                    self.metrics.set_cached_answers(stale.len());
+                }
+                response
+            }
+            Err(e) => {
+                if let Some(response) = self.stale.as_ref().and_then(|s| s.lookup(route, query)) {
+                    tracing::warn!(
+                        domain = %query_name,
+                        error = %e,
+                        "Upstream forwarding failed, serving stale answer"
+                    );
+                    self.metrics.record_stale_served();
+                    return response;
+                }
+                tracing::warn!(
+                    domain = %query_name,
+                    error = %e,
+                    "Upstream forwarding failed, answering SERVFAIL"
+                );
+                Self::create_servfail_response(query)
+            }
+        }
+    }
+
+    /// Answer `query` with a CNAME to the safe-search host `target`,
+    /// followed by `target`'s own records for the asked type as resolved
+    /// upstream, so stubs that don't chase CNAMEs still get addresses
+    async fn resolve_safe_search(&self, query: &Message, target: &str, client: IpAddr) -> Message {
+        let (Some(question), Ok(target_name)) = (
+            query.queries().first(),
+            Name::from_ascii(format!("{target}.")),
+        ) else {
+            return Self::create_servfail_response(query);
         };
+        let cname = Record::from_rdata(
+            question.name().clone(),
+            SAFE_SEARCH_TTL,
+            RData::CNAME(CNAME(target_name.clone())),
+        );
+        if question.query_type() == RecordType::CNAME {
+            let mut response = Self::create_rcode_response(query, ResponseCode::NoError);
+            response.add_answer(cname);
+            return response;
+        }
 
-        // Send response
-        let response_bytes = response.to_bytes()?;
-        socket.send_to(&response_bytes, src).await?;
+        let mut target_query = query.clone();
+        target_query.take_queries();
+        let mut target_question = question.clone();
+        target_question.set_name(target_name);
+        target_query.add_query(target_question);
+        let mut response = self.resolve_upstream(&target_query, target, client).await;
 
-        Ok(())
+        response.take_queries();
+        response.add_queries(query.queries().to_vec());
+        if response.response_code() == ResponseCode::NoError {
+            let answers = response.take_answers();
+            response.add_answer(cname);
+            response.add_answers(answers);
+        }
+        response
+    }
+
+    /// Largest UDP response this query may get: `max_udp_response_size`,
+    /// else the client's EDNS payload size (never below 512), else 512
+    fn udp_response_limit(&self, query: &Message) -> usize {
+        if let Some(size) = self.config.server.max_udp_response_size {
+            return size.into();
+        }
+        query
+            .extensions()
+            .as_ref()
+            .map_or(MIN_UDP_PAYLOAD, |edns| {
+                edns.max_payload().max(MIN_UDP_PAYLOAD)
+            })
+            .into()
     }
 
     /// Create a blocked response based on configuration
-    fn create_blocked_response(&self, query: &Message) -> Message {
+    fn create_blocked_response(&self, query: &Message, rule: &MatchKind) -> Message {
         let mut response = Message::new();
         response.set_id(query.id());
         response.set_message_type(MessageType::Response);
         response.set_op_code(OpCode::Query);
         response.add_queries(query.queries().to_vec());
+        if self.config.server.blocked_authoritative {
+            response
+                .set_authoritative(true)
+                .set_recursion_desired(query.recursion_desired())
+                .set_recursion_available(true);
+        }
 
-        match &self.config.server.blocked_response {
+        let query_type = query
+            .queries()
+            .first()
+            .map(|q| q.query_type())
+            .unwrap_or(RecordType::NULL);
+        // Address queries go to the block page when there is one
+        let block_page = self
+            .config
+            .server
+            .block_page_ip
+            .filter(|_| {
+                matches!(
+                    query_type,
+                    RecordType::A | RecordType::AAAA | RecordType::ANY
+                )
+            })
+            .map(crate::config::BlockedResponse::Ip);
+        match block_page.as_ref().unwrap_or_else(|| {
+            self.config
+                .server
+                .blocked_response_for(&query_type.to_string())
+        }) {
             crate::config::BlockedResponse::Refused => {
                 response.set_response_code(ResponseCode::Refused);
             }
             crate::config::BlockedResponse::NxDomain => {
                 response.set_response_code(ResponseCode::NXDomain);
             }
+            crate::config::BlockedResponse::NoData => {
+                response.set_response_code(ResponseCode::NoError);
+            }
             crate::config::BlockedResponse::Ip(ip) => {
                 response.set_response_code(ResponseCode::NoError);
 
-                // Add answer with blocked IP
+                // Answer only when the sink's family matches what was asked
+                // for (A for IPv4, AAAA for IPv6, or ANY); everything else
+                // gets NODATA rather than a record of the wrong type.
+                // The answer owns the question's name verbatim, so with
+                // compression it is emitted as a 2-byte pointer
                 if let Some(query_q) = query.queries().first() {
-                    let mut record = Record::new();
-                    record.set_name(query_q.name().clone());
-                    record.set_record_type(RecordType::A);
-                    record.set_ttl(60);
-
-                    match ip {
-                        IpAddr::V4(ipv4) => {
-                            record.set_data(Some(RData::A(ipv4.to_owned().into())));
+                    let rdata = match (ip, query_q.query_type()) {
+                        (IpAddr::V4(ipv4), RecordType::A | RecordType::ANY) => {
+                            Some(RData::A((*ipv4).into()))
                         }
-                        IpAddr::V6(ipv6) => {
-                            record.set_data(Some(RData::AAAA(ipv6.to_owned().into())));
+                        (IpAddr::V6(ipv6), RecordType::AAAA | RecordType::ANY) => {
+                            Some(RData::AAAA((*ipv6).into()))
                         }
+                        _ => None,
+                    };
+                    if let Some(rdata) = rdata {
+                        response.add_answer(Record::from_rdata(query_q.name().clone(), 60, rdata));
                     }
-
-                    response.add_answer(record);
                 }
             }
         }
 
+        // NODATA (configured, or a sink of the other address family) gets a
+        // synthetic SOA so resolvers can cache the empty answer
+        if response.response_code() == ResponseCode::NoError && response.answers().is_empty() {
+            Self::add_negative_soa(&mut response, query);
+        }
+
+        if self.config.server.blocked_explain {
+            Self::add_block_explanation(&mut response, query, rule);
+        }
+
+        response
+    }
+
+    /// Put a synthetic SOA for the queried name in the authority section.
+    /// Per RFC 2308 its TTL (the lesser of record TTL and MINIMUM) is how long
+    /// the empty answer may be cached.
+    fn add_negative_soa(response: &mut Message, query: &Message) {
+        let Some(question) = query.queries().first() else {
+            return;
+        };
+        let soa = SOA::new(
+            Name::from_ascii("blackhole.skypier.").expect("valid name"),
+            Name::from_ascii("hostmaster.skypier.").expect("valid name"),
+            1,
+            3600,
+            600,
+            86400,
+            BLOCKED_NEGATIVE_TTL,
+        );
+        response.add_name_server(Record::from_rdata(
+            question.name().clone(),
+            BLOCKED_NEGATIVE_TTL,
+            RData::SOA(soa),
+        ));
+    }
+
+    /// Attach a TXT record saying why the name was blocked: as the answer
+    /// for TXT/ANY queries, in the additional section otherwise
+    fn add_block_explanation(response: &mut Message, query: &Message, rule: &MatchKind) {
+        let Some(question) = query.queries().first() else {
+            return;
+        };
+
+        // A TXT character-string holds at most 255 bytes
+        let mut text = format!("blocked by skypier: {}", rule);
+        if text.len() > 255 {
+            let mut end = 255;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+
+        let record = Record::from_rdata(
+            question.name().clone(),
+            60,
+            RData::TXT(TXT::new(vec![text])),
+        );
+        match question.query_type() {
+            RecordType::TXT | RecordType::ANY => response.add_answer(record),
+            _ => response.add_additional(record),
+        };
+    }
+
+    /// Create a SERVFAIL response for a query that could not be forwarded
+    fn create_servfail_response(query: &Message) -> Message {
+        Self::create_rcode_response(query, ResponseCode::ServFail)
+    }
+
+    /// Create an answer-less response carrying only `code`
+    fn create_rcode_response(query: &Message, code: ResponseCode) -> Message {
+        let mut response = Message::new();
+        response.set_id(query.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(query.op_code());
+        response.set_recursion_desired(query.recursion_desired());
+        response.set_recursion_available(true);
+        response.set_response_code(code);
+        response.add_queries(query.queries().to_vec());
+        response
+    }
+
+    /// The RFC 8482 answer to an ANY query: a single small HINFO record
+    /// instead of everything known about the name
+    fn create_any_response(query: &Message) -> Message {
+        let mut response = Self::create_rcode_response(query, ResponseCode::NoError);
+        if let Some(question) = query.queries().first() {
+            response.add_answer(Record::from_rdata(
+                question.name().clone(),
+                3600,
+                RData::HINFO(HINFO::new("RFC8482".to_string(), String::new())),
+            ));
+        }
         response
     }
 
     /// Forward query to upstream DNS server
     ///
     /// Picks a random upstream for each query rather than always using the
-    /// first configured one, so no single resolver sees every lookup.
-    async fn forward_to_upstream(&self, query: Message) -> Result<Message> {
-        let upstreams = &self.config.server.upstream_dns;
+    /// first configured one, so no single resolver sees every lookup. The
+    /// upstreams are those routed to `client` (see `client_upstreams`).
+    async fn forward_to_upstream(
+        &self,
+        query: &Message,
+        client: Option<IpAddr>,
+    ) -> Result<Message> {
+        let upstreams = self.config.server.upstreams_for(client);
         if upstreams.is_empty() {
             return Err(anyhow::anyhow!("No upstream DNS configured"));
         }
-        let upstream = &upstreams[rand::thread_rng().gen_range(0..upstreams.len())];
+        let randomized;
+        let sent = if self.config.server.randomize_case {
+            randomized = randomize_question_case(query);
+            &randomized
+        } else {
+            query
+        };
+        let first = rand::thread_rng().gen_range(0..upstreams.len());
+        let mut result = self.forward_to(&upstreams[first], sent).await;
+        if self.config.server.on_question_mismatch == QuestionMismatch::Retry {
+            for next in 1..upstreams.len() {
+                match &result {
+                    Err(e) if e.is::<MismatchedQuestion>() => {
+                        tracing::warn!(error = %e, "Trying another upstream")
+                    }
+                    _ => break,
+                }
+                let upstream = &upstreams[(first + next) % upstreams.len()];
+                result = self.forward_to(upstream, sent).await;
+            }
+        }
+        let mut response = result?;
+        if self.config.server.randomize_case {
+            restore_question_case(&mut response, query);
+        }
+        Ok(response)
+    }
+
+    /// Forward query to `upstream`, counting the outcome in its metrics
+    async fn forward_to(&self, upstream: &Upstream, query: &Message) -> Result<Message> {
+        let started = std::time::Instant::now();
+        let result = self.exchange_with(upstream, query).await;
+        let latency = result.as_ref().ok().map(|_| started.elapsed());
+        self.metrics.record_upstream(&upstream.to_string(), latency);
+        result
+    }
 
+    /// Send query to `upstream` and post-process its answer
+    async fn exchange_with(&self, upstream: &Upstream, query: &Message) -> Result<Message> {
         // Save original query ID
         let original_id = query.id();
 
-        // Forward query
-        let query_name = query
-            .queries()
-            .first()
-            .ok_or_else(|| anyhow::anyhow!("No query in message"))?;
-
-        let name = Name::from_str(&query_name.name().to_utf8())?;
-        let query_type = query_name.query_type();
+        let mut request = Self::build_upstream_query(query, self.config.server.ecs_policy)?;
+        if let Some(cookies) = &self.cookies {
+            cookies.attach(&upstream.to_string(), &mut request);
+        }
 
-        let mut client = self.upstream_client(upstream).await?;
+        let client = self.upstream_client(upstream).await?;
         let dns_response = match client
-            .query(name.clone(), hickory_proto::rr::DNSClass::IN, query_type)
+            .send(DnsRequest::new(
+                request.clone(),
+                DnsRequestOptions::default(),
+            ))
+            .first_answer()
             .await
         {
             Ok(response) => response,
@@ -231,31 +974,252 @@ impl DnsServer {
                 // closed an idle HTTP/2 session); reconnect and retry once
                 tracing::debug!(error = %e, upstream = %upstream, "Upstream query failed, reconnecting");
                 self.upstream_clients.lock().await.remove(upstream);
-                let mut client = self.upstream_client(upstream).await?;
+                let client = self.upstream_client(upstream).await?;
                 client
-                    .query(name, hickory_proto::rr::DNSClass::IN, query_type)
+                    .send(DnsRequest::new(
+                        request.clone(),
+                        DnsRequestOptions::default(),
+                    ))
+                    .first_answer()
                     .await?
             }
         };
 
         // Convert DnsResponse to Message and restore original ID
         let mut response: Message = dns_response.into();
+
+        // A truncated UDP answer is retried over TCP to the same upstream
+        if let (Upstream::Udp(addr), true) = (upstream, response.truncated()) {
+            tracing::debug!(upstream = %upstream, "Upstream answer truncated, retrying over TCP");
+            let client = Self::connect_tcp(*addr).await?;
+            response = client
+                .send(DnsRequest::new(
+                    request.clone(),
+                    DnsRequestOptions::default(),
+                ))
+                .first_answer()
+                .await?
+                .into();
+        }
+        if let Some(cookies) = &self.cookies {
+            cookies.validate(&upstream.to_string(), &response)?;
+            // BADCOOKIE carries a fresh server cookie (now remembered): ask
+            // again with it, once (RFC 7873 section 5.3)
+            if response.response_code() == ResponseCode::BADCOOKIE {
+                tracing::debug!(upstream = %upstream, "BADCOOKIE, retrying with the new server cookie");
+                cookies.attach(&upstream.to_string(), &mut request);
+                let client = self.upstream_client(upstream).await?;
+                response = client
+                    .send(DnsRequest::new(request, DnsRequestOptions::default()))
+                    .first_answer()
+                    .await?
+                    .into();
+                cookies.validate(&upstream.to_string(), &response)?;
+            }
+        }
+        // The answer must be for the question we asked, not a spoofed or
+        // confused one; with `randomize_case`, down to the letter case
+        let asked = query.queries().first();
+        let answered = response.queries().first();
+        let case_differs = self.config.server.randomize_case
+            && !matches!((asked, answered), (Some(a), Some(b)) if a.name().eq_case(b.name()));
+        if answered != asked || case_differs {
+            return Err(MismatchedQuestion {
+                upstream: upstream.to_string(),
+                asked: query.queries().first().map(ToString::to_string),
+                answered: response.queries().first().map(ToString::to_string),
+            }
+            .into());
+        }
         response.set_id(original_id);
+        clamp_answer_ttls(
+            &mut response,
+            self.config.server.min_ttl,
+            self.config.server.max_ttl,
+        );
+        let stripped = strip_blocked_ips(&mut response, &self.config.server.blocked_ip_ranges);
+        if stripped > 0 {
+            tracing::debug!(count = stripped, "Stripped answers in blocked IP ranges");
+        }
+        if self.config.server.strip_additional {
+            let dropped = strip_additional_records(&mut response);
+            if dropped > 0 {
+                tracing::debug!(count = dropped, "Dropped additional records");
+            }
+        }
 
         Ok(response)
     }
 
+    /// Build the message sent upstream for a client query: a fresh query for
+    /// its first question with our own EDNS record, so nothing else from the
+    /// client leaks through. The client's ECS option is copied over only
+    /// under `EcsPolicy::Passthrough`.
+    fn build_upstream_query(query: &Message, ecs_policy: EcsPolicy) -> Result<Message> {
+        let question = query
+            .queries()
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No query in message"))?;
+
+        let mut request = Message::new();
+        request
+            .add_query(question.clone())
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true);
+
+        let edns = request.extensions_mut().get_or_insert_with(Edns::new);
+        edns.set_max_payload(UPSTREAM_EDNS_PAYLOAD).set_version(0);
+
+        if ecs_policy == EcsPolicy::Passthrough {
+            let subnet = query
+                .extensions()
+                .as_ref()
+                .and_then(|client_edns| client_edns.option(EdnsCode::Subnet));
+            if let Some(subnet) = subnet {
+                edns.options_mut().insert(subnet.clone());
+            }
+        }
+
+        Ok(request)
+    }
+
+    /// Response code of an upstream's answer to an A query for `domain`.
+    /// An error if no answer came back within `PING_TIMEOUT`.
+    pub(crate) async fn resolve_rcode(&self, domain: &str) -> Result<ResponseCode> {
+        let name = Name::from_str(domain).with_context(|| format!("Invalid domain {domain}"))?;
+        let mut query = Message::new();
+        query.set_recursion_desired(true);
+        query.add_query(Query::query(name, RecordType::A));
+
+        let response = tokio::time::timeout(PING_TIMEOUT, self.forward_to_upstream(&query, None))
+            .await
+            .map_err(|_| anyhow::anyhow!("no answer within {}s", PING_TIMEOUT.as_secs()))??;
+        Ok(response.response_code())
+    }
+
+    /// Send an A query for `domain` to every configured upstream, one after
+    /// another, timing each round trip (connection setup included)
+    pub(crate) async fn ping_upstreams(&self, domain: &str) -> Result<Vec<UpstreamPing>> {
+        let name = Name::from_str(domain).with_context(|| format!("Invalid domain {domain}"))?;
+        let mut query = Message::new();
+        query.set_recursion_desired(true);
+        query.add_query(Query::query(name, RecordType::A));
+        let request = Self::build_upstream_query(&query, EcsPolicy::Strip)?;
+
+        let mut pings = Vec::new();
+        for upstream in &self.config.server.upstream_dns {
+            let started = std::time::Instant::now();
+            let exchange = async {
+                let client = self.upstream_client(upstream).await?;
+                let response: Message = client
+                    .send(DnsRequest::new(
+                        request.clone(),
+                        DnsRequestOptions::default(),
+                    ))
+                    .first_answer()
+                    .await?
+                    .into();
+                Ok::<_, anyhow::Error>(response.response_code())
+            };
+            let result = match tokio::time::timeout(PING_TIMEOUT, exchange).await {
+                Ok(Ok(rcode)) => Ok((started.elapsed(), rcode)),
+                Ok(Err(e)) => Err(format!("{e:#}")),
+                Err(_) => Err(format!("no answer within {}s", PING_TIMEOUT.as_secs())),
+            };
+            pings.push(UpstreamPing {
+                upstream: upstream.to_string(),
+                result,
+            });
+        }
+        Ok(pings)
+    }
+
     /// Get the cached client for this upstream, connecting if necessary
     async fn upstream_client(&self, upstream: &Upstream) -> Result<AsyncClient> {
         let mut cached = self.upstream_clients.lock().await;
         if let Some(client) = cached.get(upstream) {
             return Ok(client.clone());
         }
-        let client = Self::connect_upstream(upstream).await?;
+        let client = match upstream {
+            Upstream::DoHHost { dns_name, port } => {
+                let ip = self.doh_addr(dns_name).await?;
+                Self::connect_upstream(&Upstream::DoH {
+                    addr: SocketAddr::new(ip, *port),
+                    dns_name: dns_name.clone(),
+                })
+                .await?
+            }
+            _ => Self::connect_upstream(upstream).await?,
+        };
         cached.insert(upstream.clone(), client.clone());
         Ok(client)
     }
 
+    /// Look up every DoH upstream given by hostname through `bootstrap_dns`,
+    /// so the first query doesn't wait for it
+    async fn bootstrap_doh_hosts(&self) -> Result<()> {
+        for upstream in self.config.server.all_upstreams() {
+            if let Upstream::DoHHost { dns_name, .. } = upstream {
+                let ip = self.doh_addr(dns_name).await?;
+                tracing::info!(upstream = %upstream, addr = %ip, "Resolved DoH upstream");
+            }
+        }
+        Ok(())
+    }
+
+    /// Address of a DoH upstream host, from the cache or asked of the
+    /// bootstrap resolver (A first, then AAAA)
+    async fn doh_addr(&self, host: &str) -> Result<IpAddr> {
+        if let Some(ip) = self.doh_addrs.lock().await.get(host) {
+            return Ok(*ip);
+        }
+        let bootstrap = self
+            .config
+            .server
+            .bootstrap_dns
+            .ok_or_else(|| anyhow::anyhow!("No bootstrap_dns configured to resolve {host}"))?;
+        let client = Self::connect_upstream(&Upstream::Udp(bootstrap)).await?;
+        let name =
+            Name::from_str(host).with_context(|| format!("Invalid DoH upstream host {host}"))?;
+
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            let mut query = Message::new();
+            query.set_recursion_desired(true);
+            query.add_query(Query::query(name.clone(), record_type));
+            let response: Message = client
+                .send(DnsRequest::new(query, DnsRequestOptions::default()))
+                .first_answer()
+                .await
+                .with_context(|| format!("Bootstrap resolver {bootstrap} failed for {host}"))?
+                .into();
+            let ip = response
+                .answers()
+                .iter()
+                .find_map(|record| match record.data() {
+                    Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                    Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                    _ => None,
+                });
+            if let Some(ip) = ip {
+                self.doh_addrs.lock().await.insert(host.to_string(), ip);
+                return Ok(ip);
+            }
+        }
+        anyhow::bail!("Bootstrap resolver {bootstrap} has no address for {host}")
+    }
+
+    /// Open a one-off TCP connection to a plain DNS upstream, used when its
+    /// UDP answer came back truncated. Not cached, since truncation is rare.
+    async fn connect_tcp(addr: SocketAddr) -> Result<AsyncClient> {
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TokioTcpStream>>::new(addr);
+        let multiplexer = DnsMultiplexer::new(stream, sender, None::<Arc<NoopMessageFinalizer>>);
+        let (client, bg) = AsyncClient::connect(multiplexer).await?;
+        tokio::spawn(bg);
+        Ok(client)
+    }
+
     /// Establish a connection to an upstream resolver
     async fn connect_upstream(upstream: &Upstream) -> Result<AsyncClient> {
         let client = match upstream {
@@ -273,6 +1237,9 @@ impl DnsServer {
                 tokio::spawn(bg);
                 client
             }
+            Upstream::DoHHost { .. } => {
+                anyhow::bail!("DoH upstream {upstream} has no address yet (see bootstrap_dns)")
+            }
         };
         Ok(client)
     }
@@ -285,9 +1252,205 @@ impl DnsServer {
     }
 }
 
-/// TLS configuration for DoH upstreams, built once (root store parsing isn't free)
-fn doh_client_config() -> Arc<rustls::ClientConfig> {
-    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+/// Clamp the TTL of every answer record into `[min_ttl, max_ttl]`. Either
+/// bound may be unset; if both are set and cross, the maximum wins.
+fn clamp_answer_ttls(response: &mut Message, min_ttl: Option<u32>, max_ttl: Option<u32>) {
+    if min_ttl.is_none() && max_ttl.is_none() {
+        return;
+    }
+    for record in response.answers_mut() {
+        let mut ttl = record.ttl();
+        if let Some(min) = min_ttl {
+            ttl = ttl.max(min);
+        }
+        if let Some(max) = max_ttl {
+            ttl = ttl.min(max);
+        }
+        record.set_ttl(ttl);
+    }
+}
+
+/// Encode `message` for the wire. With `compress`, repeated names are
+/// replaced by pointers to their first occurrence (RFC 1035 4.1.4), which
+/// keeps large answer sets inside the UDP payload budget.
+fn encode_message(message: &Message, compress: bool) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(512);
+    let mut encoder = BinEncoder::new(&mut buf);
+    // Canonical names are always written out in full
+    encoder.set_canonical_names(!compress);
+    message.emit(&mut encoder)?;
+    Ok(buf)
+}
+
+/// A FORMERR answer to a packet that failed to parse, carrying the ID,
+/// opcode and RD flag of its header. None if not even the header is
+/// readable, or if the packet is itself a response (answering those could
+/// bounce packets between two servers).
+fn formerr_response(packet: &[u8]) -> Option<Message> {
+    let header = Header::from_bytes(packet.get(..12)?).ok()?;
+    if header.message_type() == MessageType::Response {
+        return None;
+    }
+    let mut response = Message::new();
+    response
+        .set_id(header.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(header.op_code())
+        .set_recursion_desired(header.recursion_desired())
+        .set_recursion_available(true)
+        .set_response_code(ResponseCode::FormErr);
+    Some(response)
+}
+
+/// Whether to log this query, given the share `rate` of queries to log
+fn sampled(rate: f64, rng: &mut impl Rng) -> bool {
+    rate >= 1.0 || (rate > 0.0 && rng.gen_bool(rate))
+}
+
+/// Explain a failed bind of the listen socket. Permission denied on a port
+/// below 1024 gets the ways around it instead of the bare OS error.
+fn bind_error(addr: SocketAddr, err: std::io::Error) -> anyhow::Error {
+    if err.kind() == std::io::ErrorKind::PermissionDenied && addr.port() < 1024 {
+        return anyhow::anyhow!(
+            "Permission denied binding {addr}: port {} is privileged. Run as root, grant the \
+             binary CAP_NET_BIND_SERVICE (sudo setcap cap_net_bind_service=+ep <path>), or \
+             set listen_port to 1024 or above (e.g. --listen {}:5353)",
+            addr.port(),
+            match addr.ip() {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("[{ip}]"),
+            }
+        );
+    }
+    anyhow::Error::new(err).context(format!("Failed to bind {addr}"))
+}
+
+/// Encode a response for UDP. If it is longer than `limit` bytes, send the
+/// header and question only, with TC set so the client retries over TCP.
+fn encode_udp_response(mut response: Message, compress: bool, limit: usize) -> Result<Vec<u8>> {
+    let bytes = encode_message(&response, compress)?;
+    if bytes.len() <= limit {
+        return Ok(bytes);
+    }
+    tracing::debug!(
+        bytes = bytes.len(),
+        limit,
+        "Response too large for UDP, truncating"
+    );
+    response.set_truncated(true);
+    response.take_answers();
+    response.take_name_servers();
+    response.take_additionals();
+    encode_message(&response, compress)
+}
+
+/// Whether `name` is the DoH canary domain (case-insensitive, trailing dot
+/// optional)
+fn is_doh_canary(name: &str) -> bool {
+    name.trim_end_matches('.')
+        .eq_ignore_ascii_case(DOH_CANARY_DOMAIN)
+}
+
+/// Remove A/AAAA answers whose address is in one of `ranges`, returning how
+/// many were removed. If that leaves no address records, the answer section
+/// is cleared (dropping any CNAME chain too) so the client gets NODATA.
+fn strip_blocked_ips(response: &mut Message, ranges: &[IpRange]) -> usize {
+    if ranges.is_empty() {
+        return 0;
+    }
+    let address = |record: &Record| match record.data() {
+        Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+        Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+        _ => None,
+    };
+
+    let answers = response.answers_mut();
+    let before = answers.len();
+    answers.retain(|record| {
+        address(record).is_none_or(|ip| !ranges.iter().any(|range| range.contains(ip)))
+    });
+    let stripped = before - answers.len();
+
+    if stripped > 0 && !answers.iter().any(|record| address(record).is_some()) {
+        answers.clear();
+        response.set_response_code(ResponseCode::NoError);
+    }
+    stripped
+}
+
+/// Remove every additional record except OPT, returning how many were
+/// removed. EDNS is normally parsed out into `extensions()` already; OPT is
+/// kept here in case it was added as a plain record.
+fn strip_additional_records(response: &mut Message) -> usize {
+    let additionals = response.additionals_mut();
+    let before = additionals.len();
+    additionals.retain(|record| record.record_type() == RecordType::OPT);
+    before - additionals.len()
+}
+
+/// A copy of `query` whose question name has each letter's case flipped at
+/// random (DNS 0x20), adding bits a spoofer must guess
+fn randomize_question_case(query: &Message) -> Message {
+    let mut randomized = query.clone();
+    let questions = randomized.take_queries();
+    let mut rng = rand::thread_rng();
+    for mut question in questions {
+        let name = question.name();
+        let labels = name.iter().map(|label| {
+            label
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_alphabetic() && rng.gen() {
+                        b ^ 0x20
+                    } else {
+                        b
+                    }
+                })
+                .collect::<Vec<u8>>()
+        });
+        // Labels taken from a valid name are valid again
+        if let Ok(mut mixed) = Name::from_labels(labels) {
+            mixed.set_fqdn(name.is_fqdn());
+            question.set_name(mixed);
+        }
+        randomized.add_query(question);
+    }
+    randomized
+}
+
+/// Put the client's question back in an answer to a case-randomized query,
+/// and its spelling on the records owned by the question name
+fn restore_question_case(response: &mut Message, query: &Message) {
+    response.take_queries();
+    response.add_queries(query.queries().to_vec());
+    let Some(name) = query.queries().first().map(|q| q.name().clone()) else {
+        return;
+    };
+    let restore = |records: &mut Vec<Record>| {
+        for record in records.iter_mut().filter(|record| *record.name() == name) {
+            record.set_name(name.clone());
+        }
+    };
+    restore(response.answers_mut());
+    restore(response.name_servers_mut());
+}
+
+/// Check the block page server accepts TCP connections at `addr`, so a
+/// typo in `block_page_ip` doesn't leave blocked sites timing out
+pub(crate) async fn check_block_page(addr: SocketAddr) -> Result<()> {
+    match tokio::time::timeout(BLOCK_PAGE_TIMEOUT, TokioTcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e).with_context(|| format!("Block page server {addr} is not reachable")),
+        Err(_) => anyhow::bail!(
+            "Block page server {addr} is not reachable: no connection within {}s",
+            BLOCK_PAGE_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+/// TLS configuration for DoH upstreams, built once (root store parsing isn't free)
+fn doh_client_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
     CONFIG
         .get_or_init(|| {
             let mut roots = rustls::RootCertStore::empty();
@@ -316,6 +1479,14 @@ impl Clone for DnsServer {
             blocklist: Arc::clone(&self.blocklist),
             upstream_clients: Arc::clone(&self.upstream_clients),
             metrics: Arc::clone(&self.metrics),
+            query_permits: Arc::clone(&self.query_permits),
+            policy: Arc::clone(&self.policy),
+            webhook: self.webhook.clone(),
+            doh_addrs: Arc::clone(&self.doh_addrs),
+            stale: self.stale.clone(),
+            cookies: self.cookies.clone(),
+            block_events: self.block_events.clone(),
+            paused_until: Arc::clone(&self.paused_until),
         }
     }
 }
@@ -323,6 +1494,1765 @@ impl Clone for DnsServer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hickory_client::client::ClientHandle;
+    use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+    use std::net::Ipv4Addr;
+    use tokio::sync::mpsc;
+
+    /// Fake UDP upstream: answers every query with an A record and reports
+    /// each request it received on the returned channel
+    async fn spawn_fake_upstream() -> (SocketAddr, mpsc::UnboundedReceiver<Message>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_available(true)
+                    .add_queries(request.queries().to_vec());
+                if let Some(question) = request.queries().first() {
+                    response.add_answer(a_record(&question.name().to_utf8(), 300));
+                }
+                socket
+                    .send_to(&response.to_bytes().unwrap(), src)
+                    .await
+                    .unwrap();
+                let _ = tx.send(request);
+            }
+        });
+        (addr, rx)
+    }
+
+    fn test_server(upstream: SocketAddr) -> DnsServer {
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap()
+    }
+
+    fn query_with_ecs(name: &str) -> Message {
+        let mut query = Message::new();
+        query.set_id(7).set_recursion_desired(true);
+        query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        query
+            .extensions_mut()
+            .get_or_insert_with(Edns::new)
+            .options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                IpAddr::from([192, 168, 1, 0]),
+                24,
+                0,
+            )));
+        query
+    }
+
+    #[tokio::test]
+    async fn test_ecs_stripped_from_forwarded_query() {
+        let (upstream, mut requests) = spawn_fake_upstream().await;
+        let server = test_server(upstream);
+
+        let response = server
+            .forward_to_upstream(&query_with_ecs("example.com."), None)
+            .await
+            .unwrap();
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.answers().len(), 1);
+
+        let forwarded = requests.recv().await.unwrap();
+        let subnet = forwarded
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Subnet));
+        assert!(subnet.is_none(), "ECS must not be forwarded: {subnet:?}");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_queries_capped() {
+        // An upstream that never answers keeps every forwarded query pending
+        let silent_upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(silent_upstream.local_addr().unwrap())];
+        config.server.max_concurrent_queries = 3;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let runner = server.clone();
+        tokio::spawn(async move { runner.run_server(listener).await });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for i in 0..10 {
+            let mut query = Message::new();
+            query.set_id(i).set_recursion_desired(true);
+            query.add_query(Query::query(
+                Name::from_str(&format!("host{i}.example.com.")).unwrap(),
+                RecordType::A,
+            ));
+            client
+                .send_to(&query.to_bytes().unwrap(), server_addr)
+                .await
+                .unwrap();
+        }
+
+        let mut peak = 0;
+        for _ in 0..20 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let in_flight = server.in_flight_queries();
+            assert!(in_flight <= 3, "{in_flight} queries in flight, cap is 3");
+            peak = peak.max(in_flight);
+        }
+        assert_eq!(peak, 3);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_query_gets_formerr_with_its_id() {
+        let server = test_server("127.0.0.1:1".parse().unwrap());
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let runner = server.clone();
+        tokio::spawn(async move { runner.run_server(listener).await });
+
+        // A valid header announcing one question, followed by a label that
+        // runs past the end of the packet
+        let mut query = Message::new();
+        query.set_id(0xbeef).set_recursion_desired(true);
+        query.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut packet = query.to_bytes().unwrap();
+        packet.truncate(12);
+        packet.extend_from_slice(&[40, b'e', b'x']);
+        assert!(Message::from_bytes(&packet).is_err());
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&packet, server_addr).await.unwrap();
+        let mut buf = vec![0u8; 512];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+            .await
+            .expect("no answer to the malformed query")
+            .unwrap();
+        let response = Message::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 0xbeef);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+        assert!(response.recursion_desired());
+
+        // Too short for a header, or a response: still dropped
+        assert!(formerr_response(&packet[..8]).is_none());
+        let mut reply = packet.clone();
+        reply[2] |= 0x80;
+        assert!(formerr_response(&reply).is_none());
+    }
+
+    #[test]
+    fn test_recv_errors_back_off_then_rebind() {
+        let mut backoff = RecvBackoff::default();
+
+        let mut previous = Duration::ZERO;
+        for _ in 1..RECV_ERRORS_BEFORE_REBIND {
+            let delay = backoff.failed();
+            assert!(delay >= RECV_BACKOFF_BASE, "must not retry immediately");
+            assert!(delay >= previous && delay <= RECV_BACKOFF_MAX);
+            assert!(!backoff.should_rebind());
+            previous = delay;
+        }
+        assert_eq!(previous, RECV_BACKOFF_MAX);
+
+        backoff.failed();
+        assert!(backoff.should_rebind());
+
+        backoff.succeeded();
+        assert!(!backoff.should_rebind());
+        assert_eq!(backoff.failed(), RECV_BACKOFF_BASE);
+    }
+
+    #[test]
+    fn test_blocked_response_per_query_type() {
+        let mut config = Config::default();
+        config.server.blocked_response = crate::config::BlockedResponse::Refused;
+        config.server.blocked_response_by_type = [
+            ("A".to_string(), crate::config::BlockedResponse::NxDomain),
+            ("https".to_string(), crate::config::BlockedResponse::NoData),
+        ]
+        .into();
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let rule = MatchKind::Exact("ads.example.com".to_string());
+        let blocked = |record_type| {
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                record_type,
+            ));
+            server.create_blocked_response(&query, &rule)
+        };
+
+        assert_eq!(
+            blocked(RecordType::A).response_code(),
+            ResponseCode::NXDomain
+        );
+
+        let https = blocked(RecordType::HTTPS);
+        assert_eq!(https.response_code(), ResponseCode::NoError);
+        assert!(https.answers().is_empty());
+        let soa = &https.name_servers()[0];
+        assert_eq!(soa.record_type(), RecordType::SOA);
+        assert_eq!(soa.name().to_utf8(), "ads.example.com.");
+        assert_eq!(soa.ttl(), BLOCKED_NEGATIVE_TTL);
+
+        // Types without an override use the global setting
+        assert_eq!(
+            blocked(RecordType::MX).response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    #[test]
+    fn test_blocked_https_query_gets_nodata_by_default() {
+        let mut config = Config::default();
+        config.server.blocked_response = crate::config::BlockedResponse::Refused;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let rule = MatchKind::Exact("ads.example.com".to_string());
+        let blocked = |record_type| {
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                record_type,
+            ));
+            server.create_blocked_response(&query, &rule)
+        };
+
+        for record_type in [RecordType::HTTPS, RecordType::SVCB] {
+            let response = blocked(record_type);
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert!(response.answers().is_empty());
+            assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+        }
+        assert_eq!(
+            blocked(RecordType::A).response_code(),
+            ResponseCode::Refused
+        );
+
+        // An explicit override still wins
+        let mut config = Config::default();
+        config.server.blocked_response_by_type =
+            [("HTTPS".to_string(), crate::config::BlockedResponse::Refused)].into();
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("ads.example.com.").unwrap(),
+            RecordType::HTTPS,
+        ));
+        assert_eq!(
+            server
+                .create_blocked_response(&query, &rule)
+                .response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_answer_served_when_upstream_fails() {
+        let mut config = Config::default();
+        // No upstream: every forward fails at once
+        config.server.upstream_dns = vec![];
+        config.server.serve_stale = Some(3600);
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let name = Name::from_str("example.org.").unwrap();
+        let mut query = Message::new();
+        query.set_id(1);
+        query.add_query(Query::query(name.clone(), RecordType::A));
+
+        // Never answered before: SERVFAIL
+        let response = server
+            .resolve_upstream(&query, "example.org.", IpAddr::from([127, 0, 0, 1]))
+            .await;
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+
+        // A TTL-0 answer is already expired, but within the stale window
+        let mut cached = DnsServer::create_rcode_response(&query, ResponseCode::NoError);
+        cached.add_answer(Record::from_rdata(
+            name,
+            0,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34).into()),
+        ));
+        server.stale.as_ref().unwrap().store(None, &query, &cached);
+
+        query.set_id(2);
+        let response = server
+            .resolve_upstream(&query, "example.org.", IpAddr::from([127, 0, 0, 1]))
+            .await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.id(), 2);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(Ipv4Addr::new(93, 184, 216, 34).into()))
+        );
+        assert_eq!(server.metrics.stale_served(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_answers_stay_on_their_route() {
+        use crate::config::ClientUpstreams;
+
+        // One route answers, the other has no upstream and fails at once
+        async fn stale_for_other_route(default_works: bool) -> ResponseCode {
+            let (upstream, _requests) = spawn_fake_upstream().await;
+            let (working, failing) = (vec![Upstream::Udp(upstream)], vec![]);
+            let mut config = Config::default();
+            config.server.serve_stale = Some(3600);
+            let (default, family) = if default_works {
+                (working, failing)
+            } else {
+                (failing, working)
+            };
+            config.server.upstream_dns = default;
+            config.server.client_upstreams = vec![ClientUpstreams {
+                clients: vec!["192.168.1.64/28".parse().unwrap()],
+                upstream_dns: family,
+            }];
+            let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+            let (default_client, family_client) = (
+                IpAddr::from([192, 168, 1, 10]),
+                IpAddr::from([192, 168, 1, 70]),
+            );
+            let (answered, failed) = if default_works {
+                (default_client, family_client)
+            } else {
+                (family_client, default_client)
+            };
+
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("example.org.").unwrap(),
+                RecordType::A,
+            ));
+            let response = server
+                .resolve_upstream(&query, "example.org.", answered)
+                .await;
+            assert_eq!(response.answers().len(), 1);
+            server
+                .resolve_upstream(&query, "example.org.", failed)
+                .await
+                .response_code()
+        }
+
+        // Neither route is served the other's stale answer
+        assert_eq!(stale_for_other_route(true).await, ResponseCode::ServFail);
+        assert_eq!(stale_for_other_route(false).await, ResponseCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cookies_are_sent_and_validated() {
+        // Upstream echoing the client cookie with a server cookie, except
+        // for forged.example.com where it answers with someone else's,
+        // stripped.example.com where it sends none, and badcookie.example.com
+        // where it wants a new server cookie first
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = Upstream::Udp(socket.local_addr().unwrap());
+        let (tx, mut sent_cookies) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let cookie = match request
+                    .extensions()
+                    .as_ref()
+                    .and_then(|edns| edns.option(EdnsCode::Cookie))
+                {
+                    Some(EdnsOption::Unknown(_, data)) => data.clone(),
+                    _ => Vec::new(),
+                };
+                let name = request.queries()[0].name().to_utf8();
+                let mut echoed = match name.as_str() {
+                    "forged.example.com." => vec![0; 8],
+                    _ => cookie.get(..8).unwrap_or_default().to_vec(),
+                };
+                let rotated = name == "badcookie.example.com.";
+                let server_cookie: &[u8] = if rotated { b"SERVER02" } else { b"SERVER01" };
+                echoed.extend_from_slice(server_cookie);
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_queries(request.queries().to_vec());
+                if rotated && cookie.get(8..) != Some(server_cookie) {
+                    response.set_response_code(ResponseCode::BADCOOKIE);
+                } else {
+                    response.add_answer(a_record(&name, 300));
+                }
+                if name != "stripped.example.com." {
+                    response
+                        .extensions_mut()
+                        .get_or_insert_with(Edns::new)
+                        .options_mut()
+                        .insert(EdnsOption::Unknown(10, echoed));
+                }
+                // Reported before answering, so it is in by the time the
+                // answer is
+                let _ = tx.send(cookie);
+                socket
+                    .send_to(&response.to_bytes().unwrap(), src)
+                    .await
+                    .unwrap();
+            }
+        });
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![upstream.clone()];
+        config.server.upstream_cookies = true;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let query = |name: &str| {
+            let mut query = Message::new();
+            query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+            query
+        };
+
+        // First query: client cookie only; the answer's server cookie is
+        // sent back from then on
+        server
+            .forward_to(&upstream, &query("example.com."))
+            .await
+            .unwrap();
+        let first = sent_cookies.recv().await.unwrap();
+        assert_eq!(first.len(), 8);
+        server
+            .forward_to(&upstream, &query("example.org."))
+            .await
+            .unwrap();
+        let second = sent_cookies.recv().await.unwrap();
+        assert_eq!(second[..8], first[..]);
+        assert_eq!(&second[8..], b"SERVER01");
+
+        // An answer that doesn't echo our client cookie is dropped
+        let err = server
+            .forward_to(&upstream, &query("forged.example.com."))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err}");
+
+        // So is one without a cookie, now that the upstream has sent one
+        let err = server
+            .forward_to(&upstream, &query("stripped.example.com."))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no DNS cookie"), "{err}");
+
+        // BADCOOKIE: asked again once, with the new server cookie
+        let response = server
+            .forward_to(&upstream, &query("badcookie.example.com."))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        let mut sent = Vec::new();
+        while let Ok(cookie) = sent_cookies.try_recv() {
+            sent.push(cookie);
+        }
+        assert_eq!(&sent.last().unwrap()[8..], b"SERVER02");
+    }
+
+    #[tokio::test]
+    async fn test_randomize_case_mixes_name_and_checks_echo() {
+        // Upstream echoing the question as sent, except for names starting
+        // with "lower" where it answers in lowercase
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = Upstream::Udp(socket.local_addr().unwrap());
+        let (tx, mut sent_names) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut question = request.queries()[0].clone();
+                let name = question.name().to_utf8();
+                if name.to_lowercase().starts_with("lower") {
+                    question.set_name(Name::from_str(&name.to_lowercase()).unwrap());
+                }
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_query(question);
+                response.add_answer(a_record(&name, 300));
+                socket
+                    .send_to(&response.to_bytes().unwrap(), src)
+                    .await
+                    .unwrap();
+                let _ = tx.send(name);
+            }
+        });
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![upstream];
+        config.server.randomize_case = true;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let query = |name: &str| {
+            let mut query = Message::new();
+            query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+            query
+        };
+
+        // 40 letters: all of them keeping their case is a 2^-40 chance
+        let asked = "averyveryverylongsubdomainname.example.com.";
+        let response = server
+            .forward_to_upstream(&query(asked), None)
+            .await
+            .unwrap();
+        let sent = sent_names.recv().await.unwrap();
+        assert_ne!(sent, asked);
+        assert!(sent.eq_ignore_ascii_case(asked));
+        // The client gets its own spelling back
+        assert_eq!(response.queries()[0].name().to_utf8(), asked);
+        assert_eq!(response.answers()[0].name().to_utf8(), asked);
+
+        // An answer that doesn't echo the case is dropped
+        let err = server
+            .forward_to_upstream(&query("lowercaseechoingupstream.example.com."), None)
+            .await
+            .unwrap_err();
+        assert!(err.is::<MismatchedQuestion>(), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_watch_client_receives_block_events() {
+        use crate::control::{watch, ControlSocket};
+
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(Config::default(), blocklist).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ctl.sock");
+        let socket = ControlSocket::bind(&path)
+            .unwrap()
+            .with_events(server.block_events());
+        tokio::spawn(socket.serve(
+            |_| async { Ok(String::new()) },
+            |_| async { anyhow::bail!("no methods") },
+        ));
+        let mut events = watch(&path).await.unwrap().unwrap();
+
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut query = Message::new();
+        query.set_id(9).add_query(Query::query(
+            Name::from_str("ads.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        server
+            .handle_query(query, client.local_addr().unwrap(), server_socket)
+            .await
+            .unwrap();
+
+        let line = tokio::time::timeout(Duration::from_secs(1), events.next_line())
+            .await
+            .expect("no block event")
+            .unwrap()
+            .unwrap();
+        let event: BlockEvent = line.parse().unwrap();
+        assert_eq!(event.domain, "ads.example.com");
+        assert_eq!(event.client_ip, client.local_addr().unwrap().ip());
+        assert_eq!(event.rule, "exact entry ads.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_clients_are_routed_to_their_upstreams() {
+        use crate::config::ClientUpstreams;
+
+        let (default_upstream, mut default_requests) = spawn_fake_upstream().await;
+        let (family_upstream, mut family_requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(default_upstream)];
+        config.server.client_upstreams = vec![ClientUpstreams {
+            clients: vec!["192.168.1.64/28".parse().unwrap()],
+            upstream_dns: vec![Upstream::Udp(family_upstream)],
+        }];
+        config.validate().unwrap();
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("example.org.").unwrap(),
+            RecordType::A,
+        ));
+        let wait = Duration::from_secs(1);
+
+        let kid = IpAddr::from([192, 168, 1, 70]);
+        let response = server.resolve_upstream(&query, "example.org.", kid).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(tokio::time::timeout(wait, family_requests.recv())
+            .await
+            .unwrap()
+            .is_some());
+
+        let laptop = IpAddr::from([192, 168, 1, 10]);
+        let response = server
+            .resolve_upstream(&query, "example.org.", laptop)
+            .await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(tokio::time::timeout(wait, default_requests.recv())
+            .await
+            .unwrap()
+            .is_some());
+
+        // Each upstream saw only its own client's query
+        assert!(family_requests.try_recv().is_err());
+        assert!(default_requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping_upstreams_reports_latency_and_failures() {
+        let (live, _requests) = spawn_fake_upstream().await;
+        // Bound and dropped: nothing answers there
+        let dead = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(live), Upstream::Udp(dead)];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let pings = server.ping_upstreams("example.com").await.unwrap();
+
+        assert_eq!(pings.len(), 2);
+        assert_eq!(pings[0].upstream, live.to_string());
+        let (latency, rcode) = pings[0].result.clone().unwrap();
+        assert!(latency > Duration::ZERO && latency < PING_TIMEOUT);
+        assert_eq!(rcode, ResponseCode::NoError);
+        assert_eq!(pings[1].upstream, dead.to_string());
+        assert!(pings[1].result.is_err());
+    }
+
+    /// A fake upstream that answers every query for `spoofed.example.`
+    async fn spawn_mismatched_upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .add_query(Query::query(
+                        Name::from_str("spoofed.example.").unwrap(),
+                        RecordType::A,
+                    ))
+                    .add_answer(a_record("spoofed.example.", 300));
+                let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_answer_to_another_question_is_rejected() {
+        let spoofed = Upstream::Udp(spawn_mismatched_upstream().await);
+        let (good, _requests) = spawn_fake_upstream().await;
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![spoofed.clone()];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let err = server.forward_to_upstream(&query, None).await.unwrap_err();
+        assert!(err.is::<MismatchedQuestion>(), "{err:#}");
+        assert!(err.to_string().contains("spoofed.example."), "{err}");
+
+        // Retried on the other upstream, whichever is picked first
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![spoofed.clone(), Upstream::Udp(good)];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        for _ in 0..4 {
+            let response = server.forward_to_upstream(&query, None).await.unwrap();
+            assert_eq!(response.queries(), query.queries());
+        }
+
+        // ...unless mismatches are to answer SERVFAIL straight away
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![spoofed, Upstream::Udp(good)];
+        config.server.on_question_mismatch = QuestionMismatch::Servfail;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let results =
+            futures::future::join_all((0..16).map(|_| server.forward_to_upstream(&query, None)))
+                .await;
+        assert!(results.iter().any(|r| r.is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_counts_outcomes_per_upstream() {
+        let (live, _requests) = spawn_fake_upstream().await;
+        // Bound and dropped: connecting there is refused
+        let dead = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let live = Upstream::Udp(live);
+        let dead = Upstream::DoH {
+            addr: dead,
+            dns_name: "localhost".to_string(),
+        };
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![live.clone(), dead.clone()];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        for _ in 0..3 {
+            server.forward_to(&live, &query).await.unwrap();
+        }
+        for _ in 0..2 {
+            assert!(server.forward_to(&dead, &query).await.is_err());
+        }
+
+        let stats: HashMap<_, _> = server.metrics().upstream_stats().into_iter().collect();
+        let live_stats = &stats[&live.to_string()];
+        assert_eq!(
+            (live_stats.sent(), live_stats.succeeded, live_stats.failed),
+            (3, 3, 0)
+        );
+        assert!(live_stats.avg_latency().unwrap() > Duration::ZERO);
+        let dead_stats = &stats[&dead.to_string()];
+        assert_eq!(
+            (dead_stats.sent(), dead_stats.succeeded, dead_stats.failed),
+            (2, 0, 2)
+        );
+        assert_eq!(dead_stats.avg_latency(), None);
+    }
+
+    #[test]
+    fn test_allowed_query_logging_is_sampled() {
+        use rand::SeedableRng;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let logged = (0..10_000).filter(|_| sampled(0.1, &mut rng)).count();
+        assert!((900..=1100).contains(&logged), "logged {logged} of 10000");
+
+        assert!((0..1000).all(|_| sampled(1.0, &mut rng)));
+        assert!(!(0..1000).any(|_| sampled(0.0, &mut rng)));
+    }
+
+    #[test]
+    fn test_bind_error_explains_privileged_port() {
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+
+        let message = bind_error("0.0.0.0:53".parse().unwrap(), denied()).to_string();
+        assert!(message.contains("privileged"), "{message}");
+        assert!(message.contains("CAP_NET_BIND_SERVICE"), "{message}");
+        assert!(message.contains("--listen 0.0.0.0:5353"), "{message}");
+        let message = bind_error("[::1]:53".parse().unwrap(), denied()).to_string();
+        assert!(message.contains("--listen [::1]:5353"), "{message}");
+
+        // Other failures keep the OS error
+        let in_use = std::io::Error::from(std::io::ErrorKind::AddrInUse);
+        let message = format!("{:#}", bind_error("0.0.0.0:53".parse().unwrap(), in_use));
+        assert!(
+            message.starts_with("Failed to bind 0.0.0.0:53: "),
+            "{message}"
+        );
+        assert!(!message.contains("CAP_NET_BIND_SERVICE"));
+    }
+
+    #[test]
+    fn test_oversized_udp_response_is_truncated() {
+        let name = Name::from_str("big.example.com.").unwrap();
+        let mut query = Message::new();
+        query.set_id(7);
+        query.add_query(Query::query(name.clone(), RecordType::A));
+
+        let mut response = DnsServer::create_rcode_response(&query, ResponseCode::NoError);
+        for i in 0..100u8 {
+            response.add_answer(Record::from_rdata(
+                name.clone(),
+                60,
+                RData::A(Ipv4Addr::new(10, 0, 0, i).into()),
+            ));
+        }
+        assert!(encode_message(&response, true).unwrap().len() > 512);
+
+        let bytes = encode_udp_response(response.clone(), true, 512).unwrap();
+        assert!(bytes.len() <= 512);
+        let truncated = Message::from_vec(&bytes).unwrap();
+        assert!(truncated.truncated());
+        assert_eq!(truncated.id(), 7);
+        assert_eq!(truncated.queries().len(), 1);
+        assert!(truncated.answers().is_empty());
+
+        // A client advertising a bigger EDNS payload gets the full answer
+        let mut edns_query = query.clone();
+        edns_query.set_edns(Edns::new());
+        edns_query
+            .extensions_mut()
+            .as_mut()
+            .unwrap()
+            .set_max_payload(4096);
+        let server = DnsServer::new(Config::default(), Arc::new(BlocklistManager::new())).unwrap();
+        assert_eq!(server.udp_response_limit(&query), 512);
+        assert_eq!(server.udp_response_limit(&edns_query), 4096);
+        let limit = server.udp_response_limit(&edns_query);
+        let full = Message::from_vec(&encode_udp_response(response, true, limit).unwrap()).unwrap();
+        assert!(!full.truncated());
+        assert_eq!(full.answers().len(), 100);
+    }
+
+    #[test]
+    fn test_blocked_authoritative_sets_header_flags() {
+        let mut query = Message::new();
+        query.set_recursion_desired(true).add_query(Query::query(
+            Name::from_str("ads.com.").unwrap(),
+            RecordType::A,
+        ));
+        let rule = MatchKind::Exact("ads.com".to_string());
+
+        let server = DnsServer::new(Config::default(), Arc::new(BlocklistManager::new())).unwrap();
+        let response = server.create_blocked_response(&query, &rule);
+        assert!(!response.authoritative());
+
+        let mut config = Config::default();
+        config.server.blocked_authoritative = true;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let response = server.create_blocked_response(&query, &rule);
+        assert!(response.authoritative());
+        assert!(response.recursion_available());
+        assert!(response.recursion_desired());
+    }
+
+    #[test]
+    fn test_blocked_explain_adds_txt_record() {
+        let mut config = Config::default();
+        config.server.blocked_explain = true;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let rule = MatchKind::Wildcard("ads.com".to_string());
+        let txt_of = |record: &Record| match record.data() {
+            Some(RData::TXT(txt)) => txt.to_string(),
+            other => panic!("expected TXT, got {other:?}"),
+        };
+
+        let mut txt_query = Message::new();
+        txt_query.add_query(Query::query(
+            Name::from_str("x.ads.com.").unwrap(),
+            RecordType::TXT,
+        ));
+        let response = server.create_blocked_response(&txt_query, &rule);
+        assert_eq!(
+            txt_of(&response.answers()[0]),
+            "blocked by skypier: wildcard *.ads.com"
+        );
+
+        let mut a_query = Message::new();
+        a_query.add_query(Query::query(
+            Name::from_str("x.ads.com.").unwrap(),
+            RecordType::A,
+        ));
+        let response = server.create_blocked_response(&a_query, &rule);
+        assert!(response.answers().is_empty());
+        assert_eq!(
+            txt_of(&response.additionals()[0]),
+            "blocked by skypier: wildcard *.ads.com"
+        );
+    }
+
+    #[test]
+    fn test_sink_answer_follows_query_type() {
+        let blocked = |sink: IpAddr, record_type| {
+            let mut config = Config::default();
+            config.set_sink(sink);
+            let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                record_type,
+            ));
+            server.create_blocked_response(&query, &MatchKind::Exact("ads.example.com".into()))
+        };
+        let v4 = IpAddr::from([0, 0, 0, 0]);
+        let v6: IpAddr = "::".parse().unwrap();
+
+        // Mismatched family: NODATA instead of a wrong-typed record
+        for response in [blocked(v6, RecordType::A), blocked(v4, RecordType::AAAA)] {
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert!(response.answers().is_empty());
+        }
+        assert!(blocked(v4, RecordType::MX).answers().is_empty());
+
+        let aaaa = blocked(v6, RecordType::AAAA);
+        assert_eq!(aaaa.answers()[0].record_type(), RecordType::AAAA);
+        assert_eq!(
+            aaaa.answers()[0].data(),
+            Some(&RData::AAAA(std::net::Ipv6Addr::UNSPECIFIED.into()))
+        );
+    }
+
+    #[test]
+    fn test_sink_override_answers_with_sink_ip() {
+        let mut config = Config::default();
+        config.set_sink(IpAddr::from([0, 0, 0, 0]));
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let mut query = Message::new();
+        query.set_id(9);
+        query.add_query(Query::query(
+            Name::from_str("ads.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let response =
+            server.create_blocked_response(&query, &MatchKind::Exact("ads.example.com".into()));
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(Ipv4Addr::UNSPECIFIED.into()))
+        );
+    }
+
+    #[test]
+    fn test_ecs_passthrough_keeps_subnet() {
+        let query = query_with_ecs("example.com.");
+
+        let stripped = DnsServer::build_upstream_query(&query, EcsPolicy::Strip).unwrap();
+        assert!(stripped
+            .extensions()
+            .as_ref()
+            .unwrap()
+            .option(EdnsCode::Subnet)
+            .is_none());
+
+        let passed = DnsServer::build_upstream_query(&query, EcsPolicy::Passthrough).unwrap();
+        assert_eq!(
+            passed
+                .extensions()
+                .as_ref()
+                .unwrap()
+                .option(EdnsCode::Subnet),
+            query
+                .extensions()
+                .as_ref()
+                .unwrap()
+                .option(EdnsCode::Subnet)
+        );
+    }
+
+    fn a_record(name: &str, ttl: u32) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            ttl,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34).into()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failure_answers_servfail() {
+        // Nothing listens on port 1, so the DoH connect is refused immediately
+        let mut config = Config::default();
+        config.server.upstream_dns = vec!["https://127.0.0.1:1/dns-query".parse().unwrap()];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut query = Message::new();
+        query.set_id(4242).set_recursion_desired(true);
+        query.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+
+        server
+            .handle_query(query, client.local_addr().unwrap(), server_socket)
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; 512];
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        let response = Message::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 4242);
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert_eq!(response.queries().len(), 1);
+    }
+
+    /// Blocks everything for one client address, nothing for anyone else
+    struct BlockClientPolicy(IpAddr);
+
+    #[async_trait::async_trait]
+    impl BlockPolicy for BlockClientPolicy {
+        async fn decide(&self, _name: &str, _qtype: RecordType, src: IpAddr) -> Decision {
+            if src == self.0 {
+                Decision::Block(MatchKind::Policy(format!("client {}", src)))
+            } else {
+                Decision::Allow
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_policy_blocks_by_client_ip() {
+        let (upstream, _requests) = spawn_fake_upstream().await;
+        let blocked_client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let other_client = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+        let server = test_server(upstream).with_policy(Arc::new(BlockClientPolicy(
+            blocked_client.local_addr().unwrap().ip(),
+        )));
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+
+        let mut responses = Vec::new();
+        for client in [&blocked_client, &other_client] {
+            let mut query = Message::new();
+            query.set_id(11).set_recursion_desired(true);
+            query.add_query(Query::query(
+                Name::from_str("example.com.").unwrap(),
+                RecordType::A,
+            ));
+            server
+                .handle_query(
+                    query,
+                    client.local_addr().unwrap(),
+                    Arc::clone(&server_socket),
+                )
+                .await
+                .unwrap();
+
+            let mut buf = vec![0u8; 512];
+            let (len, _) = client.recv_from(&mut buf).await.unwrap();
+            responses.push(Message::from_bytes(&buf[..len]).unwrap());
+        }
+
+        // The default blocked response is REFUSED
+        assert_eq!(responses[0].response_code(), ResponseCode::Refused);
+        assert_eq!(responses[1].response_code(), ResponseCode::NoError);
+        assert_eq!(responses[1].answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_block_posts_webhook_event() {
+        let mut hook = mockito::Server::new_async().await;
+        let mock = hook
+            .mock("POST", "/hook")
+            .match_body(mockito::Matcher::PartialJsonString(
+                r#"{"domain":"ads.example.com","client_ip":"127.0.0.1","rule":"exact entry ads.example.com"}"#
+                    .to_string(),
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut config = Config::default();
+        config.server.webhook_url = Some(format!("{}/hook", hook.url()));
+        config.server.webhook_domains = vec!["ads.example.com".to_string()];
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        blocklist
+            .add_domain("other.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(config, blocklist).unwrap();
+
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for name in ["other.example.com.", "ads.example.com."] {
+            let mut query = Message::new();
+            query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+            server
+                .handle_query(
+                    query,
+                    client.local_addr().unwrap(),
+                    Arc::clone(&server_socket),
+                )
+                .await
+                .unwrap();
+        }
+
+        // Delivery happens in the background
+        for _ in 0..50 {
+            if mock.matched_async().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        mock.assert_async().await;
+    }
+
+    async fn canary_response(doh_canary: bool) -> Message {
+        let (upstream, _requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        config.server.doh_canary = doh_canary;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut query = Message::new();
+        query.set_id(7).set_recursion_desired(true);
+        query.add_query(Query::query(
+            Name::from_str("Use-Application-DNS.net.").unwrap(),
+            RecordType::A,
+        ));
+        server
+            .handle_query(query, client.local_addr().unwrap(), server_socket)
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; 512];
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        Message::from_bytes(&buf[..len]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_block_any_queries_answers_minimal_hinfo() {
+        async fn any_response(block_any_queries: bool) -> (Message, bool) {
+            let (upstream, mut requests) = spawn_fake_upstream().await;
+            let mut config = Config::default();
+            config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+            config.server.block_any_queries = block_any_queries;
+            let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+            let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+            let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            let mut query = Message::new();
+            query.set_id(9).set_recursion_desired(true);
+            query.add_query(Query::query(
+                Name::from_str("example.com.").unwrap(),
+                RecordType::ANY,
+            ));
+            server
+                .handle_query(query, client.local_addr().unwrap(), server_socket)
+                .await
+                .unwrap();
+
+            let mut buf = vec![0u8; 512];
+            let (len, _) = client.recv_from(&mut buf).await.unwrap();
+            let forwarded = requests.try_recv().is_ok();
+            (Message::from_bytes(&buf[..len]).unwrap(), forwarded)
+        }
+
+        let (response, forwarded) = any_response(true).await;
+        assert!(!forwarded);
+        assert_eq!(response.id(), 9);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].record_type(), RecordType::HINFO);
+
+        // The fake upstream's A record: forwarded as usual
+        let (response, _) = any_response(false).await;
+        assert_eq!(response.answers()[0].record_type(), RecordType::A);
+    }
+
+    #[tokio::test]
+    async fn test_honor_rd_refuses_non_recursive_queries() {
+        let (upstream, mut requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        config.server.honor_rd = true;
+        config.server.serve_stale = Some(3600);
+        config.server.blocked_response = crate::config::BlockedResponse::NxDomain;
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(config, blocklist).unwrap();
+        let src: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        let query = |name: &str, rd: bool| {
+            let mut query = Message::new();
+            query.set_id(7).set_recursion_desired(rd);
+            query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+            query
+        };
+
+        let response = server.answer(query("example.com.", false), src).await;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(!response.recursion_desired());
+        assert!(requests.try_recv().is_err());
+        // Refused, not allowed
+        assert_eq!(server.metrics().allowed_queries(), 0);
+
+        // Blocked names are answered locally either way
+        let response = server.answer(query("ads.example.com.", false), src).await;
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(requests.try_recv().is_err());
+
+        let response = server.answer(query("example.com.", true), src).await;
+        assert_eq!(response.answers()[0].record_type(), RecordType::A);
+        assert_eq!(server.metrics().allowed_queries(), 1);
+        assert!(requests.try_recv().is_ok());
+
+        // Once cached, a non-recursive query gets the answer without
+        // going upstream
+        let response = server.answer(query("example.com.", false), src).await;
+        assert_eq!(response.answers()[0].record_type(), RecordType::A);
+        assert_eq!(response.id(), 7);
+        assert!(requests.try_recv().is_err());
+        assert_eq!(server.metrics().allowed_queries(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_block_page_ip_answers_address_queries() {
+        let mut config = Config::default();
+        config.server.block_page_ip = Some("192.168.1.10".parse().unwrap());
+        config.server.blocked_response = crate::config::BlockedResponse::NxDomain;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let rule = MatchKind::Exact("ads.example.com".to_string());
+        let query = |record_type| {
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                record_type,
+            ));
+            query
+        };
+
+        let response = server.create_blocked_response(&query(RecordType::A), &rule);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(Ipv4Addr::new(192, 168, 1, 10).into()))
+        );
+        // No IPv6 block page: NODATA rather than the configured NXDOMAIN
+        let response = server.create_blocked_response(&query(RecordType::AAAA), &rule);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        // Other types keep blocked_response
+        let response = server.create_blocked_response(&query(RecordType::MX), &rule);
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+        // The startup check wants something listening
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        check_block_page(addr).await.unwrap();
+        drop(listener);
+        let err = check_block_page(addr).await.unwrap_err();
+        assert!(err.to_string().contains("not reachable"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_pause_forwards_blocked_domains() {
+        let (upstream, mut requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(config, blocklist).unwrap();
+        let src: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        let query = || {
+            let mut query = Message::new();
+            query.set_id(7).set_recursion_desired(true);
+            query.add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                RecordType::A,
+            ));
+            query
+        };
+
+        let response = server.answer(query(), src).await;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(requests.try_recv().is_err());
+
+        // Paused: forwarded like any other name; clones share the state
+        server.clone().pause(Duration::from_secs(300)).unwrap();
+        assert!(server.paused_for().is_some());
+        let response = server.answer(query(), src).await;
+        assert_eq!(response.answers()[0].record_type(), RecordType::A);
+        assert!(requests.try_recv().is_ok());
+
+        assert!(server.resume());
+        assert!(!server.resume());
+        let response = server.answer(query(), src).await;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        // A pause that runs out ends by itself
+        server.pause(Duration::from_millis(20)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.paused_for(), None);
+        let response = server.answer(query(), src).await;
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        // Absurd pauses are refused instead of overflowing the clock
+        assert!(server.pause(MAX_PAUSE + Duration::from_secs(1)).is_err());
+        assert!(server.pause(Duration::from_secs(u64::MAX)).is_err());
+        assert_eq!(server.paused_for(), None);
+    }
+
+    #[tokio::test]
+    async fn test_safe_search_answers_with_cname() {
+        async fn google(safe_search: bool) -> (Message, Message) {
+            let (upstream, mut requests) = spawn_fake_upstream().await;
+            let mut config = Config::default();
+            config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+            config.server.safe_search = safe_search;
+            let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+            let mut query = Message::new();
+            query.set_id(3).set_recursion_desired(true);
+            query.add_query(Query::query(
+                Name::from_str("www.google.com.").unwrap(),
+                RecordType::A,
+            ));
+            let response = server
+                .answer(query, "127.0.0.1:5300".parse().unwrap())
+                .await;
+            (response, requests.recv().await.unwrap())
+        }
+
+        let (response, forwarded) = google(true).await;
+        assert_eq!(
+            forwarded.queries()[0].name().to_ascii(),
+            "forcesafesearch.google.com."
+        );
+        assert_eq!(response.id(), 3);
+        assert_eq!(response.queries()[0].name().to_ascii(), "www.google.com.");
+        let answers = response.answers();
+        assert_eq!(answers[0].name().to_ascii(), "www.google.com.");
+        assert_eq!(
+            answers[0].data(),
+            Some(&RData::CNAME(CNAME(
+                Name::from_str("forcesafesearch.google.com.").unwrap()
+            )))
+        );
+        assert_eq!(answers[1].name().to_ascii(), "forcesafesearch.google.com.");
+        assert_eq!(answers[1].record_type(), RecordType::A);
+
+        // Disabled: forwarded as asked
+        let (response, forwarded) = google(false).await;
+        assert_eq!(forwarded.queries()[0].name().to_ascii(), "www.google.com.");
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].record_type(), RecordType::A);
+    }
+
+    #[tokio::test]
+    async fn test_query_without_questions_gets_formerr() {
+        let server = DnsServer::new(Config::default(), Arc::new(BlocklistManager::new())).unwrap();
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut query = Message::new();
+        query.set_id(4242).set_recursion_desired(true);
+        server
+            .handle_query(query, client.local_addr().unwrap(), server_socket)
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; 512];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+            .await
+            .expect("no response to a question-less query")
+            .unwrap();
+        let response = Message::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 4242);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::FormErr);
+        assert!(response.queries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_resolves_doh_host_before_first_query() {
+        // The fake resolver answers every A query with 93.184.216.34
+        let (bootstrap, mut requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec!["https://doh.example.net/dns-query".parse().unwrap()];
+        config.server.bootstrap_dns = Some(bootstrap);
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        server.bootstrap_doh_hosts().await.unwrap();
+
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request.queries()[0].name().to_utf8(), "doh.example.net.");
+        assert_eq!(request.queries()[0].query_type(), RecordType::A);
+        let resolved = IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34));
+        assert_eq!(
+            server.doh_addrs.lock().await.get("doh.example.net"),
+            Some(&resolved)
+        );
+
+        // Cached: no second lookup
+        assert_eq!(server.doh_addr("doh.example.net").await.unwrap(), resolved);
+        assert!(requests.try_recv().is_err());
+    }
+
+    /// `io::Write` target for a test subscriber, shared with the test
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_logs_carry_span_fields() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        // The app's own formatter, as the console and log files use it
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .event_format(crate::logger::CharmFormatter::plain())
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (upstream, _requests) = spawn_fake_upstream().await;
+        let server = test_server(upstream);
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("traced.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        server
+            .handle_query(query, client.local_addr().unwrap(), server_socket)
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = output
+            .lines()
+            .find(|line| line.contains("Query received"))
+            .expect("query log line");
+        let id = regex::Regex::new(
+            r"query\{id=([0-9a-f]{8}) client=127\.0\.0\.1 domain=traced\.example\.com\.\}",
+        )
+        .unwrap();
+        assert!(id.is_match(line), "{line}");
+    }
+
+    #[tokio::test]
+    async fn test_doh_canary_answers_nxdomain_when_enabled() {
+        let response = canary_response(true).await;
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_doh_canary_forwarded_when_disabled() {
+        let response = canary_response(false).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_start_listens_on_ipv6() {
+        // Grab a free port, then let the server bind it
+        let port = UdpSocket::bind("[::1]:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = Config::default();
+        config.server.listen_addr = "::1".to_string();
+        config.server.listen_port = port;
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(config, blocklist).unwrap();
+        tokio::spawn(async move { server.start().await });
+
+        let client = UdpSocket::bind("[::1]:0").await.unwrap();
+        let mut query = Message::new();
+        query.set_id(61);
+        query.add_query(Query::query(
+            Name::from_str("ads.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut buf = vec![0u8; 512];
+        // Retry until the server has bound the socket
+        let len = loop {
+            client
+                .send_to(&query.to_bytes().unwrap(), ("::1", port))
+                .await
+                .unwrap();
+            match tokio::time::timeout(Duration::from_millis(100), client.recv_from(&mut buf)).await
+            {
+                Ok(Ok((len, _))) => break len,
+                _ => continue,
+            }
+        };
+        let response = Message::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 61);
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn test_self_test_passes_and_fails() {
+        let (upstream, _requests) = spawn_fake_upstream().await;
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("sentinel.example".to_string())
+            .await
+            .unwrap();
+        let server_with = |configure: &dyn Fn(&mut Config)| {
+            let mut config = Config::default();
+            config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+            config.server.self_test.sentinel_domain = Some("sentinel.example".to_string());
+            configure(&mut config);
+            DnsServer::new(config, Arc::clone(&blocklist)).unwrap()
+        };
+
+        server_with(&|_| {}).self_test().await.unwrap();
+
+        let err = server_with(&|c| c.server.self_test.sentinel_domain = Some("ok.example".into()))
+            .self_test()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not blocked"), "{err}");
+
+        let err = server_with(&|c| c.server.self_test.control_domain = "sentinel.example".into())
+            .self_test()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is blocked"), "{err}");
+
+        // Nothing listens on port 1, so the upstream is unreachable
+        let err = server_with(&|c| {
+            c.server.upstream_dns = vec!["https://127.0.0.1:1/dns-query".parse().unwrap()]
+        })
+        .self_test()
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("did not resolve"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_unique_clients_counted_per_source_ip() {
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(Config::default(), blocklist).unwrap();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+
+        for client in 1..=5u8 {
+            for port in [40000, 40001] {
+                let mut query = Message::new();
+                query.add_query(Query::query(
+                    Name::from_str("ads.example.com.").unwrap(),
+                    RecordType::A,
+                ));
+                let src = SocketAddr::from(([127, 0, 0, client], port));
+                server
+                    .handle_query(query, src, Arc::clone(&socket))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(server.metrics().unique_clients(), 5);
+    }
+
+    #[test]
+    fn test_strip_blocked_ips() {
+        let ranges: Vec<IpRange> = vec!["10.0.0.0/8".parse().unwrap()];
+        let record = |ip: [u8; 4]| {
+            Record::from_rdata(
+                Name::from_str("mixed.example.com.").unwrap(),
+                300,
+                RData::A(Ipv4Addr::from(ip).into()),
+            )
+        };
+
+        let mut response = Message::new();
+        response.add_answer(record([10, 1, 2, 3]));
+        response.add_answer(record([93, 184, 216, 34]));
+        assert_eq!(strip_blocked_ips(&mut response, &ranges), 1);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(Ipv4Addr::new(93, 184, 216, 34).into()))
+        );
+
+        // Every address stripped: NODATA
+        let mut response = Message::new();
+        response.add_answer(record([10, 9, 9, 9]));
+        assert_eq!(strip_blocked_ips(&mut response, &ranges), 1);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+    }
+
+    #[test]
+    fn test_strip_additional_records() {
+        let mut response = Message::new();
+        response.set_message_type(MessageType::Response);
+        response.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        response.add_answer(a_record("example.com.", 300));
+        response.add_additional(a_record("ns1.unrelated.net.", 300));
+        response.add_additional(a_record("ns2.unrelated.net.", 300));
+        response.set_edns(Edns::new());
+
+        // Round-trip through the wire format, as an upstream answer would
+        let mut response = Message::from_bytes(&response.to_bytes().unwrap()).unwrap();
+        assert_eq!(response.additionals().len(), 2);
+
+        assert_eq!(strip_additional_records(&mut response), 2);
+        assert!(response.additionals().is_empty());
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].name().to_utf8(), "example.com.");
+        assert!(response.extensions().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_answers_in_blocked_range_are_stripped() {
+        // The fake upstream answers with 93.184.216.34
+        let (upstream, _requests) = spawn_fake_upstream().await;
+        let mut config = Config::default();
+        config.server.upstream_dns = vec![Upstream::Udp(upstream)];
+        config.server.blocked_ip_ranges = vec!["93.184.216.0/24".parse().unwrap()];
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+
+        let mut query = Message::new();
+        query.set_id(3).set_recursion_desired(true);
+        query.add_query(Query::query(
+            Name::from_str("ads-cdn.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let response = server.forward_to_upstream(&query, None).await.unwrap();
+
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn test_compression_shrinks_multi_record_response() {
+        let mut response = Message::new();
+        response.add_query(Query::query(
+            Name::from_str("cdn.some-long-domain-name.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        for _ in 0..8 {
+            response.add_answer(a_record("cdn.some-long-domain-name.example.com.", 300));
+        }
+
+        let compressed = encode_message(&response, true).unwrap();
+        let uncompressed = encode_message(&response, false).unwrap();
+
+        // Each answer name shrinks from 39 bytes to a 2-byte pointer
+        assert_eq!(uncompressed.len() - compressed.len(), 8 * (39 - 2));
+        let decoded = Message::from_bytes(&compressed).unwrap();
+        assert_eq!(decoded.answers(), response.answers());
+    }
+
+    /// Upstream that answers UDP queries with TC set and no records, and
+    /// serves the full three-record answer over TCP on the same port
+    async fn spawn_truncating_upstream() -> SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+        let respond = |request: &Message, truncated: bool| {
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_truncated(truncated)
+                .add_queries(request.queries().to_vec());
+            if !truncated {
+                let name = request.queries()[0].name().to_utf8();
+                for _ in 0..3 {
+                    response.add_answer(a_record(&name, 300));
+                }
+            }
+            response.to_bytes().unwrap()
+        };
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, src)) = udp.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                udp.send_to(&respond(&request, true), src).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = tcp.accept().await {
+                let mut len = [0u8; 2];
+                conn.read_exact(&mut len).await.unwrap();
+                let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+                conn.read_exact(&mut buf).await.unwrap();
+                let response = respond(&Message::from_bytes(&buf).unwrap(), false);
+                conn.write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                conn.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_truncated_udp_answer_retried_over_tcp() {
+        let server = test_server(spawn_truncating_upstream().await);
+
+        let mut query = Message::new();
+        query.set_id(99).set_recursion_desired(true);
+        query.add_query(Query::query(
+            Name::from_str("big.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let response = server.forward_to_upstream(&query, None).await.unwrap();
+
+        assert_eq!(response.id(), 99);
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 3);
+    }
+
+    #[test]
+    fn test_clamp_answer_ttls() {
+        let mut response = Message::new();
+        response.add_answer(a_record("low.example.com.", 0));
+        response.add_answer(a_record("mid.example.com.", 300));
+        response.add_answer(a_record("high.example.com.", 604800));
+
+        clamp_answer_ttls(&mut response, Some(60), Some(3600));
+
+        let ttls: Vec<u32> = response.answers().iter().map(|r| r.ttl()).collect();
+        assert_eq!(ttls, vec![60, 300, 3600]);
+    }
+
+    #[test]
+    fn test_clamp_answer_ttls_single_bound() {
+        let mut response = Message::new();
+        response.add_answer(a_record("low.example.com.", 5));
+        response.add_answer(a_record("high.example.com.", 86400));
+
+        clamp_answer_ttls(&mut response, Some(30), None);
+
+        let ttls: Vec<u32> = response.answers().iter().map(|r| r.ttl()).collect();
+        assert_eq!(ttls, vec![30, 86400]);
+    }
 
     /// Requires network access; run with `cargo test -- --ignored`
     #[tokio::test]
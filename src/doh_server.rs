@@ -0,0 +1,299 @@
+use crate::dns::DnsServer;
+use hickory_proto::op::Message;
+use hickory_proto::serialize::binary::BinDecodable;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Path DoH clients send their queries to (RFC 8484)
+const DOH_PATH: &str = "/dns-query";
+/// Media type of a wire-format DNS message
+const DNS_MESSAGE: &str = "application/dns-message";
+/// How long a client gets to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest request line plus headers accepted
+const MAX_HEAD: u64 = 8 * 1024;
+/// Largest DNS message
+const MAX_BODY: usize = 65535;
+
+/// What came in on a connection: a DNS query (with the `X-Forwarded-For`
+/// client, if any), or why it was turned away
+enum Request {
+    Query(Vec<u8>, Option<IpAddr>),
+    Reject(u16, &'static str),
+}
+
+/// Inbound DNS-over-HTTPS (`doh_listen`): `POST /dns-query` with an
+/// `application/dns-message` body, answered through the same pipeline as
+/// UDP queries. Speaks plain HTTP/1.1, one request per connection; TLS is
+/// left to a reverse proxy in front of it, whose `X-Forwarded-For` is used
+/// if it is in `doh_trusted_proxies`. Connections share the UDP queries'
+/// `max_concurrent_queries` slots.
+pub(crate) async fn serve(server: DnsServer, listener: TcpListener) {
+    loop {
+        // As for UDP, a flood waits in the accept backlog rather than in
+        // memory
+        let permit = match server.query_permit().await {
+            Ok(permit) => permit,
+            Err(e) => {
+                tracing::warn!(error = %e, "DoH listener stopped");
+                return;
+            }
+        };
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to accept DoH connection");
+                continue;
+            }
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(&server, stream, peer).await {
+                tracing::debug!(peer = %peer, error = %e, "DoH connection failed");
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn handle(
+    server: &DnsServer,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+) -> std::io::Result<()> {
+    let (read, mut write) = stream.split();
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(read)).await {
+        Ok(request) => request?,
+        Err(_) => return Ok(()),
+    };
+    let (query, forwarded_for) = match request {
+        Request::Query(body, forwarded_for) => (Message::from_bytes(&body), forwarded_for),
+        Request::Reject(status, reason) => {
+            return write_response(&mut write, status, reason, None).await
+        }
+    };
+    let Ok(query) = query else {
+        return write_response(&mut write, 400, "Bad Request", None).await;
+    };
+    let client = match forwarded_for {
+        Some(ip) if server.trusts_proxy(peer.ip()) => SocketAddr::new(ip, peer.port()),
+        _ => peer,
+    };
+    let response = server.answer(query, client).await;
+    match response.to_vec() {
+        Ok(body) => write_response(&mut write, 200, "OK", Some((&response, &body))).await,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to encode DoH response");
+            write_response(&mut write, 500, "Internal Server Error", None).await
+        }
+    }
+}
+
+/// Read the request line, headers and body, checking it is a DoH POST
+async fn read_request(read: impl AsyncRead + Unpin) -> std::io::Result<Request> {
+    let mut head = BufReader::new(read).take(MAX_HEAD);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let path = target.split('?').next().unwrap_or_default();
+    let is_post = method == "POST";
+
+    let mut content_type = None;
+    let mut content_length = None;
+    let mut forwarded_for = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            return Ok(Request::Reject(431, "Request Header Fields Too Large"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-type") {
+                content_type = value
+                    .split(';')
+                    .next()
+                    .map(|t| t.trim().to_ascii_lowercase());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("x-forwarded-for") {
+                // The last hop is the one the proxy itself saw
+                forwarded_for = value
+                    .rsplit(',')
+                    .next()
+                    .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+            }
+        }
+    }
+
+    if path != DOH_PATH {
+        return Ok(Request::Reject(404, "Not Found"));
+    }
+    if !is_post {
+        return Ok(Request::Reject(405, "Method Not Allowed"));
+    }
+    if content_type.as_deref() != Some(DNS_MESSAGE) {
+        return Ok(Request::Reject(415, "Unsupported Media Type"));
+    }
+    let length = match content_length {
+        None => return Ok(Request::Reject(411, "Length Required")),
+        Some(length) if length > MAX_BODY => return Ok(Request::Reject(413, "Payload Too Large")),
+        Some(length) => length,
+    };
+    let mut body = vec![0; length];
+    head.into_inner().read_exact(&mut body).await?;
+    Ok(Request::Query(body, forwarded_for))
+}
+
+/// Send the status line and headers, plus the answer if there is one.
+/// Answers may be cached for their lowest TTL (RFC 8484 section 5.1).
+async fn write_response(
+    write: &mut (impl AsyncWrite + Unpin),
+    status: u16,
+    reason: &str,
+    answer: Option<(&Message, &[u8])>,
+) -> std::io::Result<()> {
+    let body = answer.map_or(&[][..], |(_, body)| body);
+    let mut head = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    if let Some((response, _)) = answer {
+        head.push_str(&format!("Content-Type: {DNS_MESSAGE}\r\n"));
+        let min_ttl = response
+            .answers()
+            .iter()
+            .chain(response.name_servers())
+            .map(|record| record.ttl())
+            .min();
+        if let Some(ttl) = min_ttl {
+            head.push_str(&format!("Cache-Control: max-age={ttl}\r\n"));
+        }
+    }
+    head.push_str("\r\n");
+    write.write_all(head.as_bytes()).await?;
+    write.write_all(body).await?;
+    write.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlocklistManager, Config};
+    use hickory_proto::op::{MessageType, Query, ResponseCode};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_post_wire_query_gets_wire_answer() {
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let server = DnsServer::new(Config::default(), blocklist).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), DOH_PATH);
+        tokio::spawn(serve(server, listener));
+
+        let mut query = Message::new();
+        query
+            .set_id(0)
+            .set_recursion_desired(true)
+            .add_query(Query::query(
+                Name::from_str("ads.example.com.").unwrap(),
+                RecordType::A,
+            ));
+        let client = reqwest::Client::new();
+        let reply = client
+            .post(&url)
+            .header("content-type", DNS_MESSAGE)
+            .header("accept", DNS_MESSAGE)
+            .body(query.to_vec().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reply.status(), 200);
+        assert_eq!(reply.headers()["content-type"], DNS_MESSAGE);
+        let response = Message::from_bytes(&reply.bytes().await.unwrap()).unwrap();
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.queries(), query.queries());
+        // The default blocked response
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        // Anything but a posted DNS message is turned away
+        let reply = client.post(&url).body("hello").send().await.unwrap();
+        assert_eq!(reply.status(), 415);
+        let reply = client.get(&url).send().await.unwrap();
+        assert_eq!(reply.status(), 405);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_client_and_query_slots() {
+        async fn client_seen(trusted: &[&str]) -> IpAddr {
+            let mut config = Config::default();
+            config.server.doh_trusted_proxies =
+                trusted.iter().map(|r| r.parse().unwrap()).collect();
+            // An unreachable upstream: only the client stats matter here
+            config.server.upstream_dns = vec![];
+            let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}{}", listener.local_addr().unwrap(), DOH_PATH);
+            tokio::spawn(serve(server.clone(), listener));
+
+            let mut query = Message::new();
+            query.add_query(Query::query(
+                Name::from_str("example.com.").unwrap(),
+                RecordType::A,
+            ));
+            let reply = reqwest::Client::new()
+                .post(&url)
+                .header("content-type", DNS_MESSAGE)
+                .header("x-forwarded-for", "198.51.100.1, 203.0.113.9")
+                .body(query.to_vec().unwrap())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(reply.status(), 200);
+            server.metrics().top_clients(1)[0].0
+        }
+
+        assert_eq!(
+            client_seen(&["127.0.0.1/32"]).await,
+            IpAddr::from([203, 0, 113, 9])
+        );
+        // Anyone can send the header; only a trusted proxy is believed
+        assert_eq!(client_seen(&[]).await, IpAddr::from([127, 0, 0, 1]));
+
+        // A connection holds a query slot until it is done
+        let mut config = Config::default();
+        config.server.max_concurrent_queries = 1;
+        let server = DnsServer::new(config, Arc::new(BlocklistManager::new())).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(server.clone(), listener));
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let url = format!("http://{}{}", addr, DOH_PATH);
+        let client = reqwest::Client::new();
+        let queued = client
+            .get(&url)
+            .timeout(Duration::from_millis(300))
+            .send()
+            .await;
+        assert!(queued.is_err(), "second connection was served");
+        drop(idle);
+        let reply = client.get(&url).send().await.unwrap();
+        assert_eq!(reply.status(), 405);
+    }
+}
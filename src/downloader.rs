@@ -1,6 +1,147 @@
 use crate::Result;
-use reqwest::Client;
+use anyhow::Context;
+use reqwest::{Client, Url};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+/// Read size when streaming a local list
+const FILE_CHUNK: usize = 64 * 1024;
+
+/// Reassembles lines from a byte stream delivered in arbitrary chunks,
+/// holding at most one partial line between chunks
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Feed a chunk, calling `on_line` for every line it completes
+    fn push(&mut self, chunk: &[u8], mut on_line: impl FnMut(&str)) {
+        let mut rest = chunk;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            let (line, tail) = rest.split_at(pos);
+            if self.pending.is_empty() {
+                on_line(&String::from_utf8_lossy(line));
+            } else {
+                self.pending.extend_from_slice(line);
+                on_line(&String::from_utf8_lossy(&self.pending));
+                self.pending.clear();
+            }
+            rest = &tail[1..];
+        }
+        self.pending.extend_from_slice(rest);
+    }
+
+    /// Flush the last line if the stream did not end with a newline
+    fn finish(self, mut on_line: impl FnMut(&str)) {
+        if !self.pending.is_empty() {
+            on_line(&String::from_utf8_lossy(&self.pending));
+        }
+    }
+}
+
+/// Where a list is fetched from, by the scheme of its location
+#[derive(Debug, PartialEq, Eq)]
+enum ListLocation {
+    Http,
+    /// A `file://` URL, or a path without a scheme. `Config::load` makes
+    /// relative `remote_lists` paths absolute against the config file's
+    /// directory; others (e.g. given to `check-source`) resolve against the
+    /// working directory.
+    File(PathBuf),
+}
+
+impl ListLocation {
+    fn of(url: &str) -> Result<Self> {
+        let Some((scheme, _)) = url.split_once("://") else {
+            return Ok(ListLocation::File(PathBuf::from(url)));
+        };
+        match scheme.to_ascii_lowercase().as_str() {
+            "http" | "https" => Ok(ListLocation::Http),
+            "file" => Url::parse(url)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .map(ListLocation::File)
+                .with_context(|| format!("Invalid file URL {url}: expected file:///absolute/path")),
+            other => anyhow::bail!(
+                "Unsupported scheme '{other}' in {url}: lists can be fetched over http(s):// \
+                 or read from file:// URLs and local paths"
+            ),
+        }
+    }
+}
+
+/// Line syntax of a blocklist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListFormat {
+    /// One domain per line
+    Plain,
+    /// `0.0.0.0 domain` / `127.0.0.1 domain` (or the IPv6 sinks `::`/`::1`)
+    Hosts,
+    /// `||domain^` rules
+    AdBlock,
+    /// `/pattern/` rules
+    Regex,
+}
+
+impl std::fmt::Display for ListFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ListFormat::Plain => "plain",
+            ListFormat::Hosts => "hosts",
+            ListFormat::AdBlock => "adblock",
+            ListFormat::Regex => "regex",
+        })
+    }
+}
+
+/// Statistics gathered while parsing a downloaded blocklist
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceReport {
+    /// HTTP status code of the download, 0 for a local file
+    pub status: u16,
+    /// Total lines, including comments and blanks
+    pub lines: usize,
+    /// Blank, comment and header lines
+    pub comments: usize,
+    /// Rule lines that yielded at least one entry
+    pub valid: usize,
+    /// Rule lines that yielded nothing (invalid domains, unsupported rules)
+    pub skipped: usize,
+    /// Entries produced (`||domain^` yields two)
+    pub entries: usize,
+    /// Rule lines per syntax
+    pub formats: HashMap<ListFormat, usize>,
+}
+
+impl SourceReport {
+    /// Parse one line, appending its entries to `domains` and counting it
+    fn record(&mut self, line: &str, domains: &mut Vec<String>) {
+        self.lines += 1;
+        let before = domains.len();
+        match BlocklistDownloader::parse_line(line, domains) {
+            None => self.comments += 1,
+            Some(format) => {
+                *self.formats.entry(format).or_default() += 1;
+                if domains.len() > before {
+                    self.valid += 1;
+                } else {
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+
+    /// The most common rule syntax, or None if there were no rules
+    pub fn detected_format(&self) -> Option<ListFormat> {
+        self.formats
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(format, _)| *format)
+    }
+}
 
 /// Downloader for remote blocklists
 pub struct BlocklistDownloader {
@@ -21,20 +162,54 @@ impl BlocklistDownloader {
     /// Download a blocklist from a URL
     /// Returns a vector of domain strings
     pub async fn download(&self, url: &str) -> Result<Vec<String>> {
-        tracing::info!("Downloading blocklist from: {}", url);
+        let (domains, _) = self.download_with_report(url).await?;
+        Ok(domains)
+    }
 
-        let response = self.client.get(url).send().await?;
+    /// Download a blocklist and also report what was found in it: HTTP
+    /// status, detected format, and how many lines were used or skipped.
+    /// `http(s)://` URLs are fetched, `file://` URLs and plain paths read
+    /// from disk; any other scheme (e.g. `ftp://`) is an error.
+    pub async fn download_with_report(&self, url: &str) -> Result<(Vec<String>, SourceReport)> {
+        let location = ListLocation::of(url)?;
+        tracing::info!("Downloading blocklist from: {}", url);
 
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to download blocklist: HTTP {}", response.status());
+        // Parse the body as it arrives instead of buffering all of it, so
+        // memory stays bounded by the parsed entries plus one chunk
+        let mut report = SourceReport::default();
+        let mut lines = LineBuffer::default();
+        let mut domains = Vec::new();
+        match location {
+            ListLocation::Http => {
+                let mut response = self.client.get(url).send().await?;
+                if !response.status().is_success() {
+                    anyhow::bail!("Failed to download blocklist: HTTP {}", response.status());
+                }
+                report.status = response.status().as_u16();
+                while let Some(chunk) = response.chunk().await? {
+                    lines.push(&chunk, |line| report.record(line, &mut domains));
+                }
+            }
+            ListLocation::File(path) => {
+                let mut file = tokio::fs::File::open(&path)
+                    .await
+                    .with_context(|| format!("Failed to open blocklist {}", path.display()))?;
+                let mut chunk = vec![0; FILE_CHUNK];
+                loop {
+                    let len = file.read(&mut chunk).await?;
+                    if len == 0 {
+                        break;
+                    }
+                    lines.push(&chunk[..len], |line| report.record(line, &mut domains));
+                }
+            }
         }
-
-        let content = response.text().await?;
-        let domains = Self::parse_blocklist(&content);
+        lines.finish(|line| report.record(line, &mut domains));
+        report.entries = domains.len();
 
         tracing::info!("Downloaded {} domains from {}", domains.len(), url);
 
-        Ok(domains)
+        Ok((domains, report))
     }
 
     /// Parse a blocklist file content
@@ -42,48 +217,145 @@ impl BlocklistDownloader {
     /// - Plain domain list (one per line)
     /// - Hosts file format (0.0.0.0 domain.com)
     /// - Hosts file format (127.0.0.1 domain.com)
+    /// - Hosts file format with an IPv6 sink (:: domain.com, ::1 domain.com)
     /// - Comments starting with #
-    fn parse_blocklist(content: &str) -> Vec<String> {
+    /// - AdBlock-style `||domain.com^` rules (domain and its subdomains)
+    /// - Regex rules `/pattern/`, passed through verbatim
+    /// - Allow rules `@@domain.com` / `@@*.domain.com`
+    pub(crate) fn parse_blocklist(content: &str) -> Vec<String> {
         let mut domains = Vec::new();
-
         for line in content.lines() {
-            let line = line.trim();
+            Self::parse_line(line, &mut domains);
+        }
+        domains
+    }
 
-            // Skip empty lines and comments
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
+    /// Parse one blocklist line (see `parse_blocklist`), appending the
+    /// entries it yields to `domains`. Returns the line's syntax, or None
+    /// for blank lines, comments and headers.
+    pub(crate) fn parse_line(line: &str, domains: &mut Vec<String>) -> Option<ListFormat> {
+        let line = line.trim();
 
-            // Parse different formats
-            let domain = if line.starts_with("0.0.0.0 ") {
-                // Hosts format: 0.0.0.0 domain.com
-                line.trim_start_matches("0.0.0.0 ").trim()
-            } else if line.starts_with("127.0.0.1 ") {
-                // Hosts format: 127.0.0.1 domain.com
-                line.trim_start_matches("127.0.0.1 ").trim()
-            } else if line.contains(' ') {
-                // Generic hosts format: IP domain.com
-                // Take the second token (domain)
-                match line.split_whitespace().nth(1) {
-                    Some(d) => d,
-                    None => line,
+        // Skip empty lines and comments (including AdBlock `!` comments
+        // and `[Adblock Plus 2.0]` headers)
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            return None;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            return None;
+        }
+
+        // Regex rule: kept as-is for the blocklist's regex matcher, which
+        // validates the pattern
+        if line.len() > 2 && line.starts_with('/') && line.ends_with('/') {
+            domains.push(line.to_string());
+            return Some(ListFormat::Regex);
+        }
+
+        // AdBlock rules: only plain `||domain^` applies at the DNS level;
+        // exceptions (`@@||`) and rules with `$` modifiers are skipped
+        if let Some(rule) = line.strip_prefix("||") {
+            if let Some(domain) = rule.strip_suffix('^') {
+                let domain = domain.to_lowercase();
+                if Self::is_valid_domain(&domain) {
+                    domains.push(format!("*.{}", domain));
+                    domains.push(domain);
                 }
-            } else {
-                // Plain domain
-                line
-            };
+            }
+            return Some(ListFormat::AdBlock);
+        }
+        if let Some(rule) = line.strip_prefix("@@") {
+            if rule.starts_with("||") {
+                return Some(ListFormat::AdBlock);
+            }
+            // Allow rule: @@domain.com or @@*.domain.com
+            if let Some(domain) = Self::normalize_domain(rule) {
+                domains.push(format!("@@{}", domain));
+            }
+            return Some(ListFormat::Plain);
+        }
 
-            // Validate domain and add
-            if !domain.is_empty() && Self::is_valid_domain(domain) {
-                domains.push(domain.to_lowercase());
+        // Parse different formats
+        let format = if line.contains(' ') {
+            ListFormat::Hosts
+        } else {
+            ListFormat::Plain
+        };
+        let domain = if line.starts_with("0.0.0.0 ") {
+            // Hosts format: 0.0.0.0 domain.com
+            line.trim_start_matches("0.0.0.0 ").trim()
+        } else if line.starts_with("127.0.0.1 ") {
+            // Hosts format: 127.0.0.1 domain.com
+            line.trim_start_matches("127.0.0.1 ").trim()
+        } else if let Some(rest) = line
+            .strip_prefix(":: ")
+            .or_else(|| line.strip_prefix("::1 "))
+        {
+            // Hosts format with an IPv6 sink: :: domain.com / ::1 domain.com
+            rest.trim()
+        } else if line.contains(' ') {
+            // Generic hosts format: IP domain.com
+            // Take the second token (domain)
+            match line.split_whitespace().nth(1) {
+                Some(d) => d,
+                None => line,
             }
+        } else {
+            // Plain domain
+            line
+        };
+
+        // Clean up and validate the domain, then add it
+        if let Some(domain) = Self::normalize_domain(domain) {
+            domains.push(domain);
         }
+        Some(format)
+    }
 
+    /// Sinkholed names from a hosts file: every name on a line mapped to
+    /// 0.0.0.0, 127.0.0.1, `::` or `::1`. Lines pointing at any other address
+    /// are real host mappings and are skipped, as are localhost names.
+    pub(crate) fn parse_hosts_sinks(content: &str) -> Vec<String> {
+        let mut domains = Vec::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            if !matches!(fields.next(), Some("0.0.0.0" | "127.0.0.1" | "::" | "::1")) {
+                continue;
+            }
+            domains.extend(fields.filter_map(Self::normalize_domain));
+        }
         domains
     }
 
+    /// Clean up an entry pasted in URL form: lowercase it and strip a scheme
+    /// (`https://`), anything after the host (path, query, port) and leading
+    /// or trailing dots, so `https://Ads.Example.com./x` yields
+    /// `ads.example.com`. Internationalized names are converted to punycode
+    /// (`bücher.de` -> `xn--bcher-kva.de`). None if what remains is not a
+    /// valid domain.
+    pub(crate) fn normalize_domain(entry: &str) -> Option<String> {
+        let mut domain = entry.trim().to_lowercase();
+        if let Some((_, rest)) = domain.split_once("://") {
+            domain = rest.to_string();
+        }
+        if let Some(end) = domain.find(['/', '?', '#']) {
+            domain.truncate(end);
+        }
+        if let Some((host, port)) = domain.rsplit_once(':') {
+            if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) {
+                domain.truncate(host.len());
+            }
+        }
+        if !domain.is_ascii() {
+            domain = hickory_proto::rr::Name::from_utf8(&domain).ok()?.to_ascii();
+        }
+        let domain = domain.trim_matches('.');
+        Self::is_valid_domain(domain).then(|| domain.to_string())
+    }
+
     /// Basic domain validation
-    fn is_valid_domain(domain: &str) -> bool {
+    pub(crate) fn is_valid_domain(domain: &str) -> bool {
         // Skip localhost and special domains
         if domain == "localhost"
             || domain.starts_with("localhost.")
@@ -114,15 +386,33 @@ impl BlocklistDownloader {
             .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '*')
     }
 
-    /// Download multiple blocklists and merge them
+    /// Concatenate lists in order, keeping the first occurrence of each
+    /// entry. Domains and wildcards compare case-insensitively; regex rules
+    /// exactly, since their case is significant. `*.ex.com` and `ex.com`
+    /// are different rules and both stay.
+    pub(crate) fn merge_lists(lists: Vec<Vec<String>>) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut merged = Vec::new();
+        for entry in lists.into_iter().flatten() {
+            let key = if entry.starts_with('/') {
+                entry.clone()
+            } else {
+                entry.to_ascii_lowercase()
+            };
+            if seen.insert(key) {
+                merged.push(entry);
+            }
+        }
+        merged
+    }
+
+    /// Download multiple blocklists and merge them (see `merge_lists`)
     pub async fn download_multiple(&self, urls: &[String]) -> Result<Vec<String>> {
-        let mut all_domains = Vec::new();
+        let mut lists = Vec::new();
 
         for url in urls {
             match self.download(url).await {
-                Ok(mut domains) => {
-                    all_domains.append(&mut domains);
-                }
+                Ok(domains) => lists.push(domains),
                 Err(e) => {
                     tracing::error!("Failed to download from {}: {}", url, e);
                     // Continue with other URLs
@@ -130,9 +420,7 @@ impl BlocklistDownloader {
             }
         }
 
-        // Deduplicate
-        all_domains.sort();
-        all_domains.dedup();
+        let all_domains = Self::merge_lists(lists);
 
         tracing::info!("Total unique domains downloaded: {}", all_domains.len());
 
@@ -144,6 +432,118 @@ impl BlocklistDownloader {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_download_with_report_counts_lines() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/hosts")
+            .with_body(
+                "# header\n\n0.0.0.0 ads.example.com\n0.0.0.0 tracker.example.net\n\
+                 0.0.0.0 localhost\n127.0.0.1 bad_domain!\nplain.example.org\n",
+            )
+            .create_async()
+            .await;
+
+        let downloader = BlocklistDownloader::new().unwrap();
+        let (domains, report) = downloader
+            .download_with_report(&format!("{}/hosts", server.url()))
+            .await
+            .unwrap();
+        mock.assert_async().await;
+
+        assert_eq!(report.status, 200);
+        assert_eq!(report.lines, 7);
+        assert_eq!(report.comments, 2);
+        assert_eq!(report.valid, 3);
+        assert_eq!(report.skipped, 2);
+        assert_eq!(report.entries, domains.len());
+        assert_eq!(report.detected_format(), Some(ListFormat::Hosts));
+    }
+
+    #[tokio::test]
+    async fn test_download_reads_file_urls_and_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("list.txt");
+        std::fs::write(
+            &path,
+            "# local list\n0.0.0.0 ads.example.com\ntracker.net\n",
+        )
+        .unwrap();
+
+        let downloader = BlocklistDownloader::new().unwrap();
+        let url = Url::from_file_path(&path).unwrap().to_string();
+        assert!(url.starts_with("file:///"));
+        let (domains, report) = downloader.download_with_report(&url).await.unwrap();
+        assert_eq!(domains, ["ads.example.com", "tracker.net"]);
+        assert_eq!(report.status, 0);
+        assert_eq!(report.lines, 3);
+
+        let from_path = downloader.download(&path.display().to_string()).await;
+        assert_eq!(from_path.unwrap(), domains);
+        let missing = format!("file://{}", dir.path().join("missing.txt").display());
+        assert!(downloader.download(&missing).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_rejects_unsupported_scheme() {
+        let downloader = BlocklistDownloader::new().unwrap();
+        let err = downloader
+            .download("ftp://lists.example.com/hosts.txt")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Unsupported scheme 'ftp'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_merge_lists_keeps_first_seen_order() {
+        let list = |entries: &[&str]| entries.iter().map(|e| e.to_string()).collect();
+        let merged = BlocklistDownloader::merge_lists(vec![
+            list(&["zeta.com", "*.ex.com", "Ads.Example.com", "/Track/"]),
+            list(&[
+                "ex.com",
+                "ads.example.com",
+                "alpha.com",
+                "/track/",
+                "ZETA.com",
+            ]),
+        ]);
+        assert_eq!(
+            merged,
+            [
+                "zeta.com",
+                "*.ex.com",
+                "Ads.Example.com",
+                "/Track/",
+                "ex.com",
+                "alpha.com",
+                "/track/",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_buffer_matches_whole_content_parse() {
+        let content = "# hosts\r\n0.0.0.0 ads.example.com\r\ntracker.net\n||adblock.org^\nlast.com";
+        let expected = BlocklistDownloader::parse_blocklist(content);
+
+        // Every split point, including mid-line and mid-CRLF
+        for split in 0..content.len() {
+            let (a, b) = content.as_bytes().split_at(split);
+            let mut lines = LineBuffer::default();
+            let mut domains = Vec::new();
+            let mut parse = |line: &str| {
+                BlocklistDownloader::parse_line(line, &mut domains);
+            };
+            lines.push(a, &mut parse);
+            lines.push(b, &mut parse);
+            lines.finish(parse);
+            assert_eq!(domains, expected, "split at {split}");
+        }
+    }
+
     #[test]
     fn test_parse_plain_domains() {
         let content = r#"
@@ -172,6 +572,56 @@ malware.example.com
         assert!(domains.contains(&"tracker.example.com".to_string()));
     }
 
+    #[test]
+    fn test_parse_hosts_format_ipv6_sinks() {
+        let content = ":: ads.example.com\n::1 tracker.example.com\n::1 localhost\n";
+
+        let domains = BlocklistDownloader::parse_blocklist(content);
+        assert_eq!(
+            domains,
+            vec![
+                "ads.example.com".to_string(),
+                "tracker.example.com".to_string()
+            ]
+        );
+        let mut parsed = Vec::new();
+        assert_eq!(
+            BlocklistDownloader::parse_line(":: ads.example.com", &mut parsed),
+            Some(ListFormat::Hosts)
+        );
+
+        let domains = BlocklistDownloader::parse_hosts_sinks(content);
+        assert_eq!(
+            domains,
+            vec![
+                "ads.example.com".to_string(),
+                "tracker.example.com".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_hosts_sinks_skips_real_mappings() {
+        let content = r#"
+127.0.0.1 localhost
+127.0.1.1 workstation.lan
+::1 localhost ip6-localhost ip6-loopback
+192.168.1.10 nas.home.example
+0.0.0.0 ads.example.com tracker.example.com # sinkholed
+127.0.0.1 malware.example.net
+"#;
+
+        let domains = BlocklistDownloader::parse_hosts_sinks(content);
+        assert_eq!(
+            domains,
+            vec![
+                "ads.example.com".to_string(),
+                "tracker.example.com".to_string(),
+                "malware.example.net".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_mixed_format() {
         let content = r#"
@@ -215,4 +665,85 @@ good.example.com
         assert!(!BlocklistDownloader::is_valid_domain("invalid"));
         assert!(!BlocklistDownloader::is_valid_domain(""));
     }
+
+    #[test]
+    fn test_normalize_domain_cleans_pasted_entries() {
+        let cases = [
+            ("https://ads.example.com./", "ads.example.com"),
+            (
+                "http://Tracker.Example.NET/pixel.gif?id=1",
+                "tracker.example.net",
+            ),
+            ("ads.example.com.", "ads.example.com"),
+            (".ads.example.com", "ads.example.com"),
+            ("https://ads.example.com:8443/path", "ads.example.com"),
+            ("  metrics.example.org#frag ", "metrics.example.org"),
+            ("*.example.com.", "*.example.com"),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(
+                BlocklistDownloader::normalize_domain(entry).as_deref(),
+                Some(expected),
+                "{entry}"
+            );
+        }
+        for entry in ["https://localhost/", "http://10.0.0.1/", "https://", "..."] {
+            assert_eq!(
+                BlocklistDownloader::normalize_domain(entry),
+                None,
+                "{entry}"
+            );
+        }
+
+        let domains = BlocklistDownloader::parse_blocklist(
+            "https://ads.example.com./\n0.0.0.0 tracker.example.net.\n",
+        );
+        assert_eq!(domains, vec!["ads.example.com", "tracker.example.net"]);
+    }
+
+    #[test]
+    fn test_parse_adblock_format() {
+        let content = r#"
+[Adblock Plus 2.0]
+! Title: test filters
+||ads.example.com^
+||tracker.example.com^$third-party
+@@||allowed.example.com^
+/^ad[0-9]+\.example\.com$/
+"#;
+
+        let domains = BlocklistDownloader::parse_blocklist(content);
+        assert_eq!(
+            domains,
+            vec![
+                "*.ads.example.com".to_string(),
+                "ads.example.com".to_string(),
+                r"/^ad[0-9]+\.example\.com$/".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_downloaded_regex_rule_blocks_query() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/filters.txt")
+            .with_body("! filters\n||tracker.example.org^\n/^ads[0-9]+\\.example\\.com$/\n/(bad/\n")
+            .create_async()
+            .await;
+
+        let downloader = BlocklistDownloader::new().unwrap();
+        let domains = downloader
+            .download(&format!("{}/filters.txt", server.url()))
+            .await
+            .unwrap();
+        mock.assert_async().await;
+
+        let manager = crate::BlocklistManager::new();
+        manager.load_domains(domains).await.unwrap();
+        assert!(manager.is_blocked("ads7.example.com").await);
+        assert!(!manager.is_blocked("www.example.com").await);
+        assert!(manager.is_blocked("tracker.example.org").await);
+        assert!(manager.is_blocked("cdn.tracker.example.org").await);
+    }
 }
@@ -1,21 +1,30 @@
 mod blocklist;
 mod cli;
 mod config;
+mod control;
+mod cookie;
 mod dns;
+mod doh_server;
 mod downloader;
 mod loader;
 mod logger;
 mod metrics;
+mod pidfile;
+mod policy;
+mod safe_search;
 mod scheduler;
+mod stale;
 pub mod tui;
+mod webhook;
 
-pub use blocklist::BlocklistManager;
-pub use cli::Cli;
+pub use blocklist::{AddReport, BlocklistManager, MatchKind, RuleCounts, WildcardCoverage};
+pub use cli::{Cli, EXIT_BLOCKED, EXIT_ERROR, EXIT_STOPPED};
 pub use config::{get_default_config_path, Config};
 pub use dns::DnsServer;
-pub use downloader::BlocklistDownloader;
+pub use downloader::{BlocklistDownloader, ListFormat, SourceReport};
 pub use logger::setup_logging;
-pub use metrics::RuntimeMetrics;
+pub use metrics::{ClientStats, RuntimeMetrics, UpstreamStats};
+pub use policy::{BlockPolicy, BlocklistPolicy, Decision};
 pub use scheduler::UpdateScheduler;
 
 pub type Result<T> = std::result::Result<T, anyhow::Error>;
@@ -1,11 +1,20 @@
-use crate::{BlocklistManager, Config, Result};
+use crate::{BlocklistDownloader, BlocklistManager, Config, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Kind of blocklist source
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SourceKind {
     Custom,
     Local,
+    Suffix,
+    Hosts,
     RemoteCache,
 }
 
@@ -14,6 +23,8 @@ impl SourceKind {
         match self {
             SourceKind::Custom => "custom",
             SourceKind::Local => "local",
+            SourceKind::Suffix => "suffix",
+            SourceKind::Hosts => "hosts",
             SourceKind::RemoteCache => "remote cache",
         }
     }
@@ -24,29 +35,387 @@ impl SourceKind {
 pub struct SourceSummary {
     pub kind: SourceKind,
     pub path: PathBuf,
+    /// Category the source's entries are tagged with, if any
+    pub category: Option<String>,
     /// Number of domain entries, or None if the file is missing
     pub domains: Option<usize>,
 }
 
+/// The entries last read from each source file, kept by the running server
+/// so a reload only re-reads the files whose modification time (or size)
+/// changed since, reusing the rest. Holds a second copy of the entries,
+/// shared rather than cloned with each reload that reads them.
+#[derive(Default)]
+pub struct SourceCache {
+    sources: Mutex<HashMap<PathBuf, CachedSource>>,
+}
+
+struct CachedSource {
+    modified: SystemTime,
+    len: u64,
+    entries: Arc<[String]>,
+}
+
+impl SourceCache {
+    /// The entries of a source, read from disk only if it changed since the
+    /// last call
+    fn read(&self, kind: SourceKind, path: &Path) -> Result<Arc<[String]>> {
+        let metadata = std::fs::metadata(path)?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+        let mut sources = self.sources.lock().unwrap();
+        if let Some(cached) = sources
+            .get(path)
+            .filter(|c| c.modified == modified && c.len == len)
+        {
+            tracing::info!(
+                "{} blocklist {} unchanged, not re-reading it",
+                kind.label(),
+                path.display()
+            );
+            return Ok(Arc::clone(&cached.entries));
+        }
+        tracing::info!("Loading {} blocklist from {}", kind.label(), path.display());
+        let entries: Arc<[String]> = read_source(kind, path)?.into();
+        sources.insert(
+            path.to_path_buf(),
+            CachedSource {
+                modified,
+                len,
+                entries: Arc::clone(&entries),
+            },
+        );
+        Ok(entries)
+    }
+
+    /// Drop the entries of files that are no longer loaded
+    fn retain(&self, loaded: &[SourceSummary]) {
+        self.sources.lock().unwrap().retain(|path, _| {
+            loaded
+                .iter()
+                .any(|s| &s.path == path && s.domains.is_some())
+        });
+    }
+}
+
 /// Path of the cache file where downloaded remote lists are stored
 /// (same directory as the custom list)
 pub fn remote_cache_path(config: &Config) -> PathBuf {
+    remote_cache_path_for(config, None)
+}
+
+/// Path of the cache file for the remote lists of one category (`None` for
+/// untagged lists, see `remote_cache_path`). With `compress_cache` the file
+/// is gzipped and gets a `.gz` suffix.
+pub fn remote_cache_path_for(config: &Config, category: Option<&str>) -> PathBuf {
+    let name = match category {
+        None => "remote-blocklist-cache.txt".to_string(),
+        Some(category) => {
+            let safe: String = category
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("remote-blocklist-cache.{}.txt", safe)
+        }
+    };
+    let name = if config.blocklist.compress_cache {
+        name + ".gz"
+    } else {
+        name
+    };
+    Path::new(&config.blocklist.custom_list)
+        .parent()
+        .unwrap_or(Path::new("/tmp"))
+        .join(name)
+}
+
+/// The same cache file with the other compression setting
+fn other_cache_variant(path: &Path) -> PathBuf {
+    let name = path.to_string_lossy();
+    match name.strip_suffix(".gz") {
+        Some(plain) => PathBuf::from(plain),
+        None => PathBuf::from(format!("{}.gz", name)),
+    }
+}
+
+/// The cache file to load for `category`: the configured variant, or the
+/// other one if only that exists (`compress_cache` was switched since the
+/// last update)
+fn existing_remote_cache(config: &Config, category: Option<&str>) -> PathBuf {
+    let path = remote_cache_path_for(config, category);
+    let other = other_cache_variant(&path);
+    if !path.exists() && other.exists() {
+        other
+    } else {
+        path
+    }
+}
+
+/// Save downloaded remote domains to the cache file for `category`,
+/// returning its path. A leftover cache with the other compression setting
+/// is removed so it can't be loaded instead.
+pub fn write_remote_cache(
+    config: &Config,
+    category: Option<&str>,
+    domains: &[String],
+) -> Result<PathBuf> {
+    let path = remote_cache_path_for(config, category);
+    let mut content = domains.join("\n");
+    content.push('\n');
+    if config.blocklist.compress_cache {
+        let mut encoder = GzEncoder::new(
+            BufWriter::new(File::create(&path)?),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(content.as_bytes())?;
+        encoder.finish()?.flush()?;
+    } else {
+        std::fs::write(&path, content)?;
+    }
+    match std::fs::remove_file(other_cache_variant(&path)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(path)
+}
+
+/// Current size of the cache for `category` if replacing it with `new_len`
+/// domains would shrink it below `updater.min_update_ratio`, in which case
+/// the download is suspect and the cache should be kept
+pub fn suspicious_shrink(config: &Config, category: Option<&str>, new_len: usize) -> Option<usize> {
+    let ratio = config.updater.min_update_ratio;
+    if ratio <= 0.0 {
+        return None;
+    }
+    let current = count_domains(&existing_remote_cache(config, category))?;
+    ((new_len as f64) < current as f64 * ratio).then_some(current)
+}
+
+/// Path of the list of categories switched off with `category disable`
+/// (same directory as the custom list), one name per line
+pub fn disabled_categories_path(config: &Config) -> PathBuf {
+    Path::new(&config.blocklist.custom_list)
+        .parent()
+        .unwrap_or(Path::new("/tmp"))
+        .join("disabled-categories.txt")
+}
+
+/// Files kept next to the custom list under fixed names (see
+/// `Config::validate`)
+pub fn reserved_paths(config: &Config) -> Vec<PathBuf> {
+    let mut paths = vec![
+        index_path(config),
+        temporary_list_path(config),
+        disabled_categories_path(config),
+    ];
+    let caches = std::iter::once(None).chain(config.blocklist.categories().into_iter().map(Some));
+    for category in caches {
+        let cache = remote_cache_path_for(config, category.as_deref());
+        paths.push(other_cache_variant(&cache));
+        paths.push(cache);
+    }
+    paths
+}
+
+/// Categories currently switched off
+pub fn read_disabled_categories(config: &Config) -> Result<BTreeSet<String>> {
+    match std::fs::read_to_string(disabled_categories_path(config)) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter(|line| is_entry(line))
+            .map(|line| line.trim().to_string())
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Switch a category on or off in the persisted state. Returns false if it
+/// was already in that state.
+pub fn set_category_enabled(config: &Config, category: &str, enabled: bool) -> Result<bool> {
+    let mut disabled = read_disabled_categories(config)?;
+    let changed = if enabled {
+        disabled.remove(category)
+    } else {
+        disabled.insert(category.to_string())
+    };
+    if changed {
+        let path = disabled_categories_path(config);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content: String = disabled.iter().map(|c| format!("{}\n", c)).collect();
+        std::fs::write(&path, content)?;
+    }
+    Ok(changed)
+}
+
+/// Path of the compiled blocklist index (same directory as the custom list)
+pub fn index_path(config: &Config) -> PathBuf {
     Path::new(&config.blocklist.custom_list)
         .parent()
         .unwrap_or(Path::new("/tmp"))
-        .join("remote-blocklist-cache.txt")
+        .join("blocklist-index.bin")
+}
+
+/// Path of the temporary block list written by `block --for` (same
+/// directory as the custom list). Each line is `<domain> <unix expiry>`.
+pub fn temporary_list_path(config: &Config) -> PathBuf {
+    Path::new(&config.blocklist.custom_list)
+        .parent()
+        .unwrap_or(Path::new("/tmp"))
+        .join("temporary-blocklist.txt")
+}
+
+/// Parse a temporary block list, keeping only well-formed, unexpired entries
+fn read_temporary(path: &Path, now: SystemTime) -> Result<Vec<(String, SystemTime)>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|line| is_entry(line)) {
+        let mut fields = line.split_whitespace();
+        let (Some(domain), Some(Ok(secs))) = (fields.next(), fields.next().map(str::parse)) else {
+            tracing::warn!("Skipping malformed temporary block: {}", line.trim());
+            continue;
+        };
+        let expires = UNIX_EPOCH + Duration::from_secs(secs);
+        if expires > now {
+            entries.push((domain.to_string(), expires));
+        }
+    }
+    Ok(entries)
+}
+
+/// Record a temporary block of `domain` for `ttl`, pruning expired entries
+/// and any earlier block of the same domain. Returns the expiry time.
+pub fn append_temporary_block(config: &Config, domain: &str, ttl: Duration) -> Result<SystemTime> {
+    let path = temporary_list_path(config);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let now = SystemTime::now();
+    let expires = now + ttl;
+    let mut entries = read_temporary(&path, now)?;
+    entries.retain(|(existing, _)| existing != domain);
+    entries.push((domain.to_string(), expires));
+
+    let mut content = String::new();
+    for (domain, expires) in entries {
+        let secs = expires.duration_since(UNIX_EPOCH)?.as_secs();
+        content.push_str(&format!("{} {}\n", domain, secs));
+    }
+    std::fs::write(&path, content)?;
+    Ok(expires)
+}
+
+/// Load the unexpired temporary blocks into the manager
+async fn load_temporary(config: &Config, blocklist: &BlocklistManager) -> Result<()> {
+    let path = temporary_list_path(config);
+    let entries = read_temporary(&path, SystemTime::now())?;
+    if !entries.is_empty() {
+        tracing::info!(
+            "Loading {} temporary blocks from {}",
+            entries.len(),
+            path.display()
+        );
+    }
+    for (domain, expires) in entries {
+        if let Err(e) = blocklist.add_domain_until(domain.clone(), expires).await {
+            tracing::warn!("Skipping temporary block {}: {}", domain, e);
+        }
+    }
+    Ok(())
+}
+
+/// Identifies the configured source set an index was built from, so an
+/// index goes unused after sources are added to or removed from the config
+fn index_key(config: &Config) -> String {
+    source_paths(config)
+        .iter()
+        .map(|(kind, path, category)| match category {
+            Some(category) => format!("{}[{}]={}", kind.label(), category, path.display()),
+            None => format!("{}={}", kind.label(), path.display()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether the index exists and is at least as new as every source file
+fn index_is_fresh(config: &Config) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(index_mtime) = modified(&index_path(config)) else {
+        return false;
+    };
+    source_paths(config)
+        .iter()
+        .filter_map(|(_, path, _)| modified(path))
+        .all(|mtime| mtime <= index_mtime)
+}
+
+/// Write the blocklist's current rules to the compiled index
+pub async fn save_index(config: &Config, blocklist: &BlocklistManager) -> Result<()> {
+    let path = index_path(config);
+    blocklist.save_index(&path, &index_key(config)).await?;
+    tracing::info!("Saved blocklist index to {}", path.display());
+    Ok(())
 }
 
-/// All configured sources, in load order
-fn source_paths(config: &Config) -> Vec<(SourceKind, PathBuf)> {
+/// Parse every source from scratch and write the compiled index
+pub async fn rebuild_index(config: &Config) -> Result<()> {
+    let blocklist = BlocklistManager::new();
+    load_sources(config, &blocklist, None).await?;
+    save_index(config, &blocklist).await
+}
+
+/// All configured sources with their category, in load order
+fn source_paths(config: &Config) -> Vec<(SourceKind, PathBuf, Option<String>)> {
     let mut paths = vec![(
         SourceKind::Custom,
         PathBuf::from(&config.blocklist.custom_list),
+        None,
     )];
-    for local in &config.blocklist.local_lists {
-        paths.push((SourceKind::Local, PathBuf::from(local)));
+    for local in config.blocklist.local_lists.iter().filter(|l| l.enabled) {
+        paths.push((
+            SourceKind::Local,
+            PathBuf::from(&local.location),
+            local.category.clone(),
+        ));
+    }
+    for suffix in &config.blocklist.suffix_lists {
+        paths.push((SourceKind::Suffix, PathBuf::from(suffix), None));
+    }
+    for hosts in &config.blocklist.hosts_files {
+        paths.push((SourceKind::Hosts, PathBuf::from(hosts), None));
+    }
+    if !config.blocklist.use_remote_cache {
+        return paths;
+    }
+    paths.push((
+        SourceKind::RemoteCache,
+        existing_remote_cache(config, None),
+        None,
+    ));
+    for category in config
+        .blocklist
+        .enabled_remote_groups()
+        .into_keys()
+        .flatten()
+    {
+        paths.push((
+            SourceKind::RemoteCache,
+            existing_remote_cache(config, Some(&category)),
+            Some(category),
+        ));
     }
-    paths.push((SourceKind::RemoteCache, remote_cache_path(config)));
     paths
 }
 
@@ -55,19 +424,59 @@ fn is_entry(line: &str) -> bool {
     !line.is_empty() && !line.starts_with('#')
 }
 
+/// Open a source file for reading line by line, decompressing it on the fly
+/// if it is gzipped (detected from the magic bytes, not the name)
+fn open_source(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Read a source file's entries line by line, so only the entries (not the
+/// whole file) are held in memory
 fn read_domains(path: &Path) -> Result<Vec<String>> {
+    let reader = open_source(path)?;
+    let mut domains = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if is_entry(&line) {
+            domains.push(line.trim().to_string());
+        }
+    }
+    Ok(domains)
+}
+
+/// Read a source's entries: the sinkholed names of a hosts file, every
+/// entry line of any other
+fn read_source(kind: SourceKind, path: &Path) -> Result<Vec<String>> {
+    if kind == SourceKind::Hosts {
+        read_hosts_sinks(path)
+    } else {
+        read_domains(path)
+    }
+}
+
+/// Read the sinkholed names from a hosts-format file (see
+/// `BlocklistDownloader::parse_hosts_sinks`)
+fn read_hosts_sinks(path: &Path) -> Result<Vec<String>> {
     let content = std::fs::read_to_string(path)?;
-    Ok(content
-        .lines()
-        .filter(|line| is_entry(line))
-        .map(|line| line.trim().to_string())
-        .collect())
+    Ok(BlocklistDownloader::parse_hosts_sinks(&content))
 }
 
 /// Count the entries in one source file; None if it is missing or unreadable.
 /// For display only — load errors are surfaced by `load_blocklist`.
 pub fn count_domains(path: &Path) -> Option<usize> {
-    read_domains(path).ok().map(|domains| domains.len())
+    let reader = open_source(path).ok()?;
+    let mut count = 0;
+    for line in reader.lines() {
+        if is_entry(&line.ok()?) {
+            count += 1;
+        }
+    }
+    Some(count)
 }
 
 /// Load all configured blocklist sources into the manager, reading each file
@@ -75,20 +484,162 @@ pub fn count_domains(path: &Path) -> Option<usize> {
 ///
 /// A missing file is skipped (with a warning, except for the remote cache);
 /// an existing file that cannot be read is an error. Does not clear the
-/// manager first; call `blocklist.clear()` beforehand for a full reload.
+/// manager first; for a full reload, load into a new manager and swap it in
+/// with `BlocklistManager::replace_with`.
+///
+/// With `use_index` enabled, a compiled index that is newer than every
+/// source is loaded instead of parsing the sources. Unexpired temporary
+/// blocks and the set of disabled categories are loaded either way.
 pub async fn load_blocklist(
     config: &Config,
     blocklist: &BlocklistManager,
+) -> Result<Vec<SourceSummary>> {
+    load_blocklist_inner(config, blocklist, None).await
+}
+
+/// `load_blocklist`, reusing the entries `cache` holds for source files
+/// that haven't changed since they were last read through it
+pub async fn load_blocklist_cached(
+    config: &Config,
+    blocklist: &BlocklistManager,
+    cache: &SourceCache,
+) -> Result<Vec<SourceSummary>> {
+    load_blocklist_inner(config, blocklist, Some(cache)).await
+}
+
+async fn load_blocklist_inner(
+    config: &Config,
+    blocklist: &BlocklistManager,
+    cache: Option<&SourceCache>,
+) -> Result<Vec<SourceSummary>> {
+    blocklist.set_max_match_labels(config.blocklist.max_match_labels);
+    let sources = load_rules(config, blocklist, cache).await?;
+    load_temporary(config, blocklist).await?;
+    let disabled = read_disabled_categories(config)?;
+    if !disabled.is_empty() {
+        tracing::info!(
+            "Disabled categories: {}",
+            disabled.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    blocklist
+        .set_disabled_categories(disabled.into_iter().collect())
+        .await;
+    Ok(sources)
+}
+
+/// Load the permanent rules, from the index if allowed and fresh
+async fn load_rules(
+    config: &Config,
+    blocklist: &BlocklistManager,
+    cache: Option<&SourceCache>,
+) -> Result<Vec<SourceSummary>> {
+    if config.blocklist.use_index && index_is_fresh(config) {
+        let path = index_path(config);
+        match blocklist.load_index(&path, &index_key(config)).await {
+            Ok(true) => {
+                // The index holds only untagged rules; categorized sources
+                // are always parsed
+                let mut sources = Vec::new();
+                for (kind, path, category) in source_paths(config) {
+                    let domains = match &category {
+                        Some(category) if path.exists() => {
+                            let domains = read_domains(&path)?;
+                            let count = domains.len();
+                            blocklist.load_category(category, domains).await?;
+                            Some(count)
+                        }
+                        None if kind == SourceKind::Hosts && path.exists() => {
+                            Some(read_hosts_sinks(&path)?.len())
+                        }
+                        _ => count_domains(&path),
+                    };
+                    sources.push(SourceSummary {
+                        kind,
+                        path,
+                        category,
+                        domains,
+                    });
+                }
+                tracing::info!(
+                    "Loaded {} total domains from blocklist index {}",
+                    blocklist.count().await,
+                    path.display()
+                );
+                return Ok(sources);
+            }
+            Ok(false) => tracing::info!("Blocklist index was built for other sources, ignoring it"),
+            Err(e) => tracing::warn!(error = %e, "Ignoring unreadable blocklist index"),
+        }
+    }
+
+    load_sources(config, blocklist, cache).await
+}
+
+/// Number of redundant entries across the loaded sources: every entry line
+/// counted per source, minus the distinct rules that ended up loaded. An
+/// entry listed in three sources counts as two duplicates.
+pub fn duplicate_count(sources: &[SourceSummary], unique: usize) -> usize {
+    let total: usize = sources.iter().filter_map(|s| s.domains).sum();
+    total.saturating_sub(unique)
+}
+
+/// Check freshly loaded lists against `reload_check`: every `must_block`
+/// domain has to be blocked and no `must_allow` domain may be. The error
+/// names each domain that failed.
+pub async fn check_reload(config: &Config, blocklist: &BlocklistManager) -> Result<()> {
+    let check = &config.blocklist.reload_check;
+    let mut failures = Vec::new();
+    for domain in &check.must_block {
+        if !blocklist.is_blocked(domain).await {
+            failures.push(format!("{} is not blocked", domain));
+        }
+    }
+    for domain in &check.must_allow {
+        if let Some(rule) = blocklist.matching_rule(domain).await {
+            failures.push(format!("{} is blocked by {}", domain, rule));
+        }
+    }
+    if !failures.is_empty() {
+        anyhow::bail!("Reload check failed: {}", failures.join(", "));
+    }
+    Ok(())
+}
+
+/// Parse all configured sources into the manager (see `load_blocklist`)
+async fn load_sources(
+    config: &Config,
+    blocklist: &BlocklistManager,
+    cache: Option<&SourceCache>,
 ) -> Result<Vec<SourceSummary>> {
     let mut sources = Vec::new();
     let mut all_domains = Vec::new();
+    let mut all_suffixes = Vec::new();
+    let mut by_category: HashMap<String, Vec<String>> = HashMap::new();
 
-    for (kind, path) in source_paths(config) {
+    for (kind, path, category) in source_paths(config) {
         let domains = if path.exists() {
-            tracing::info!("Loading {} blocklist from {}", kind.label(), path.display());
-            let domains = read_domains(&path)?;
-            let count = domains.len();
-            all_domains.extend(domains);
+            let target = if let Some(category) = &category {
+                by_category.entry(category.clone()).or_default()
+            } else if kind == SourceKind::Suffix {
+                &mut all_suffixes
+            } else {
+                &mut all_domains
+            };
+            let count = match cache {
+                Some(cache) => {
+                    let domains = cache.read(kind, &path)?;
+                    target.extend(domains.iter().cloned());
+                    domains.len()
+                }
+                None => {
+                    tracing::info!("Loading {} blocklist from {}", kind.label(), path.display());
+                    let domains = read_source(kind, &path)?;
+                    let count = domains.len();
+                    target.extend(domains);
+                    count
+                }
+            };
             Some(count)
         } else {
             if kind != SourceKind::RemoteCache {
@@ -99,54 +650,287 @@ pub async fn load_blocklist(
         sources.push(SourceSummary {
             kind,
             path,
+            category,
             domains,
         });
     }
 
+    if let Some(cache) = cache {
+        cache.retain(&sources);
+    }
     blocklist.load_domains(all_domains).await?;
+    blocklist.load_suffixes(all_suffixes).await?;
+    for (category, domains) in by_category {
+        blocklist.load_category(&category, domains).await?;
+    }
     let count = blocklist.count().await;
     tracing::info!("Loaded {} total domains into blocklist", count);
 
     Ok(sources)
 }
 
+/// Check an entry given to `add` before it is written, so typos such as a
+/// pasted URL don't end up in the custom list as rules that never match.
+/// Accepts `domain`, `*.domain` and `/regex/`.
+pub fn validate_custom_entry(entry: &str) -> Result<()> {
+    let entry = entry.trim();
+    if entry.len() > 2 && entry.starts_with('/') && entry.ends_with('/') {
+        regex::Regex::new(&entry[1..entry.len() - 1])
+            .map_err(|e| anyhow::anyhow!("invalid regex: {e}"))?;
+        return Ok(());
+    }
+    if entry.contains("://") {
+        anyhow::bail!("looks like a URL; give just the host name");
+    }
+    if entry.contains('/') {
+        anyhow::bail!("contains a path; give just the host name");
+    }
+    let host = entry.strip_prefix("*.").unwrap_or(entry);
+    if host.contains('*') {
+        anyhow::bail!("wildcards are only supported as a leading '*.'");
+    }
+    if !BlocklistDownloader::is_valid_domain(&host.to_lowercase()) {
+        anyhow::bail!("not a valid domain name");
+    }
+    Ok(())
+}
+
+/// Read-modify-write the custom list under an exclusive advisory lock
+/// (flock), so concurrent `add`/`remove` runs apply one after another
+/// instead of overwriting each other. The file is rewritten only if `edit`
+/// changed the content.
+fn edit_custom_list<T>(
+    path: &Path,
+    create: bool,
+    edit: impl FnOnce(&mut String) -> T,
+) -> Result<T> {
+    use nix::fcntl::{Flock, FlockArg};
+    use std::io::{Read, Seek};
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .truncate(false)
+        .open(path)?;
+    let mut file = Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, errno)| anyhow::anyhow!("Failed to lock {}: {}", path.display(), errno))?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let original = content.clone();
+    let result = edit(&mut content);
+    if content != original {
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(content.as_bytes())?;
+    }
+    Ok(result)
+}
+
+/// The entries of the custom list; none if it doesn't exist yet
+pub fn read_custom_list(config: &Config) -> Result<Vec<String>> {
+    match read_domains(Path::new(&config.blocklist.custom_list)) {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+        {
+            Ok(Vec::new())
+        }
+        result => result,
+    }
+}
+
 /// Append a domain to the custom list, creating the file if needed and
 /// repairing a missing trailing newline. Returns the new entry count.
 pub fn append_custom_domain(config: &Config, domain: &str) -> Result<usize> {
+    append_custom_domains(config, &[domain.to_string()])
+}
+
+/// Append several domains to the custom list in a single write (see
+/// `append_custom_domain`). Returns the new entry count.
+pub fn append_custom_domains(config: &Config, domains: &[String]) -> Result<usize> {
     let path = Path::new(&config.blocklist.custom_list);
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent)?;
     }
-    let mut content = match std::fs::read_to_string(path) {
-        Ok(content) => content,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
-        Err(e) => return Err(e.into()),
-    };
-    if !content.is_empty() && !content.ends_with('\n') {
-        content.push('\n');
-    }
-    content.push_str(domain);
-    content.push('\n');
-    std::fs::write(path, &content)?;
-    Ok(content.lines().filter(|line| is_entry(line)).count())
+    edit_custom_list(path, true, |content| {
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        for domain in domains {
+            content.push_str(domain);
+            content.push('\n');
+        }
+        content.lines().filter(|line| is_entry(line)).count()
+    })
 }
 
 /// Remove a domain from the custom list. Returns the new entry count, or
 /// None if the domain was not present.
 pub fn remove_custom_domain(config: &Config, domain: &str) -> Result<Option<usize>> {
-    let path = &config.blocklist.custom_list;
-    let content = std::fs::read_to_string(path)?;
-    let kept: Vec<&str> = content
-        .lines()
-        .map(str::trim)
-        .filter(|line| !line.is_empty() && *line != domain)
+    let path = Path::new(&config.blocklist.custom_list);
+    edit_custom_list(path, false, |content| {
+        let kept: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && *line != domain)
+            .collect();
+        if kept.len() == content.lines().filter(|l| !l.trim().is_empty()).count() {
+            return None;
+        }
+        *content = kept.join("\n") + "\n";
+        Some(content.lines().filter(|line| is_entry(line)).count())
+    })
+}
+
+/// Most near-miss entries `similar_entries` returns
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Entries close to `domain` (case-insensitive edit distance, a swap of
+/// two adjacent characters counting as one edit), closest first, so a typo
+/// given to `remove` can be pointed at what was probably meant. Allows one
+/// edit per six characters, between one and three.
+pub fn similar_entries(entries: &[String], domain: &str) -> Vec<String> {
+    let domain = domain.trim().to_ascii_lowercase();
+    let max_distance = (domain.len() / 6).clamp(1, 3);
+    let mut close: Vec<(usize, &String)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let distance = edit_distance(&domain, &entry.to_ascii_lowercase());
+            (distance > 0 && distance <= max_distance).then_some((distance, entry))
+        })
+        .collect();
+    close.sort();
+    close.dedup_by(|a, b| a.1 == b.1);
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, entry)| entry.clone())
+        .collect()
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent bytes each cost one
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    // Rows i-2, i-1 and i of the distance table
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Partition of two blocklists' domain sets (see `diff_lists`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListDiff {
+    /// Domains only in the first list, i.e. removed by switching to the second
+    pub only_a: Vec<String>,
+    /// Domains only in the second list, i.e. added by switching to it
+    pub only_b: Vec<String>,
+    /// Number of domains present in both
+    pub common: usize,
+}
+
+/// Compare the contents of two blocklists, each parsed with the downloader's
+/// format detection (plain lists and hosts files). Domain lists are sorted.
+pub fn diff_lists(a: &str, b: &str) -> ListDiff {
+    let a: BTreeSet<String> = BlocklistDownloader::parse_blocklist(a)
+        .into_iter()
+        .collect();
+    let b: BTreeSet<String> = BlocklistDownloader::parse_blocklist(b)
+        .into_iter()
         .collect();
-    if kept.len() == content.lines().filter(|l| !l.trim().is_empty()).count() {
-        return Ok(None);
+    ListDiff {
+        only_a: a.difference(&b).cloned().collect(),
+        only_b: b.difference(&a).cloned().collect(),
+        common: a.intersection(&b).count(),
     }
-    let content = kept.join("\n") + "\n";
-    std::fs::write(path, &content)?;
-    Ok(Some(content.lines().filter(|line| is_entry(line)).count()))
+}
+
+/// What `normalize_list` did to a list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NormalizeReport {
+    /// Entries in the normalized list
+    pub kept: usize,
+    /// Entries rewritten (case, URL or host-file syntax, punycode, ...)
+    pub changed: usize,
+    /// Entries dropped as invalid or as duplicates
+    pub removed: usize,
+    /// Comment and blank lines dropped
+    pub comments: usize,
+}
+
+impl std::fmt::Display for NormalizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} entries, {} changed, {} removed, {} comment/blank lines dropped",
+            self.kept, self.changed, self.removed, self.comments
+        )
+    }
+}
+
+/// Rewrite a list as one normalized entry per line, sorted and without
+/// duplicates or comments. Lines go through the downloader's parser, so
+/// URLs, hosts-file lines and `||domain^` rules become plain entries.
+pub fn normalize_list(content: &str) -> (String, NormalizeReport) {
+    let mut report = NormalizeReport::default();
+    let mut entries = BTreeSet::new();
+    for line in content.lines() {
+        // Trailing `# comment`, unless the line is a regex
+        let line = match line.find(" #").or_else(|| line.find("\t#")) {
+            Some(end) if !line.trim_start().starts_with('/') => &line[..end],
+            _ => line,
+        };
+        let mut parsed = Vec::new();
+        if BlocklistDownloader::parse_line(line, &mut parsed).is_none() {
+            report.comments += 1;
+            continue;
+        }
+        if parsed.is_empty() {
+            report.removed += 1;
+            continue;
+        }
+        if parsed != [line.trim()] {
+            report.changed += 1;
+        }
+        for entry in parsed {
+            if !entries.insert(entry) {
+                report.removed += 1;
+            }
+        }
+    }
+    report.kept = entries.len();
+    let mut normalized = String::new();
+    for entry in entries {
+        normalized.push_str(&entry);
+        normalized.push('\n');
+    }
+    (normalized, report)
+}
+
+/// Replace `path`'s contents by writing a temporary file next to it and
+/// renaming it over the original
+pub fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -159,6 +943,21 @@ mod tests {
         config
     }
 
+    #[test]
+    fn validate_rejects_urls_and_paths() {
+        assert!(validate_custom_entry("http://ads.example.com").is_err());
+        assert!(validate_custom_entry("ads.example.com/path").is_err());
+        assert!(validate_custom_entry("ads*.example.com").is_err());
+        assert!(validate_custom_entry("/[unclosed/").is_err());
+    }
+
+    #[test]
+    fn validate_accepts_domains_wildcards_and_regexes() {
+        assert!(validate_custom_entry("ads.example.com").is_ok());
+        assert!(validate_custom_entry("*.tracker.com").is_ok());
+        assert!(validate_custom_entry("/^ads[0-9]+\\./").is_ok());
+    }
+
     #[test]
     fn append_repairs_missing_trailing_newline() {
         let dir = tempfile::tempdir().unwrap();
@@ -172,6 +971,20 @@ mod tests {
         assert_eq!(content, "foo.com\nbar.com\n");
     }
 
+    #[test]
+    fn append_many_writes_all_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_for(dir.path());
+        std::fs::write(&config.blocklist.custom_list, "foo.com\n").unwrap();
+
+        let count =
+            append_custom_domains(&config, &["a.com".to_string(), "*.b.com".to_string()]).unwrap();
+
+        assert_eq!(count, 3);
+        let content = std::fs::read_to_string(&config.blocklist.custom_list).unwrap();
+        assert_eq!(content, "foo.com\na.com\n*.b.com\n");
+    }
+
     #[test]
     fn append_creates_file_and_parents() {
         let dir = tempfile::tempdir().unwrap();
@@ -185,6 +998,37 @@ mod tests {
         assert_eq!(content, "foo.com\n");
     }
 
+    #[test]
+    fn concurrent_appends_all_land_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_for(dir.path());
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        append_custom_domain(&config, &format!("w{writer}-{i}.example.com"))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let content = std::fs::read_to_string(&config.blocklist.custom_list).unwrap();
+        let lines: BTreeSet<&str> = content.lines().collect();
+        assert_eq!(content.lines().count(), 200);
+        assert_eq!(lines.len(), 200);
+        for writer in 0..8 {
+            for i in 0..25 {
+                assert!(lines.contains(format!("w{writer}-{i}.example.com").as_str()));
+            }
+        }
+    }
+
     #[test]
     fn remove_reports_missing_domain() {
         let dir = tempfile::tempdir().unwrap();
@@ -197,6 +1041,378 @@ mod tests {
         assert_eq!(content, "foo.com\n");
     }
 
+    #[test]
+    fn remove_typo_suggests_close_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_for(dir.path());
+        std::fs::write(
+            &config.blocklist.custom_list,
+            "ads.example.com\nads.example.org\ntracker.net\n*.ads.example.com\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            remove_custom_domain(&config, "ads.exmaple.com").unwrap(),
+            None
+        );
+        let entries = read_custom_list(&config).unwrap();
+        assert_eq!(
+            similar_entries(&entries, "ads.exmaple.com"),
+            ["ads.example.com"]
+        );
+        assert_eq!(
+            similar_entries(&entries, "ADS.example.cog"),
+            ["ads.example.com", "ads.example.org"]
+        );
+        assert!(similar_entries(&entries, "unrelated.io").is_empty());
+    }
+
+    #[test]
+    fn diff_lists_partitions_domains() {
+        let a = "# list A\nads.example.com\n0.0.0.0 shared.example.com\nold.example.com\n";
+        let b = "127.0.0.1 shared.example.com\nnew.example.com\nads.example.com\n";
+
+        let diff = diff_lists(a, b);
+
+        assert_eq!(diff.only_a, vec!["old.example.com".to_string()]);
+        assert_eq!(diff.only_b, vec!["new.example.com".to_string()]);
+        assert_eq!(diff.common, 2);
+    }
+
+    #[test]
+    fn normalize_list_cleans_messy_file() {
+        let messy = "# My list\n\n  Ads.Example.COM  \nhttps://tracker.net/pixel.gif\n\
+                     0.0.0.0 zeta.org # old entry\nads.example.com\nbücher.de\n\
+                     not a domain!\n*.CDN.example.com\n/^ad[0-9]+\\./\n";
+        let (normalized, report) = normalize_list(messy);
+        assert_eq!(
+            normalized,
+            "*.cdn.example.com\n/^ad[0-9]+\\./\nads.example.com\ntracker.net\n\
+             xn--bcher-kva.de\nzeta.org\n"
+        );
+        assert_eq!(
+            report,
+            NormalizeReport {
+                kept: 6,
+                changed: 5,
+                removed: 2,
+                comments: 2,
+            }
+        );
+
+        // Already normalized: nothing to do
+        let (again, report) = normalize_list(&normalized);
+        assert_eq!(again, normalized);
+        assert_eq!((report.changed, report.removed), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_suffix_mode_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let suffix_list = dir.path().join("suffixes.txt");
+        std::fs::write(&suffix_list, "# trackers\ntracker.com\n").unwrap();
+        config.blocklist.suffix_lists = vec![suffix_list.display().to_string()];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+
+        let suffix = sources
+            .iter()
+            .find(|s| s.kind == SourceKind::Suffix)
+            .unwrap();
+        assert_eq!(suffix.domains, Some(1));
+        assert!(blocklist.is_blocked("tracker.com").await);
+        assert!(blocklist.is_blocked("pixel.tracker.com").await);
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_hosts_file_blocks_only_sinkholed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let hosts = dir.path().join("hosts");
+        std::fs::write(
+            &hosts,
+            "127.0.0.1 localhost\n192.168.1.10 nas.home.example\n0.0.0.0 ads.example.com\n",
+        )
+        .unwrap();
+        config.blocklist.hosts_files = vec![hosts.display().to_string()];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+
+        let hosts = sources
+            .iter()
+            .find(|s| s.kind == SourceKind::Hosts)
+            .unwrap();
+        assert_eq!(hosts.domains, Some(1));
+        assert!(blocklist.is_blocked("ads.example.com").await);
+        assert!(!blocklist.is_blocked("nas.home.example").await);
+    }
+
+    #[tokio::test]
+    async fn temporary_blocks_persist_until_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_for(dir.path());
+        let path = temporary_list_path(&config);
+        std::fs::write(&path, "old.com 1\ngarbage\n").unwrap();
+
+        append_temporary_block(&config, "focus.com", Duration::from_secs(3600)).unwrap();
+        append_temporary_block(&config, "focus.com", Duration::from_secs(7200)).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1, "{content}");
+        assert!(content.starts_with("focus.com "));
+
+        let blocklist = BlocklistManager::new();
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("focus.com").await);
+        assert!(!blocklist.is_blocked("old.com").await);
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_honours_disabled_categories() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let social = dir.path().join("social.txt");
+        std::fs::write(&social, "facebook.com\n").unwrap();
+        std::fs::write(
+            remote_cache_path_for(&config, Some("ads")),
+            "ads.example.com\n",
+        )
+        .unwrap();
+        config.blocklist.local_lists = vec![crate::config::ListSource {
+            location: social.display().to_string(),
+            enabled: true,
+            category: Some("social".to_string()),
+        }];
+        config.blocklist.remote_lists = vec![crate::config::ListSource {
+            location: "https://example.com/ads.txt".to_string(),
+            enabled: true,
+            category: Some("ads".to_string()),
+        }];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("facebook.com").await);
+        assert!(blocklist.is_blocked("ads.example.com").await);
+        assert!(sources
+            .iter()
+            .any(|s| s.category.as_deref() == Some("ads") && s.domains == Some(1)));
+
+        assert!(set_category_enabled(&config, "social", false).unwrap());
+        assert!(!set_category_enabled(&config, "social", false).unwrap());
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(!blocklist.is_blocked("facebook.com").await);
+        assert!(blocklist.is_blocked("ads.example.com").await);
+
+        assert!(set_category_enabled(&config, "social", true).unwrap());
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("facebook.com").await);
+    }
+
+    #[tokio::test]
+    async fn compressed_remote_cache_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        config.blocklist.compress_cache = true;
+        let domains: Vec<String> = (0..500).map(|i| format!("ads{}.example.com", i)).collect();
+
+        // A plain cache from before the switch is replaced
+        std::fs::write(dir.path().join("remote-blocklist-cache.txt"), "old.com\n").unwrap();
+        let path = write_remote_cache(&config, None, &domains).unwrap();
+        assert_eq!(path, dir.path().join("remote-blocklist-cache.txt.gz"));
+        assert!(!dir.path().join("remote-blocklist-cache.txt").exists());
+        assert_eq!(&std::fs::read(&path).unwrap()[..2], &[0x1f, 0x8b]);
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+        let cache = sources
+            .iter()
+            .find(|s| s.kind == SourceKind::RemoteCache)
+            .unwrap();
+        assert_eq!(cache.domains, Some(domains.len()));
+        assert_eq!(read_domains(&path).unwrap(), domains);
+        assert!(blocklist.is_blocked("ads499.example.com").await);
+        assert!(!blocklist.is_blocked("old.com").await);
+
+        // Still loaded after compression is switched back off
+        config.blocklist.compress_cache = false;
+        let blocklist = BlocklistManager::new();
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("ads0.example.com").await);
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_ignores_remote_cache_when_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        std::fs::write(&config.blocklist.custom_list, "mine.com\n").unwrap();
+        std::fs::write(remote_cache_path(&config), "stale-remote.com\n").unwrap();
+
+        let blocklist = BlocklistManager::new();
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("stale-remote.com").await);
+
+        config.blocklist.use_remote_cache = false;
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("mine.com").await);
+        assert!(!blocklist.is_blocked("stale-remote.com").await);
+        assert!(sources.iter().all(|s| s.kind != SourceKind::RemoteCache));
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_skips_disabled_local_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let enabled = dir.path().join("enabled.txt");
+        let disabled = dir.path().join("disabled.txt");
+        std::fs::write(&enabled, "on.com\n").unwrap();
+        std::fs::write(&disabled, "off.com\n").unwrap();
+        config.blocklist.local_lists = vec![
+            enabled.display().to_string().into(),
+            crate::config::ListSource {
+                location: disabled.display().to_string(),
+                enabled: false,
+                category: None,
+            },
+        ];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+
+        assert!(blocklist.is_blocked("on.com").await);
+        assert!(!blocklist.is_blocked("off.com").await);
+        assert!(sources.iter().all(|s| s.path != disabled));
+    }
+
+    #[tokio::test]
+    async fn cached_reload_rereads_only_changed_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let touched = dir.path().join("touched.txt");
+        let untouched = dir.path().join("untouched.txt");
+        std::fs::write(&touched, "old-a.com\n").unwrap();
+        std::fs::write(&untouched, "old-b.com\n").unwrap();
+        config.blocklist.local_lists = vec![
+            touched.display().to_string().into(),
+            untouched.display().to_string().into(),
+        ];
+        let cache = SourceCache::default();
+        load_blocklist_cached(&config, &BlocklistManager::new(), &cache)
+            .await
+            .unwrap();
+
+        // Both files get new contents of the same size, but only one gets a
+        // new mtime
+        let mtime = |path: &Path| std::fs::metadata(path).unwrap().modified().unwrap();
+        let untouched_mtime = mtime(&untouched);
+        std::fs::write(&touched, "new-a.com\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(mtime(&touched) + Duration::from_secs(1))
+            .unwrap();
+        std::fs::write(&untouched, "new-b.com\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&untouched)
+            .unwrap()
+            .set_modified(untouched_mtime)
+            .unwrap();
+
+        let blocklist = BlocklistManager::new();
+        load_blocklist_cached(&config, &blocklist, &cache)
+            .await
+            .unwrap();
+        assert!(blocklist.is_blocked("new-a.com").await);
+        assert!(!blocklist.is_blocked("old-a.com").await);
+        // Merged in from the cache, not re-read
+        assert!(blocklist.is_blocked("old-b.com").await);
+        assert!(!blocklist.is_blocked("new-b.com").await);
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_streams_large_list() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        let local = dir.path().join("large.txt");
+        let mut file = std::io::BufWriter::new(File::create(&local).unwrap());
+        for i in 0..100_000 {
+            if i % 1000 == 0 {
+                writeln!(file, "# section {i}").unwrap();
+            }
+            writeln!(file, "host{i}.example.com").unwrap();
+        }
+        file.flush().unwrap();
+        drop(file);
+        config.blocklist.local_lists = vec![local.display().to_string().into()];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+
+        assert_eq!(blocklist.count().await, 100_000);
+        assert_eq!(count_domains(&local), Some(100_000));
+        assert!(sources.iter().any(|s| s.domains == Some(100_000)));
+        assert!(blocklist.is_blocked("host99999.example.com").await);
+    }
+
+    #[tokio::test]
+    async fn duplicate_count_spans_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        std::fs::write(&config.blocklist.custom_list, "a.com\nb.com\n").unwrap();
+        let local = dir.path().join("local.txt");
+        std::fs::write(&local, "B.com\nc.com\na.com\n").unwrap();
+        config.blocklist.local_lists = vec![local.display().to_string().into()];
+
+        let blocklist = BlocklistManager::new();
+        let sources = load_blocklist(&config, &blocklist).await.unwrap();
+        let unique = blocklist.count().await;
+
+        assert_eq!(unique, 3);
+        assert_eq!(duplicate_count(&sources, unique), 2);
+    }
+
+    #[tokio::test]
+    async fn load_blocklist_uses_fresh_index_only() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config_for(dir.path());
+        config.blocklist.use_index = true;
+        std::fs::write(&config.blocklist.custom_list, "indexed.com\n").unwrap();
+        rebuild_index(&config).await.unwrap();
+
+        let set_custom_mtime = |mtime: SystemTime| {
+            std::fs::File::options()
+                .write(true)
+                .open(&config.blocklist.custom_list)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+
+        // Source older than the index: rules come from the index
+        std::fs::write(&config.blocklist.custom_list, "parsed.com\n").unwrap();
+        set_custom_mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        let blocklist = BlocklistManager::new();
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(blocklist.is_blocked("indexed.com").await);
+        assert!(!blocklist.is_blocked("parsed.com").await);
+
+        // Source newer than the index: sources are parsed
+        set_custom_mtime(SystemTime::now() + Duration::from_secs(60));
+        let blocklist = BlocklistManager::new();
+        load_blocklist(&config, &blocklist).await.unwrap();
+        assert!(!blocklist.is_blocked("indexed.com").await);
+        assert!(blocklist.is_blocked("parsed.com").await);
+    }
+
     #[tokio::test]
     async fn load_blocklist_errors_on_unreadable_file() {
         use std::os::unix::fs::PermissionsExt;
@@ -1,17 +1,34 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::io::IsTerminal;
-use std::sync::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
 
+use anyhow::Context as _;
 use colored::{ColoredString, Colorize};
-use tracing::{Event, Level, Subscriber};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::field::Visit;
+use tracing_subscriber::filter::dynamic_filter_fn;
 use tracing_subscriber::fmt::format::Writer;
-use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
+use crate::config::{LogRotation, LoggingConfig};
 use crate::Result;
 
+/// Target of the `[BLOCKED]`/`[ALLOWED]` query lines, which go to
+/// `query_log_path` instead of the app log when that is set
+pub(crate) const QUERY_LOG_TARGET: &str = "skypier_blackhole::query";
+
+/// The open log files, set once by `log_to_files`
+static FILE_LOGS: OnceLock<FileLogs> = OnceLock::new();
+
 /// Setup logging with a charmbracelet/log-style human-friendly formatter.
 ///
 /// Output looks like:
@@ -21,27 +38,225 @@ use crate::Result;
 /// 14:02:55 ERROR Error handling query: timed out
 /// ```
 /// Structured fields are rendered as dimmed `key=value` pairs after the message.
+/// Enclosing spans come before the message, e.g.
+/// `query{id=3f2a9c01 client=10.0.0.4 domain=example.com.}`.
 ///
 /// Consecutive identical lines are collapsed: on a terminal the line is
 /// redrawn in place with an `(xN)` counter; otherwise repeats are suppressed
 /// and summarized once a different line is logged.
-pub fn setup_logging() -> Result<()> {
-    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
+///
+/// `level` (from the `-q`/`-v` flags) takes precedence over `RUST_LOG`;
+/// without it the filter comes from `RUST_LOG`, falling back to `info`.
+///
+/// Events also go to the log files once `log_to_files` opened them.
+pub fn setup_logging(level: Option<&str>) -> Result<()> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?,
+    };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .event_format(CharmFormatter::new())
+    let console = tracing_subscriber::fmt::layer().event_format(CharmFormatter::new());
+    let files = tracing_subscriber::fmt::layer()
+        .event_format(CharmFormatter::plain())
+        .with_writer(FileLogWriter)
+        .with_filter(dynamic_filter_fn(|_, _| FILE_LOGS.get().is_some()));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(files)
         .init();
 
     Ok(())
 }
 
+/// Also write the log to `log_path` (and the query lines to
+/// `query_log_path`, if set), rotated as `log_rotation` says. Only the
+/// first call opens files; the daemon makes it once its config is loaded.
+pub(crate) fn log_to_files(config: &LoggingConfig) -> Result<()> {
+    if FILE_LOGS.get().is_some() {
+        return Ok(());
+    }
+    let app = open_log(Path::new(&config.log_path), config)?;
+    let queries = match &config.query_log_path {
+        Some(path) => Some(open_log(Path::new(path), config)?),
+        None => None,
+    };
+    let _ = FILE_LOGS.set(FileLogs {
+        app: Mutex::new(app),
+        queries: queries.map(Mutex::new),
+    });
+    Ok(())
+}
+
+/// Open a log file (creating its directory) with the configured rotation
+fn open_log(path: &Path, config: &LoggingConfig) -> Result<Box<dyn Write + Send>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        anyhow::bail!("Invalid log path: {}", path.display());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create log directory: {}", dir.display()))?;
+
+    let rotation = match config.log_rotation {
+        LogRotation::Size => {
+            let max_bytes = config.log_max_size_mb.saturating_mul(1024 * 1024);
+            return Ok(Box::new(SizeRollingFile::open(
+                path.to_path_buf(),
+                max_bytes,
+                config.log_max_files,
+            )?));
+        }
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(name.to_string_lossy())
+        .max_log_files(config.log_max_files)
+        .build(dir)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+    Ok(Box::new(appender))
+}
+
+/// The app log, and the query log when it is separate
+struct FileLogs {
+    app: Mutex<Box<dyn Write + Send>>,
+    queries: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+/// `MakeWriter` picking the log file an event belongs in
+struct FileLogWriter;
+
+impl<'a> MakeWriter<'a> for FileLogWriter {
+    type Writer = FileLogSink;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        FileLogSink { query: false }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        FileLogSink {
+            query: meta.target() == QUERY_LOG_TARGET,
+        }
+    }
+}
+
+/// One event's write to the app or query log; a no-op until files are open
+struct FileLogSink {
+    query: bool,
+}
+
+impl FileLogSink {
+    fn with_file<T>(
+        &self,
+        write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+        idle: T,
+    ) -> io::Result<T> {
+        let Some(logs) = FILE_LOGS.get() else {
+            return Ok(idle);
+        };
+        let file = match &logs.queries {
+            Some(queries) if self.query => queries,
+            _ => &logs.app,
+        };
+        write(file.lock().unwrap().as_mut())
+    }
+}
+
+impl Write for FileLogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_file(|file| file.write(buf), buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.with_file(|file| file.write_all(buf), ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_file(|file| file.flush(), ())
+    }
+}
+
+/// A log file that is moved to `<path>.1` once it grows past `max_bytes`,
+/// shifting older ones up to `<path>.<max_files - 1>` (`log_rotation = "size"`)
+struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let file = Self::append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(SizeRollingFile {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    /// Shift `<path>.N` to `<path>.N+1` (dropping the oldest), move the
+    /// current file to `<path>.1` and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        let kept = self.max_files.saturating_sub(1);
+        if kept == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(kept));
+            for n in (1..kept).rev() {
+                let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = Self::append(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
 /// A compact, colorful event formatter inspired by charmbracelet/log.
-struct CharmFormatter {
+pub(crate) struct CharmFormatter {
     dedup: Mutex<DedupState>,
     /// Whether stdout is a terminal, i.e. whether in-place line rewriting
     /// with ANSI cursor movement is safe.
     is_tty: bool,
+    /// Strip colors (for log files)
+    plain: bool,
 }
 
 /// Tracks the last rendered line (sans timestamp) to collapse repeats.
@@ -56,6 +271,16 @@ impl CharmFormatter {
         Self {
             dedup: Mutex::new(DedupState::default()),
             is_tty: std::io::stdout().is_terminal(),
+            plain: false,
+        }
+    }
+
+    /// Uncolored lines, repeats summarized rather than redrawn
+    pub(crate) fn plain() -> Self {
+        Self {
+            dedup: Mutex::new(DedupState::default()),
+            is_tty: false,
+            plain: true,
         }
     }
 }
@@ -67,10 +292,36 @@ where
 {
     fn format_event(
         &self,
-        _ctx: &FmtContext<'_, S, N>,
+        ctx: &FmtContext<'_, S, N>,
         mut writer: Writer<'_>,
         event: &Event<'_>,
     ) -> fmt::Result {
+        // Enclosing spans (e.g. `query{id=.. client=.. domain=..}`),
+        // outermost first, so every line of a query can be tied together
+        let mut spans = String::new();
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = Writer::new(&mut spans);
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                match extensions.get::<FormattedFields<N>>() {
+                    Some(fields) if !fields.is_empty() => {
+                        write!(spans, "{}{{{}}} ", span.name(), fields)?
+                    }
+                    _ => write!(spans, "{} ", span.name())?,
+                }
+            }
+        }
+        if !self.plain {
+            return self.format_line(&mut writer, &spans, event);
+        }
+        let mut line = String::new();
+        self.format_line(&mut Writer::new(&mut line), &spans, event)?;
+        writer.write_str(&strip_ansi(&line))
+    }
+}
+
+impl CharmFormatter {
+    fn format_line(&self, writer: &mut Writer<'_>, spans: &str, event: &Event<'_>) -> fmt::Result {
         let meta = event.metadata();
 
         // Render everything except the timestamp into a buffer so identical
@@ -81,6 +332,9 @@ where
 
             // Colored, fixed-width level badge.
             write!(body_writer, "{} ", level_badge(meta.level()))?;
+            if !spans.is_empty() {
+                write!(body_writer, "{}", spans.dimmed())?;
+            }
 
             // Message, then the remaining fields as `key=value` pairs.
             let mut visitor = CharmVisitor {
@@ -189,6 +443,116 @@ impl Visit for CharmVisitor<'_, '_> {
     }
 }
 
+/// Query totals read back from a log by `analyze`
+#[derive(Debug, Default)]
+pub(crate) struct LogSummary {
+    pub blocked: u64,
+    /// Allowed queries, scaled up from sampled lines (`sample_rate=`)
+    pub allowed: u64,
+    /// Whether some `[ALLOWED]` lines were sampled, making `allowed` an
+    /// estimate
+    pub sampled: bool,
+    /// Number of blocked queries per domain
+    pub blocked_domains: HashMap<String, u64>,
+}
+
+impl LogSummary {
+    /// Share of the counted queries that were blocked, in percent
+    pub fn block_rate(&self) -> f64 {
+        let total = self.blocked + self.allowed;
+        if total == 0 {
+            return 0.0;
+        }
+        self.blocked as f64 * 100.0 / total as f64
+    }
+
+    /// The `n` most blocked domains, most blocked first (ties by name)
+    pub fn top_blocked(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut domains: Vec<(&str, u64)> = self
+            .blocked_domains
+            .iter()
+            .map(|(domain, count)| (domain.as_str(), *count))
+            .collect();
+        domains.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        domains.truncate(n);
+        domains
+    }
+}
+
+/// Count the `[BLOCKED]` and `[ALLOWED]` query lines written by the DNS
+/// server. Color codes are ignored, and a `(repeated N more times)` summary
+/// from the dedup logic counts as N more of the same line. An `[ALLOWED]`
+/// line with `sample_rate=R` counts as 1/R queries.
+pub(crate) fn analyze_log(reader: impl BufRead) -> io::Result<LogSummary> {
+    let mut summary = LogSummary::default();
+    let mut allowed = 0.0;
+    for line in reader.lines() {
+        let line = strip_ansi(&line?);
+        let (blocked, fields) = if let Some((_, rest)) = line.split_once("[BLOCKED]") {
+            (true, rest)
+        } else if let Some((_, rest)) = line.split_once("[ALLOWED]") {
+            (false, rest)
+        } else {
+            continue;
+        };
+
+        let (fields, times) = match fields.split_once("(repeated ") {
+            Some((fields, repeat)) => {
+                let more = repeat
+                    .split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .unwrap_or(0);
+                (fields, 1 + more)
+            }
+            None => (fields, 1),
+        };
+
+        if blocked {
+            summary.blocked += times;
+            let domain = fields
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("domain="));
+            if let Some(domain) = domain {
+                *summary
+                    .blocked_domains
+                    .entry(domain.trim_end_matches('.').to_string())
+                    .or_default() += times;
+            }
+        } else {
+            let rate = fields
+                .split_whitespace()
+                .find_map(|field| field.strip_prefix("sample_rate="))
+                .and_then(|rate| rate.parse::<f64>().ok())
+                .filter(|rate| *rate > 0.0 && *rate < 1.0);
+            if rate.is_some() {
+                summary.sampled = true;
+            }
+            allowed += times as f64 / rate.unwrap_or(1.0);
+        }
+    }
+    summary.allowed = allowed.round() as u64;
+    Ok(summary)
+}
+
+/// Drop ANSI color sequences (`ESC [ ... <letter>`) from a log line
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
 /// Prints a `Debug` value but strips the outer quotes that strings get,
 /// so messages read naturally.
 struct DebugAsDisplay<'a>(&'a dyn fmt::Debug);
@@ -203,3 +567,77 @@ impl fmt::Debug for DebugAsDisplay<'_> {
         f.write_str(trimmed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_rotation_starts_a_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.log");
+        let rotated = |n: usize| dir.path().join(format!("blackhole.log.{n}"));
+        let mut log = SizeRollingFile::open(path.clone(), 64, 3).unwrap();
+
+        log.write_all(&[b'a'; 40]).unwrap();
+        assert!(!rotated(1).exists());
+
+        // Crossing 64 bytes moves the full file aside
+        log.write_all(&[b'b'; 40]).unwrap();
+        assert_eq!(fs::read(rotated(1)).unwrap(), [b'a'; 40]);
+        assert_eq!(fs::read(&path).unwrap(), [b'b'; 40]);
+
+        // Three files at most: the oldest is dropped
+        log.write_all(&[b'c'; 40]).unwrap();
+        log.write_all(&[b'd'; 40]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [b'd'; 40]);
+        assert_eq!(fs::read(rotated(1)).unwrap(), [b'c'; 40]);
+        assert_eq!(fs::read(rotated(2)).unwrap(), [b'b'; 40]);
+        assert!(!rotated(3).exists());
+
+        // Reopening picks up the current size
+        let mut log = SizeRollingFile::open(path.clone(), 64, 3).unwrap();
+        log.write_all(&[b'e'; 40]).unwrap();
+        assert_eq!(fs::read(rotated(1)).unwrap(), [b'd'; 40]);
+    }
+
+    #[test]
+    fn test_analyze_log_counts_queries() {
+        let log = "\
+14:02:50 INFO  DNS server listening proto=UDP addr=0.0.0.0:53
+14:02:51 INFO  [BLOCKED] domain=ads.example.com. client=10.0.0.4 qtype=A blocked=true
+14:02:51 DEBUG [ALLOWED] domain=example.org. client=10.0.0.4 qtype=A
+14:02:52 INFO  [BLOCKED] domain=tracker.example.net. client=10.0.0.5 qtype=AAAA blocked=true
+14:02:53 \x1b[32mINFO \x1b[0m [BLOCKED] \x1b[2mdomain\x1b[0m=\x1b[2mads.example.com.\x1b[0m blocked=true
+14:02:58 INFO  [BLOCKED] domain=ads.example.com. client=10.0.0.4 qtype=A blocked=true (repeated 3 more times)
+14:02:58 DEBUG [ALLOWED] domain=example.org. client=10.0.0.6 qtype=A
+14:02:59 WARN  Upstream forwarding failed, answering SERVFAIL domain=example.org.
+";
+        let summary = analyze_log(log.as_bytes()).unwrap();
+
+        assert_eq!(summary.blocked, 7);
+        assert_eq!(summary.allowed, 2);
+        assert!((summary.block_rate() - 7.0 * 100.0 / 9.0).abs() < 1e-9);
+        assert_eq!(
+            summary.top_blocked(10),
+            vec![("ads.example.com", 6), ("tracker.example.net", 1)]
+        );
+        assert_eq!(summary.top_blocked(1), vec![("ads.example.com", 6)]);
+        assert!(!summary.sampled);
+    }
+
+    #[test]
+    fn test_analyze_log_scales_sampled_allowed_lines() {
+        let log = "\
+14:02:51 INFO  [BLOCKED] domain=ads.example.com. client=10.0.0.4 qtype=A blocked=true
+14:02:51 INFO  [ALLOWED] domain=example.org. client=10.0.0.4 qtype=A sample_rate=0.25
+14:02:52 INFO  [ALLOWED] domain=example.net. client=10.0.0.4 qtype=A sample_rate=0.25 (repeated 1 more times)
+";
+        let summary = analyze_log(log.as_bytes()).unwrap();
+
+        // Three sampled lines at 1 in 4
+        assert_eq!(summary.allowed, 12);
+        assert!(summary.sampled);
+        assert!((summary.block_rate() - 100.0 / 13.0).abs() < 1e-9);
+    }
+}
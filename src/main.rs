@@ -1,15 +1,26 @@
 use clap::Parser;
-use skypier_blackhole::{Cli, Result};
+use skypier_blackhole::{Cli, Result, EXIT_ERROR};
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Parse CLI arguments
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+async fn run() -> Result<ExitCode> {
+    // Parse CLI arguments first: -q/-v decide the log level
     let cli = Cli::parse();
 
     // The TUI owns the terminal and captures logs into its own panel,
     // so only install the console logger for regular commands.
     if !cli.is_tui() {
-        skypier_blackhole::setup_logging()?;
+        skypier_blackhole::setup_logging(cli.log_level())?;
 
         tracing::info!(
             version = env!("CARGO_PKG_VERSION"),
@@ -18,7 +29,5 @@ async fn main() -> Result<()> {
     }
 
     // Execute CLI command (each command loads its own config)
-    cli.execute().await?;
-
-    Ok(())
+    cli.execute().await
 }
@@ -1,7 +1,120 @@
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Register index bits of the client-count HyperLogLog: 2^12 one-byte
+/// registers (4 KiB), about 1.6% standard error
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Most clients with per-client counters; once reached, the less busy half
+/// is dropped to make room
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+/// Approximate distinct counter (HyperLogLog) with fixed memory, updated
+/// lock-free from concurrent query tasks
+#[derive(Debug)]
+struct HyperLogLog {
+    registers: Vec<AtomicU8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        HyperLogLog {
+            registers: (0..HLL_REGISTERS).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    fn insert(&self, value: impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // Low bits pick the register; the rest give the rank (position of
+        // the first set bit)
+        let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+        let rank = ((hash >> HLL_PRECISION).trailing_zeros() + 1).min(64 - HLL_PRECISION + 1) as u8;
+        self.registers[index].fetch_max(rank, Ordering::Relaxed);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let mut sum = 0.0;
+        let mut zeros = 0usize;
+        for register in &self.registers {
+            let rank = register.load(Ordering::Relaxed);
+            sum += 2f64.powi(-(rank as i32));
+            if rank == 0 {
+                zeros += 1;
+            }
+        }
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let raw = alpha * m * m / sum;
+
+        // Small-range correction: linear counting while registers are empty
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+/// Outcome counters of the queries forwarded to one upstream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpstreamStats {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Summed latency of the successful queries
+    total_latency: Duration,
+}
+
+impl UpstreamStats {
+    /// Queries sent to this upstream
+    pub fn sent(&self) -> u64 {
+        self.succeeded + self.failed
+    }
+
+    /// Mean latency of the successful queries, None before the first
+    pub fn avg_latency(&self) -> Option<Duration> {
+        let succeeded = u32::try_from(self.succeeded).ok().filter(|&n| n > 0)?;
+        Some(self.total_latency / succeeded)
+    }
+}
+
+impl fmt::Display for UpstreamStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} ok, {} failed",
+            self.sent(),
+            self.succeeded,
+            self.failed
+        )?;
+        if let Some(latency) = self.avg_latency() {
+            write!(f, ", avg {:.1}ms", latency.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Query counters of one client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pub queries: u64,
+    pub blocked: u64,
+}
+
+impl fmt::Display for ClientStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} queries, {} blocked", self.queries, self.blocked)
+    }
+}
 
 /// In-memory runtime metrics for the DNS daemon.
 ///
@@ -13,8 +126,20 @@ pub struct RuntimeMetrics {
     total_queries: AtomicU64,
     blocked_queries: AtomicU64,
     allowed_queries: AtomicU64,
+    /// Answers served from the stale cache because the upstream failed
+    stale_served: AtomicU64,
+    /// Block events dropped because the webhook queue was full
+    webhook_dropped: AtomicU64,
+    /// Answers currently held for `serve_stale`
+    cached_answers: AtomicU64,
     /// Per-domain hit counts for blocked queries since startup
     domain_hits: Mutex<HashMap<String, u64>>,
+    /// Approximate set of client IPs seen since startup
+    clients: HyperLogLog,
+    /// Counters of the busiest clients, at most `MAX_TRACKED_CLIENTS`
+    client_hits: Mutex<HashMap<IpAddr, ClientStats>>,
+    /// Forwarding outcomes per upstream, keyed by its display form
+    upstreams: Mutex<BTreeMap<String, UpstreamStats>>,
 }
 
 impl Default for RuntimeMetrics {
@@ -30,7 +155,35 @@ impl RuntimeMetrics {
             total_queries: AtomicU64::new(0),
             blocked_queries: AtomicU64::new(0),
             allowed_queries: AtomicU64::new(0),
+            stale_served: AtomicU64::new(0),
+            webhook_dropped: AtomicU64::new(0),
+            cached_answers: AtomicU64::new(0),
             domain_hits: Mutex::new(HashMap::new()),
+            clients: HyperLogLog::new(),
+            client_hits: Mutex::new(HashMap::new()),
+            upstreams: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Note a query from `ip` for the unique-client estimate and its
+    /// per-client counters
+    pub fn record_client(&self, ip: IpAddr) {
+        self.clients.insert(ip);
+
+        let mut hits = self.client_hits.lock().unwrap();
+        if hits.len() >= MAX_TRACKED_CLIENTS && !hits.contains_key(&ip) {
+            // Keep the busier half; a newcomer has to earn its place again
+            let mut queries: Vec<u64> = hits.values().map(|s| s.queries).collect();
+            let (_, &mut median, _) = queries.select_nth_unstable(MAX_TRACKED_CLIENTS / 2);
+            hits.retain(|_, stats| stats.queries > median);
+        }
+        hits.entry(ip).or_default().queries += 1;
+    }
+
+    /// Note that a query from `ip` was blocked
+    pub fn record_client_blocked(&self, ip: IpAddr) {
+        if let Some(stats) = self.client_hits.lock().unwrap().get_mut(&ip) {
+            stats.blocked += 1;
         }
     }
 
@@ -48,6 +201,32 @@ impl RuntimeMetrics {
         *hits.entry(normalized).or_insert(0) += 1;
     }
 
+    pub fn record_stale_served(&self) {
+        self.stale_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Note a query forwarded to `upstream`: its latency if it was
+    /// answered, None if it failed
+    pub fn record_upstream(&self, upstream: &str, latency: Option<Duration>) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let stats = upstreams.entry(upstream.to_string()).or_default();
+        match latency {
+            Some(latency) => {
+                stats.succeeded += 1;
+                stats.total_latency += latency;
+            }
+            None => stats.failed += 1,
+        }
+    }
+
+    pub fn record_webhook_dropped(&self) {
+        self.webhook_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_cached_answers(&self, count: usize) {
+        self.cached_answers.store(count as u64, Ordering::Relaxed);
+    }
+
     pub fn uptime(&self) -> std::time::Duration {
         self.start_time.elapsed()
     }
@@ -64,11 +243,54 @@ impl RuntimeMetrics {
         self.allowed_queries.load(Ordering::Relaxed)
     }
 
+    pub fn stale_served(&self) -> u64 {
+        self.stale_served.load(Ordering::Relaxed)
+    }
+
+    pub fn webhook_dropped(&self) -> u64 {
+        self.webhook_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn cached_answers(&self) -> u64 {
+        self.cached_answers.load(Ordering::Relaxed)
+    }
+
     /// Number of distinct domains blocked since startup
     pub fn distinct_blocked(&self) -> usize {
         self.domain_hits.lock().unwrap().len()
     }
 
+    /// Approximate number of distinct client IPs since startup (exact for
+    /// small counts, within a few percent for large ones)
+    pub fn unique_clients(&self) -> u64 {
+        self.clients.estimate()
+    }
+
+    /// Forwarding counters of every upstream queried since startup, sorted
+    /// by upstream
+    pub fn upstream_stats(&self) -> Vec<(String, UpstreamStats)> {
+        let upstreams = self.upstreams.lock().unwrap();
+        upstreams
+            .iter()
+            .map(|(upstream, stats)| (upstream.clone(), stats.clone()))
+            .collect()
+    }
+
+    /// Top `n` clients by query count, descending (ties by blocked count).
+    /// Counts since startup; clients dropped to bound memory start over.
+    pub fn top_clients(&self, n: usize) -> Vec<(IpAddr, ClientStats)> {
+        let hits = self.client_hits.lock().unwrap();
+        let mut entries: Vec<(IpAddr, ClientStats)> =
+            hits.iter().map(|(ip, stats)| (*ip, *stats)).collect();
+        entries.sort_by(|a, b| {
+            (b.1.queries, b.1.blocked)
+                .cmp(&(a.1.queries, a.1.blocked))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        entries.truncate(n);
+        entries
+    }
+
     /// Top `n` blocked domains by hit count, descending
     pub fn top_blocked(&self, n: usize) -> Vec<(String, u64)> {
         let hits = self.domain_hits.lock().unwrap();
@@ -100,4 +322,70 @@ mod tests {
         assert_eq!(top[0], ("ads.example.com".to_string(), 2));
         assert_eq!(top[1], ("tracker.com".to_string(), 1));
     }
+
+    #[test]
+    fn test_top_clients_ranks_by_queries_then_blocks() {
+        let m = RuntimeMetrics::new();
+        let query = |ip: [u8; 4], blocked: bool| {
+            m.record_client(IpAddr::from(ip));
+            if blocked {
+                m.record_client_blocked(IpAddr::from(ip));
+            }
+        };
+        for _ in 0..5 {
+            query([10, 0, 0, 1], false);
+        }
+        for blocked in [true, true, false] {
+            query([10, 0, 0, 2], blocked);
+            query([10, 0, 0, 3], !blocked);
+        }
+        query([10, 0, 0, 4], true);
+
+        let top = m.top_clients(3);
+        let ranking: Vec<_> = top
+            .iter()
+            .map(|(ip, s)| (ip.to_string(), s.queries, s.blocked))
+            .collect();
+        assert_eq!(
+            ranking,
+            [
+                ("10.0.0.1".to_string(), 5, 0),
+                ("10.0.0.2".to_string(), 3, 2),
+                ("10.0.0.3".to_string(), 3, 1),
+            ]
+        );
+
+        // Memory stays bounded, and the busiest clients survive a trim
+        for i in 0..(MAX_TRACKED_CLIENTS as u32 * 2) {
+            m.record_client(IpAddr::from((0x0b00_0000 + i).to_be_bytes()));
+        }
+        assert!(m.client_hits.lock().unwrap().len() <= MAX_TRACKED_CLIENTS);
+        assert_eq!(m.top_clients(1)[0].0, IpAddr::from([10, 0, 0, 1]));
+    }
+
+    #[test]
+    fn test_unique_clients() {
+        let m = RuntimeMetrics::new();
+        assert_eq!(m.unique_clients(), 0);
+
+        for i in 0..20u8 {
+            // Repeat queries from the same client count once
+            for _ in 0..3 {
+                m.record_client(IpAddr::from([192, 168, 1, i]));
+            }
+        }
+        m.record_client("2001:db8::1".parse().unwrap());
+        assert_eq!(m.unique_clients(), 21);
+
+        for i in 0..10_000u32 {
+            m.record_client(IpAddr::from((0x0a00_0000 + i).to_be_bytes()));
+        }
+        let estimate = m.unique_clients() as f64;
+        let error = (estimate - 10_021.0).abs() / 10_021.0;
+        assert!(
+            error < 0.05,
+            "estimate {estimate} off by {:.1}%",
+            error * 100.0
+        );
+    }
 }
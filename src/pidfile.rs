@@ -0,0 +1,176 @@
+use crate::Result;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// The server's PID file. Written by `start` and removed again when the
+/// guard is dropped on shutdown. Holds the PID and, where `/proc` has it,
+/// the process start time, so a PID reused by another process after the
+/// server died isn't taken for the server.
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Record this process in `path`. Fails if the file names another live
+    /// process; a stale file left by a crashed server is overwritten.
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(pid) = read(path)? {
+            if pid != std::process::id() {
+                anyhow::bail!(
+                    "Server already running (PID {pid}, from {})",
+                    path.display()
+                );
+            }
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let pid = std::process::id();
+        let line = match start_time(pid) {
+            Some(started) => format!("{pid} {started}\n"),
+            None => format!("{pid}\n"),
+        };
+        std::fs::write(path, line)
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to remove PID file");
+        }
+    }
+}
+
+/// PID of the server recorded in `path`, if that process is still alive.
+/// A stale file (process gone) is removed and reads as no server.
+pub(crate) fn read(path: &Path) -> Result<Option<u32>> {
+    Ok(read_process(path)?.map(|(pid, _)| pid))
+}
+
+/// PID and recorded start time of the server in `path`, if it is still
+/// that process (see `is_same_process`). A stale file is removed.
+pub(crate) fn read_process(path: &Path) -> Result<Option<(u32, Option<u64>)>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read PID file {}", path.display()))
+        }
+    };
+
+    let mut fields = content.split_whitespace();
+    let pid = fields.next().and_then(|pid| pid.parse::<u32>().ok());
+    let started = fields
+        .next()
+        .and_then(|started| started.parse::<u64>().ok());
+    let pid = match pid {
+        Some(pid) if is_same_process(pid, started) => return Ok(Some((pid, started))),
+        Some(pid) => pid.to_string(),
+        None => format!("unparseable {:?}", content.trim()),
+    };
+    tracing::debug!(path = %path.display(), pid = %pid, "Removing stale PID file");
+    // Best-effort: the caller may not own the file
+    let _ = std::fs::remove_file(path);
+    Ok(None)
+}
+
+/// Whether `pid` is alive and still the process that started at `started`
+/// (as recorded by `start_time`). Without a recorded start time, any live
+/// process with that PID counts.
+pub(crate) fn is_same_process(pid: u32, started: Option<u64>) -> bool {
+    is_alive(pid) && (started.is_none() || start_time(pid) == started)
+}
+
+/// When `pid` started, in clock ticks since boot (field 22 of
+/// `/proc/<pid>/stat`); None where there is no `/proc`
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in parentheses may contain spaces; fields after it
+    // start at 3 (state)
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether a process with this PID exists (signal 0 probes without sending)
+fn is_alive(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    let Ok(raw) = i32::try_from(pid) else {
+        return false;
+    };
+    // EPERM means it exists but belongs to another user
+    matches!(kill(Pid::from_raw(raw), None), Ok(()) | Err(Errno::EPERM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_written_read_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join("blackhole.pid");
+
+        let guard = PidFile::create(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(&format!("{} ", std::process::id())));
+        assert!(content.ends_with('\n'));
+        assert_eq!(read(&path).unwrap(), Some(std::process::id()));
+
+        drop(guard);
+        assert!(!path.exists());
+        assert_eq!(read(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stale_pid_file_is_ignored_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.pid");
+
+        // A child that has exited and been reaped leaves a dead PID
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        std::fs::write(&path, format!("{dead_pid}\n")).unwrap();
+
+        assert_eq!(read(&path).unwrap(), None);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+
+        // A live PID that started at another time has been reused by some
+        // other process: not the server
+        let pid = std::process::id();
+        let started = start_time(pid).unwrap();
+        std::fs::write(&path, format!("{pid} {}\n", started + 1)).unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+        assert!(!path.exists());
+        assert!(is_same_process(pid, Some(started)));
+        assert!(!is_same_process(pid, Some(started + 1)));
+    }
+
+    #[test]
+    fn test_create_refuses_live_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blackhole.pid");
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        std::fs::write(&path, format!("{}\n", child.id())).unwrap();
+
+        let err = PidFile::create(&path).err().unwrap();
+        assert!(err.to_string().contains("already running"));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
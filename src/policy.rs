@@ -0,0 +1,71 @@
+use crate::{BlocklistManager, MatchKind};
+use async_trait::async_trait;
+use hickory_proto::rr::RecordType;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Outcome of a `BlockPolicy` for one query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Resolve the query through the upstream
+    Allow,
+    /// Answer with the configured blocked response; the rule is logged and
+    /// used for `blocked_explain`
+    Block(MatchKind),
+}
+
+/// Decides whether a query is blocked. `DnsServer` consults one for every
+/// query; the default is `BlocklistPolicy`, and library users can inject
+/// their own with `DnsServer::with_policy`.
+#[async_trait]
+pub trait BlockPolicy: Send + Sync {
+    /// `name` is the query name as received (trailing dot included)
+    async fn decide(&self, name: &str, qtype: RecordType, src: IpAddr) -> Decision;
+}
+
+/// The default policy: block whatever the blocklists match, for every
+/// client and record type
+pub struct BlocklistPolicy {
+    blocklist: Arc<BlocklistManager>,
+}
+
+impl BlocklistPolicy {
+    pub fn new(blocklist: Arc<BlocklistManager>) -> Self {
+        BlocklistPolicy { blocklist }
+    }
+}
+
+#[async_trait]
+impl BlockPolicy for BlocklistPolicy {
+    async fn decide(&self, name: &str, _qtype: RecordType, _src: IpAddr) -> Decision {
+        match self.blocklist.matching_rule(name).await {
+            Some(rule) => Decision::Block(rule),
+            None => Decision::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_blocklist_policy_follows_blocklist() {
+        let blocklist = Arc::new(BlocklistManager::new());
+        blocklist
+            .add_domain("ads.example.com".to_string())
+            .await
+            .unwrap();
+        let policy = BlocklistPolicy::new(blocklist);
+        let src: IpAddr = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(
+            policy.decide("ads.example.com.", RecordType::A, src).await,
+            Decision::Block(MatchKind::Exact("ads.example.com".to_string()))
+        );
+        assert_eq!(
+            policy.decide("example.com.", RecordType::A, src).await,
+            Decision::Allow
+        );
+    }
+}
@@ -0,0 +1,28 @@
+/// Search and video sites with an enforced safe-search endpoint, and the
+/// names their front pages are answered with (`safe_search`). Each
+/// provider documents the CNAME as the way to lock filtering on for a
+/// network.
+const SAFE_SEARCH_HOSTS: &[(&str, &str)] = &[
+    ("google.com", "forcesafesearch.google.com"),
+    ("www.google.com", "forcesafesearch.google.com"),
+    ("bing.com", "strict.bing.com"),
+    ("www.bing.com", "strict.bing.com"),
+    ("duckduckgo.com", "safe.duckduckgo.com"),
+    ("www.duckduckgo.com", "safe.duckduckgo.com"),
+    ("youtube.com", "restrict.youtube.com"),
+    ("www.youtube.com", "restrict.youtube.com"),
+    ("m.youtube.com", "restrict.youtube.com"),
+    ("youtubei.googleapis.com", "restrict.youtube.com"),
+    ("youtube.googleapis.com", "restrict.youtube.com"),
+    ("www.youtube-nocookie.com", "restrict.youtube.com"),
+];
+
+/// The safe-search name `name` is rewritten to, if it is one of the known
+/// hosts (case-insensitive, trailing dot optional)
+pub(crate) fn safe_search_target(name: &str) -> Option<&'static str> {
+    let name = name.trim_end_matches('.');
+    SAFE_SEARCH_HOSTS
+        .iter()
+        .find(|(host, _)| host.eq_ignore_ascii_case(name))
+        .map(|(_, target)| *target)
+}
@@ -83,81 +83,53 @@ impl UpdateScheduler {
     async fn run_update(config: &Config, blocklist: &BlocklistManager) -> Result<usize> {
         let start = Utc::now();
 
-        // Download from remote sources
+        // Download from remote sources, one cache file per category
         let downloader = BlocklistDownloader::new()?;
-        let domains = downloader
-            .download_multiple(&config.blocklist.remote_lists)
-            .await?;
+        let mut downloaded = 0;
+        for (category, urls) in config.blocklist.enabled_remote_groups() {
+            let domains = downloader.download_multiple(&urls).await?;
+            if domains.is_empty() {
+                continue;
+            }
+            if let Some(current) =
+                crate::loader::suspicious_shrink(config, category.as_deref(), domains.len())
+            {
+                warn!(
+                    category = category.as_deref().unwrap_or("-"),
+                    downloaded = domains.len(),
+                    cached = current,
+                    "Download is much smaller than the cached list, keeping the cache"
+                );
+                continue;
+            }
+            downloaded += domains.len();
 
-        if domains.is_empty() {
-            warn!("No domains downloaded from remote sources");
-            return Ok(0);
+            // Save to cache (same directory as custom list)
+            let cache_path =
+                crate::loader::write_remote_cache(config, category.as_deref(), &domains)?;
+            info!(domains = domains.len(), cache = %cache_path.display(), "Saved domains to cache");
         }
 
-        // Get cache file path (same directory as custom list)
-        let cache_path =
-            if let Some(parent) = std::path::Path::new(&config.blocklist.custom_list).parent() {
-                parent.join("remote-blocklist-cache.txt")
-            } else {
-                std::path::PathBuf::from("remote-blocklist-cache.txt")
-            };
-
-        // Save to cache
-        std::fs::write(&cache_path, domains.join("\n"))?;
-        info!(domains = domains.len(), cache = %cache_path.display(), "Saved domains to cache");
-
-        // Reload blocklist from all sources (including new cache)
-        blocklist.clear().await?;
-
-        let mut all_domains = Vec::new();
-
-        // Load custom list
-        if std::path::Path::new(&config.blocklist.custom_list).exists() {
-            let content = std::fs::read_to_string(&config.blocklist.custom_list)?;
-            let domains: Vec<String> = content
-                .lines()
-                .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-                .map(|line| line.trim().to_string())
-                .collect();
-            info!(
-                domains = domains.len(),
-                source = "custom list",
-                "Loaded domains"
-            );
-            all_domains.extend(domains);
+        if downloaded == 0 {
+            warn!("No domains downloaded from remote sources");
+            return Ok(0);
         }
 
-        // Load local lists
-        for path in &config.blocklist.local_lists {
-            if std::path::Path::new(path).exists() {
-                let content = std::fs::read_to_string(path)?;
-                let domains: Vec<String> = content
-                    .lines()
-                    .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-                    .map(|line| line.trim().to_string())
-                    .collect();
-                info!(domains = domains.len(), source = %path, "Loaded domains");
-                all_domains.extend(domains);
+        // Reload blocklist from all sources (including new cache), swapping
+        // the new rules in at once so queries never see a partial list.
+        // `reload_check` guards it like a manual reload.
+        let fresh = BlocklistManager::new();
+        crate::loader::load_blocklist(config, &fresh).await?;
+        if let Err(e) = crate::loader::check_reload(config, &fresh).await {
+            if config.blocklist.reload_check.keep_previous {
+                return Err(e.context("Keeping the previous blocklist"));
             }
+            error!("{:#}; serving the updated lists anyway", e);
         }
-
-        // Load cached remote list
-        if cache_path.exists() {
-            let content = std::fs::read_to_string(&cache_path)?;
-            let domains: Vec<String> = content
-                .lines()
-                .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
-                .map(|line| line.trim().to_string())
-                .collect();
-            info!(
-                domains = domains.len(),
-                source = "remote cache",
-                "Loaded domains"
-            );
-            all_domains.extend(domains);
+        if config.blocklist.use_index {
+            crate::loader::save_index(config, &fresh).await?;
         }
-
-        blocklist.load_domains(all_domains).await?;
+        blocklist.replace_with(fresh).await;
 
         let duration = Utc::now().signed_duration_since(start);
         let total_count = blocklist.count().await;
@@ -189,14 +161,14 @@ impl UpdateScheduler {
             return;
         }
 
-        if self.config.blocklist.remote_lists.is_empty() {
+        if self.config.blocklist.enabled_remote_lists().is_empty() {
             info!("No remote blocklists configured, skipping startup refresh");
             return;
         }
 
         let config = Arc::clone(&self.config);
         let blocklist = Arc::clone(&self.blocklist);
-        let sources = config.blocklist.remote_lists.len();
+        let sources = config.blocklist.enabled_remote_lists().len();
 
         tokio::spawn(async move {
             info!(sources, "Refreshing remote blocklists at startup");
@@ -251,12 +223,51 @@ mod tests {
                 listen_port: 15353,
                 upstream_dns: vec!["1.1.1.1:53".parse().unwrap()],
                 blocked_response: crate::config::BlockedResponse::Refused,
+                blocked_response_by_type: Default::default(),
+                min_ttl: None,
+                max_ttl: None,
+                ecs_policy: crate::config::EcsPolicy::Strip,
+                on_question_mismatch: Default::default(),
+                max_concurrent_queries: 1024,
+                blocked_explain: false,
+                blocked_authoritative: false,
+                block_any_queries: false,
+                doh_listen: None,
+                doh_trusted_proxies: vec![],
+                allow_public_sink: false,
+                block_page_ip: None,
+                block_page_port: 80,
+                honor_rd: false,
+                safe_search: false,
+                self_test: Default::default(),
+                blocked_ip_ranges: vec![],
+                doh_canary: true,
+                compress_responses: true,
+                strip_additional: false,
+                max_udp_response_size: None,
+                serve_stale: None,
+                cache_max_entries: 10_000,
+                webhook_url: None,
+                webhook_domains: vec![],
+                pid_file: "blackhole.pid".to_string(),
+                bootstrap_dns: None,
+                control_socket: "control.sock".to_string(),
+                upstream_cookies: false,
+                randomize_case: false,
+                client_upstreams: vec![],
             },
             blocklist: crate::config::BlocklistConfig {
                 remote_lists: vec![],
                 local_lists: vec![],
+                suffix_lists: vec![],
+                hosts_files: vec![],
                 custom_list: custom_list.to_string_lossy().to_string(),
                 enable_wildcards: true,
+                max_match_labels: 127,
+                use_index: false,
+                use_remote_cache: true,
+                compress_cache: false,
+                reload_check: Default::default(),
             },
             logging: crate::config::LoggingConfig {
                 log_blocked: true,
@@ -265,17 +276,96 @@ mod tests {
                     .join("test.log")
                     .to_string_lossy()
                     .to_string(),
+                query_log_path: None,
+                log_rotation: Default::default(),
+                log_max_size_mb: 100,
+                log_max_files: 7,
                 log_level: "info".to_string(),
+                query_log_sample_rate: 1.0,
             },
             updater: crate::config::UpdaterConfig {
                 enabled: true,
                 schedule: "0 0 0 * * *".to_string(),
                 timezone: "UTC".to_string(),
                 update_on_start: false,
+                min_update_ratio: 0.5,
             },
         }
     }
 
+    #[tokio::test]
+    async fn test_shrunken_download_keeps_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/list")
+            .with_body("only.example.com\n")
+            .create_async()
+            .await;
+        let mut config = create_test_config(&temp_dir);
+        config.blocklist.remote_lists = vec![format!("{}/list", server.url()).into()];
+
+        let cache = crate::loader::remote_cache_path(&config);
+        let cached: String = (0..10).map(|i| format!("ads{}.example.com\n", i)).collect();
+        std::fs::write(&cache, &cached).unwrap();
+
+        let blocklist = BlocklistManager::new();
+        assert_eq!(
+            UpdateScheduler::run_update(&config, &blocklist)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(std::fs::read_to_string(&cache).unwrap(), cached);
+
+        // With the check off the download replaces the cache
+        config.updater.min_update_ratio = 0.0;
+        UpdateScheduler::run_update(&config, &blocklist)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&cache).unwrap(),
+            "only.example.com\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_failing_check_keeps_previous_list() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/list")
+            .with_body("ads.example.com\n")
+            .create_async()
+            .await;
+        let mut config = create_test_config(&temp_dir);
+        config.blocklist.remote_lists = vec![format!("{}/list", server.url()).into()];
+        config.blocklist.reload_check.must_block = vec!["sentinel.example.com".to_string()];
+        config.blocklist.reload_check.keep_previous = true;
+
+        let blocklist = BlocklistManager::new();
+        blocklist
+            .add_domain("sentinel.example.com".to_string())
+            .await
+            .unwrap();
+        let err = format!(
+            "{:#}",
+            UpdateScheduler::run_update(&config, &blocklist)
+                .await
+                .unwrap_err()
+        );
+        assert!(err.contains("sentinel.example.com is not blocked"), "{err}");
+        assert!(blocklist.is_blocked("sentinel.example.com").await);
+        assert!(!blocklist.is_blocked("ads.example.com").await);
+
+        // Without keep_previous the update goes live anyway
+        config.blocklist.reload_check.keep_previous = false;
+        UpdateScheduler::run_update(&config, &blocklist)
+            .await
+            .unwrap();
+        assert!(blocklist.is_blocked("ads.example.com").await);
+    }
+
     #[tokio::test]
     async fn test_scheduler_creation() {
         let temp_dir = TempDir::new().unwrap();
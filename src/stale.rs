@@ -0,0 +1,204 @@
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::RecordType;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// TTL of records in a stale answer, so clients ask again soon (RFC 8767
+/// suggests 30 seconds)
+pub(crate) const STALE_TTL: u32 = 30;
+
+/// The last good upstream answer per question and upstream route, kept only
+/// to be served when every upstream fails (`serve_stale`). Routes are kept
+/// apart so a client never gets an answer from another client's upstreams
+/// (see `Route`). Holds at most `cache_max_entries` answers, evicting the
+/// least recently used.
+pub(crate) struct StaleCache {
+    /// How long past its TTL an answer may still be served
+    max_stale: Duration,
+    entries: Mutex<LruCache<(Route, String, RecordType), Entry>>,
+}
+
+/// The upstreams an answer came from: the index of the `client_upstreams`
+/// entry, or None for `upstream_dns`
+pub(crate) type Route = Option<usize>;
+
+struct Entry {
+    response: Message,
+    stored: Instant,
+    /// Lowest TTL among the answer records
+    ttl: Duration,
+}
+
+impl Entry {
+    fn servable(&self, max_stale: Duration) -> bool {
+        self.stored.elapsed() <= self.ttl + max_stale
+    }
+}
+
+impl StaleCache {
+    pub fn new(max_stale: Duration, max_entries: usize) -> Self {
+        let capacity = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN);
+        StaleCache {
+            max_stale,
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Number of answers held
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Forget every answer, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    /// Remember a successful answer to `query` from the upstreams of
+    /// `route`. Only NOERROR answers with records are kept.
+    pub fn store(&self, route: Route, query: &Message, response: &Message) {
+        let Some(key) = key(route, query) else {
+            return;
+        };
+        if response.response_code() != ResponseCode::NoError || response.answers().is_empty() {
+            return;
+        }
+        let ttl = response
+            .answers()
+            .iter()
+            .map(|r| r.ttl())
+            .min()
+            .unwrap_or(0);
+
+        self.entries.lock().unwrap().put(
+            key,
+            Entry {
+                response: response.clone(),
+                stored: Instant::now(),
+                ttl: Duration::from_secs(ttl.into()),
+            },
+        );
+    }
+
+    /// The answer stored for `query` on `route` if it is within the stale
+    /// window, with the query's ID and every TTL lowered to `STALE_TTL`
+    pub fn lookup(&self, route: Route, query: &Message) -> Option<Message> {
+        let key = key(route, query)?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key).filter(|e| e.servable(self.max_stale))?;
+
+        let mut response = entry.response.clone();
+        response.set_id(query.id());
+        for record in response.answers_mut() {
+            record.set_ttl(record.ttl().min(STALE_TTL));
+        }
+        for record in response.name_servers_mut() {
+            record.set_ttl(record.ttl().min(STALE_TTL));
+        }
+        Some(response)
+    }
+
+    /// The answer stored for `query` on `route` while it is still within its
+    /// TTL (no stale allowance), with the query's ID and every TTL lowered
+    /// by the time it has been held
+    pub fn fresh(&self, route: Route, query: &Message) -> Option<Message> {
+        let key = key(route, query)?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key).filter(|e| e.stored.elapsed() < e.ttl)?;
+
+        let held = u32::try_from(entry.stored.elapsed().as_secs()).unwrap_or(u32::MAX);
+        let mut response = entry.response.clone();
+        response.set_id(query.id());
+        for record in response.answers_mut() {
+            record.set_ttl(record.ttl().saturating_sub(held));
+        }
+        for record in response.name_servers_mut() {
+            record.set_ttl(record.ttl().saturating_sub(held));
+        }
+        Some(response)
+    }
+}
+
+/// Cache key of a query: the route, and its first question's name
+/// (case-insensitive) and type
+fn key(route: Route, query: &Message) -> Option<(Route, String, RecordType)> {
+    let question = query.queries().first()?;
+    Some((
+        route,
+        question.name().to_utf8().to_ascii_lowercase(),
+        question.query_type(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RData, Record};
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn question(name: &str) -> Message {
+        let mut query = Message::new();
+        query.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        query
+    }
+
+    fn answer(query: &Message) -> Message {
+        let mut response = query.clone();
+        response.add_answer(Record::from_rdata(
+            query.queries()[0].name().clone(),
+            300,
+            RData::A(Ipv4Addr::new(192, 0, 2, 1).into()),
+        ));
+        response
+    }
+
+    #[test]
+    fn test_fresh_answers_stay_within_their_ttl() {
+        let cache = StaleCache::new(Duration::from_secs(3600), 10);
+        let query = question("fresh.example.");
+        cache.store(None, &query, &answer(&query));
+        let fresh = cache.fresh(None, &query).unwrap();
+        assert!(fresh.answers()[0].ttl() <= 300);
+
+        // Expired: only the stale path still serves it
+        let query = question("expired.example.");
+        let mut expired = answer(&query);
+        expired.answers_mut()[0].set_ttl(0);
+        cache.store(None, &query, &expired);
+        assert!(cache.fresh(None, &query).is_none());
+        assert!(cache.lookup(None, &query).is_some());
+    }
+
+    #[test]
+    fn test_least_recently_used_answers_are_evicted() {
+        let cache = StaleCache::new(Duration::from_secs(3600), 3);
+        let queries: Vec<Message> = ["a.example.", "b.example.", "c.example.", "d.example."]
+            .iter()
+            .map(|name| question(name))
+            .collect();
+
+        for query in &queries[..3] {
+            cache.store(None, query, &answer(query));
+        }
+        // Using a.example makes b.example the least recently used
+        assert!(cache.lookup(None, &queries[0]).is_some());
+        cache.store(None, &queries[3], &answer(&queries[3]));
+
+        assert_eq!(cache.len(), 3);
+        assert!(cache.lookup(None, &queries[1]).is_none());
+        for query in [&queries[0], &queries[2], &queries[3]] {
+            assert!(cache.lookup(None, query).is_some());
+        }
+
+        // Storing a known question again doesn't grow the cache
+        cache.store(None, &queries[0], &answer(&queries[0]));
+        assert_eq!(cache.len(), 3);
+    }
+}
@@ -22,7 +22,10 @@ use tracing_subscriber::EnvFilter;
 const LOG_CAPACITY: usize = 500;
 
 /// Run the DNS daemon with the interactive dashboard attached
-pub async fn run(config_path: &str) -> Result<()> {
+///
+/// `log_level` comes from the `-q`/`-v` flags and overrides both `RUST_LOG`
+/// and the config's `log_level`.
+pub async fn run(config_path: &str, log_level: Option<&str>) -> Result<()> {
     let config = Config::load_or_prompt_default(config_path)?;
 
     // Install log capture before anything logs: stdout belongs to the TUI,
@@ -30,14 +33,19 @@ pub async fn run(config_path: &str) -> Result<()> {
     let log_buffer: LogBuffer = Arc::new(Mutex::new(VecDeque::new()));
     // The activity panel is the TUI's only log sink and blocked queries are
     // logged at INFO, so a warn/error config level would silently hide them.
-    let level = config.logging.log_level.to_lowercase();
+    let level = log_level
+        .map(str::to_string)
+        .unwrap_or_else(|| config.logging.log_level.to_lowercase());
     let level = match level.as_str() {
         "warn" | "error" => "info",
         other => other,
     };
-    let filter = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new(level))
-        .or_else(|_| EnvFilter::try_new("info"))?;
+    let filter = match log_level {
+        Some(_) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env()
+            .or_else(|_| EnvFilter::try_new(level))
+            .or_else(|_| EnvFilter::try_new("info"))?,
+    };
     tracing_subscriber::registry()
         .with(filter)
         .with(TuiLogLayer::new(Arc::clone(&log_buffer), LOG_CAPACITY))
@@ -258,7 +266,7 @@ impl App {
 
     /// Kick off a remote blocklist update in the background
     fn trigger_update(&self) {
-        if self.config.blocklist.remote_lists.is_empty() {
+        if self.config.blocklist.enabled_remote_lists().is_empty() {
             tracing::warn!("No remote blocklist sources configured");
             return;
         }
@@ -281,12 +289,14 @@ impl App {
     /// Full reload of the blocklist from all files on disk
     async fn reload(&mut self) {
         tracing::info!("Reloading blocklists from disk");
-        if let Err(e) = self.blocklist.clear().await {
-            tracing::error!(error = %e, "Failed to clear blocklist");
-            return;
-        }
-        match loader::load_blocklist(&self.config, &self.blocklist).await {
-            Ok(sources) => self.sources = sources,
+        // Loaded off to the side so the server keeps the old rules until
+        // the new ones are complete (or for good if loading fails)
+        let fresh = BlocklistManager::new();
+        match loader::load_blocklist(&self.config, &fresh).await {
+            Ok(sources) => {
+                self.blocklist.replace_with(fresh).await;
+                self.sources = sources;
+            }
             Err(e) => tracing::error!(error = %e, "Failed to reload blocklists"),
         }
         self.refresh_cache_info();
@@ -13,7 +13,7 @@ pub(crate) fn draw(frame: &mut Frame, app: &App) {
 
     // Bottom panel grows with the number of configured sources
     let bottom_height =
-        (5 + app.sources.len() + app.config.blocklist.remote_lists.len()).min(14) as u16;
+        (5 + app.sources.len() + app.config.blocklist.enabled_remote_lists().len()).min(14) as u16;
 
     let [main, bottom] =
         Layout::vertical([Constraint::Min(10), Constraint::Length(bottom_height)]).areas(area);
@@ -103,7 +103,7 @@ fn draw_right(frame: &mut Frame, app: &App, area: Rect) {
     let upstream_count = app.config.server.upstream_dns.len().max(1) as u16;
     let [upstream_area, stats_area, top_area, config_area] = Layout::vertical([
         Constraint::Length(upstream_count + 2),
-        Constraint::Length(8),
+        Constraint::Length(11),
         Constraint::Min(4),
         Constraint::Length(8),
     ])
@@ -130,7 +130,7 @@ fn draw_upstreams(frame: &mut Frame, app: &App, area: Rect) {
             };
             let kind = match upstream {
                 Upstream::Udp(_) => "UDP".cyan().bold(),
-                Upstream::DoH { .. } => "DoH".magenta().bold(),
+                Upstream::DoH { .. } | Upstream::DoHHost { .. } => "DoH".magenta().bold(),
             };
             Line::from(vec![
                 "● ".green(),
@@ -177,6 +177,21 @@ fn draw_stats(frame: &mut Frame, app: &App, area: Rect) {
             metrics.distinct_blocked().to_string(),
             Color::Red,
         ),
+        stat_line(
+            "Unique clients",
+            metrics.unique_clients().to_string(),
+            Color::Cyan,
+        ),
+        stat_line(
+            "Cached answers",
+            metrics.cached_answers().to_string(),
+            Color::White,
+        ),
+        stat_line(
+            "Stale answers",
+            metrics.stale_served().to_string(),
+            Color::Yellow,
+        ),
     ];
 
     frame.render_widget(
@@ -224,6 +239,7 @@ fn draw_config(frame: &mut Frame, app: &App, area: Rect) {
     let response = match &server.blocked_response {
         BlockedResponse::Refused => "REFUSED".to_string(),
         BlockedResponse::NxDomain => "NXDOMAIN".to_string(),
+        BlockedResponse::NoData => "NODATA".to_string(),
         BlockedResponse::Ip(ip) => format!("IP {}", ip),
     };
     let updater = if app.config.updater.enabled {
@@ -236,11 +252,7 @@ fn draw_config(frame: &mut Frame, app: &App, area: Rect) {
     };
 
     let lines = vec![
-        stat_line(
-            "Listen",
-            format!("{}:{}", server.listen_addr, server.listen_port),
-            Color::Cyan,
-        ),
+        stat_line("Listen", server.listen_display(), Color::Cyan),
         stat_line("Blocked response", response, Color::White),
         stat_line(
             "Wildcards",
@@ -290,20 +302,21 @@ fn draw_blocklists(frame: &mut Frame, app: &App, area: Rect) {
             ),
             None => Span::styled("     missing", Style::default().fg(Color::DarkGray)),
         };
+        let label = match &source.category {
+            Some(category) => format!("{} [{}]", source.kind.label(), category),
+            None => source.kind.label().to_string(),
+        };
         lines.push(Line::from(vec![
-            Span::styled(
-                format!("  {:<13}", source.kind.label()),
-                Style::default().fg(Color::Cyan),
-            ),
+            Span::styled(format!("  {:<13}", label), Style::default().fg(Color::Cyan)),
             count,
             "  ".into(),
             source.path.display().to_string().dark_gray(),
         ]));
     }
-    for url in &app.config.blocklist.remote_lists {
+    for url in app.config.blocklist.enabled_remote_lists() {
         lines.push(Line::from(vec![
             Span::styled("  remote url   ", Style::default().fg(Color::Cyan)),
-            url.clone().blue(),
+            url.blue(),
         ]));
     }
 
@@ -0,0 +1,162 @@
+use crate::{MatchKind, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Block events waiting for delivery; past this, new ones are dropped
+/// rather than piling up behind a slow webhook
+const WEBHOOK_QUEUE: usize = 64;
+
+/// Body POSTed to `webhook_url` for a blocked query, also streamed to
+/// `watch` clients
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct BlockEvent {
+    pub domain: String,
+    pub client_ip: IpAddr,
+    /// RFC 3339, UTC
+    pub timestamp: String,
+    /// The matching rule, as shown by `test`
+    pub rule: String,
+}
+
+impl BlockEvent {
+    pub fn new(domain: &str, client_ip: IpAddr, rule: &MatchKind) -> Self {
+        BlockEvent {
+            domain: domain.trim_end_matches('.').to_string(),
+            client_ip,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            rule: rule.to_string(),
+        }
+    }
+}
+
+/// One line on the control socket's `watch` stream: timestamp, client,
+/// domain, then the rule (which may contain spaces)
+impl fmt::Display for BlockEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.timestamp, self.client_ip, self.domain, self.rule
+        )
+    }
+}
+
+impl FromStr for BlockEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> std::result::Result<Self, Self::Err> {
+        let mut fields = line.splitn(4, ' ');
+        let mut field = || {
+            fields
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Malformed block event: {line:?}"))
+        };
+        Ok(BlockEvent {
+            timestamp: field()?.to_string(),
+            client_ip: field()?.parse()?,
+            domain: field()?.to_string(),
+            rule: field()?.to_string(),
+        })
+    }
+}
+
+/// Posts block events to the configured webhook, one at a time from a
+/// single background task
+#[derive(Clone)]
+pub(crate) struct Webhook {
+    queue: mpsc::Sender<BlockEvent>,
+    domains: Vec<String>,
+}
+
+impl Webhook {
+    /// `domains` limits events to those names and their subdomains. Starts
+    /// the delivery task, so it must be called inside the runtime.
+    pub fn new(url: &str, domains: &[String]) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .user_agent(concat!("Skypier-Blackhole/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let (queue, events) = mpsc::channel(WEBHOOK_QUEUE);
+        tokio::spawn(deliver(client, url.to_string(), events));
+
+        Ok(Webhook {
+            queue,
+            domains: domains
+                .iter()
+                .map(|d| d.trim_end_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// Whether a block of `domain` should be reported
+    pub fn wants(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        self.domains.iter().any(|watched| {
+            domain == *watched
+                || domain
+                    .strip_suffix(watched.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    /// Queue `event` for posting. Best-effort: never delays the DNS answer,
+    /// and false if the queue is full and the event was dropped.
+    pub fn send(&self, event: BlockEvent) -> bool {
+        self.queue.try_send(event).is_ok()
+    }
+}
+
+/// Post queued events until every `Webhook` clone is gone; failures are
+/// logged and the event is not retried
+async fn deliver(client: Client, url: String, mut events: mpsc::Receiver<BlockEvent>) {
+    while let Some(event) = events.recv().await {
+        let result = client
+            .post(&url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            tracing::warn!(url = %url, domain = %event.domain, error = %e, "Webhook delivery failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wants_matches_domain_and_subdomains() {
+        let webhook =
+            Webhook::new("http://127.0.0.1:1/", &["Malware.example".to_string()]).unwrap();
+
+        assert!(webhook.wants("malware.example."));
+        assert!(webhook.wants("c2.malware.example."));
+        assert!(!webhook.wants("notmalware.example."));
+        assert!(!webhook.wants("ads.example.com."));
+
+        let none = Webhook::new("http://127.0.0.1:1/", &[]).unwrap();
+        assert!(!none.wants("ads.example.com."));
+    }
+
+    #[tokio::test]
+    async fn test_send_drops_events_when_queue_is_full() {
+        let webhook = Webhook::new("http://127.0.0.1:1/", &["example.com".to_string()]).unwrap();
+        let event = BlockEvent::new(
+            "ads.example.com.",
+            "127.0.0.1".parse().unwrap(),
+            &MatchKind::Exact("ads.example.com".to_string()),
+        );
+        // Nothing is delivered before this test yields, so the queue fills
+        let queued = (0..WEBHOOK_QUEUE + 10)
+            .filter(|_| webhook.send(event.clone()))
+            .count();
+        assert_eq!(queued, WEBHOOK_QUEUE);
+    }
+}